@@ -67,7 +67,11 @@ pub fn assemble_from_ir<'a>(
     }
 }
 
-pub fn ir_for_atom(atom: &Bytes, allow_keyword: bool) -> IRRepr {
+pub fn ir_for_atom(
+    atom: &Bytes,
+    allow_keyword: bool,
+    keyword_from_atom: &Record<Vec<u8>, String>,
+) -> IRRepr {
     if atom.length() == 0 {
         return IRRepr::Null;
     }
@@ -83,7 +87,7 @@ pub fn ir_for_atom(atom: &Bytes, allow_keyword: bool) -> IRRepr {
         // Determine whether the bytes identity an integer in canonical form.
     } else {
         if allow_keyword {
-            match KEYWORD_FROM_ATOM().get(atom.data()) {
+            match keyword_from_atom.get(atom.data()) {
                 Some(kw) => {
                     return IRRepr::Symbol(kw.to_string());
                 }
@@ -125,7 +129,7 @@ pub fn disassemble_to_ir_with_kw<'a>(
 
         SExp::Atom(a) => {
             let bytes = Bytes::new(Some(BytesFromType::Raw(allocator.buf(&a).to_vec())));
-            return ir_for_atom(&bytes, allow_keyword);
+            return ir_for_atom(&bytes, allow_keyword, keyword_from_atom);
         }
     }
 }
@@ -1,11 +1,11 @@
 use core::cell::RefCell;
 
-use std::borrow::Borrow;
 use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::io;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::mem::swap;
+use std::path::Path;
 use std::rc::Rc;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
@@ -18,7 +18,6 @@ use clvm_rs::allocator::{Allocator, NodePtr, SExp};
 use clvm_rs::reduction::EvalErr;
 use clvm_rs::run_program::PreEval;
 
-use num_bigint::ToBigInt;
 #[macro_use]
 use yamlette::yamlette;
 use yamlette::model::yaml::str::FORCE_QUOTES;
@@ -29,16 +28,20 @@ use crate::classic::clvm::sexp::{enlist, proper_list, sexp_as_bin};
 use crate::classic::clvm::KEYWORD_FROM_ATOM;
 use crate::classic::clvm_tools::binutils::{assemble_from_ir, disassemble, disassemble_with_kw};
 use crate::classic::clvm_tools::clvmc::detect_modern;
+use crate::classic::clvm_tools::curry::{curry as curry_program, uncurry};
 use crate::classic::clvm_tools::debug::trace_pre_eval;
-use crate::classic::clvm_tools::debug::{trace_to_table, trace_to_text};
+use crate::classic::clvm_tools::debug::{trace_to_backtrace, trace_to_table, trace_to_text};
 use crate::classic::clvm_tools::ir::reader::read_ir;
+use crate::classic::clvm_tools::profile::{Profile, ProfileRecorder};
 use crate::classic::clvm_tools::sha256tree::sha256tree;
 use crate::classic::clvm_tools::stages;
 use crate::classic::clvm_tools::stages::stage_0::{
     DefaultProgramRunner, RunProgramOption, TRunProgram,
 };
 use crate::classic::clvm_tools::stages::stage_2::operators::run_program_for_search_paths;
-use crate::classic::clvm_tools::stages::stage_2::optimize::optimize_sexp;
+use crate::classic::clvm_tools::stages::stage_2::optimize::{
+    configure_optimizer, take_optimizer_report, OPTIMIZER_PASSES,
+};
 
 use crate::classic::platform::PathJoin;
 
@@ -46,16 +49,27 @@ use crate::classic::platform::argparse::{
     Argument, ArgumentParser, ArgumentValue, ArgumentValueConv, IntConversion, NArgsSpec,
     TArgOptionAction, TArgumentParserProps,
 };
-use crate::compiler::clvm::{convert_from_clvm_rs, get_history_len, run_step, start_step, RunStep};
-use crate::compiler::compiler::{compile_file, run_optimizer, DefaultCompilerOpts};
+use crate::compiler::cldb::{CldbDebugger, CldbRun, CldbRunEnv, FunctionMap};
+use crate::compiler::cldb_repl::CldbRepl;
+use crate::compiler::cltest::{
+    junit_report, module_for_test_file, parse_tests, run_tests, tap_report, test_file_for_module,
+    TestSuite, TEST_FILE_SUFFIX,
+};
+use crate::compiler::clvm::{convert_from_clvm_rs, convert_to_clvm_rs, start_step};
+use crate::compiler::compiler::{check_file, compile_file, run_optimizer, DefaultCompilerOpts};
 use crate::compiler::comptypes::{CompileErr, CompilerOpts};
+use crate::compiler::coverage::{Coverage, CoverageHits};
+use crate::compiler::dap::{encode_message, read_message, DapServer};
 use crate::compiler::debug::build_symbol_table_mut;
+use crate::compiler::format::{format_source, DEFAULT_WIDTH};
+use crate::compiler::lsp::LspServer;
 use crate::compiler::prims;
 use crate::compiler::runtypes::RunFailure;
 use crate::compiler::sexp;
 use crate::compiler::sexp::parse_sexp;
-use crate::compiler::srcloc::Srcloc;
-use crate::util::{collapse, Number};
+use crate::compiler::sourcemap::{source_map_path, SourceMap};
+use crate::compiler::srcloc::{parse_srcloc, Srcloc};
+use crate::util::collapse;
 
 pub struct PathOrCodeConv {}
 
@@ -84,7 +98,54 @@ pub trait TConversion {
         allocator: &'a mut Allocator,
         text: &String,
     ) -> Result<Tuple<NodePtr, String>, String>;
+
+    // Locations for the nodes of the converted program, when the input has
+    // any to give.
+    fn source_map(
+        &self,
+        _allocator: &mut Allocator,
+        _file: &Option<String>,
+        _text: &String,
+        _sexp: NodePtr,
+    ) -> Option<SourceMap> {
+        None
+    }
+}
+
+// Write a tool's output to path and any source map for it next to that.
+fn write_output(
+    path: &String,
+    text: &String,
+    source_map: Option<&SourceMap>,
+) -> Result<(), String> {
+    fs::write(path, format!("{}\n", text)).map_err(|_| format!("failed to write {}", path))?;
+    match source_map {
+        Some(m) => {
+            let map_path = source_map_path(path);
+            fs::write(&map_path, m.to_json()).map_err(|_| format!("failed to write {}", map_path))
+        }
+        None => Ok(()),
+    }
+}
+
+fn read_source_map(path: &String) -> Result<SourceMap, String> {
+    fs::read_to_string(path)
+        .map_err(|_| format!("failed to read {}", path))
+        .and_then(|text| SourceMap::from_json(&text))
+}
+
+// Add coverage to what the LCOV file at path already holds, if it exists, and
+// write the total back, so that many runs can build up one report.
+fn save_coverage(path: &String, coverage: &Coverage) -> Result<Coverage, String> {
+    let mut total = match fs::read_to_string(path) {
+        Ok(text) => Coverage::from_lcov(&text).map_err(|e| format!("{}: {}", path, e))?,
+        Err(_) => Coverage::new(),
+    };
+    total.merge(coverage);
+    fs::write(path, total.to_lcov()).map_err(|_| format!("failed to write {}", path))?;
+    Ok(total)
 }
+
 pub fn call_tool<'a>(
     allocator: &'a mut Allocator,
     tool_name: String,
@@ -104,6 +165,13 @@ pub fn call_tool<'a>(
             .set_action(TArgOptionAction::StoreTrue)
             .set_help("Show only sha256 tree hash of program".to_string()),
     );
+    parser.add_argument(
+        vec!["-o".to_string(), "--output".to_string()],
+        Argument::new().set_type(Rc::new(PathJoin {})).set_help(
+            "write the result to this file, with a source map beside it when there is one"
+                .to_string(),
+        ),
+    );
     parser.add_argument(
         vec!["path_or_code".to_string()],
         Argument::new()
@@ -139,9 +207,18 @@ pub fn call_tool<'a>(
         _ => vec![],
     };
 
+    let output_path = match args.get("output") {
+        Some(ArgumentValue::ArgString(_, p)) => Some(p.to_string()),
+        _ => None,
+    };
+    if output_path.is_some() && args_path_or_code.len() != 1 {
+        print!("FAIL: --output takes exactly one program\n");
+        return;
+    }
+
     for program in args_path_or_code {
         match program {
-            ArgumentValue::ArgString(_, s) => {
+            ArgumentValue::ArgString(file, s) => {
                 if s == "-" {
                     panic!("Read stdin is not supported at this time");
                 }
@@ -153,8 +230,24 @@ pub fn call_tool<'a>(
                         let text = conv_result.rest();
                         if args.contains_key(&"script_hash".to_string()) {
                             print!("{}\n", sha256tree(allocator, sexp).hex());
-                        } else if text.len() > 0 {
-                            print!("{}\n", text);
+                        } else {
+                            match &output_path {
+                                Some(path) => {
+                                    let source_map =
+                                        conversion.source_map(allocator, &file, &s, sexp);
+                                    match write_output(path, &text, source_map.as_ref()) {
+                                        Err(e) => {
+                                            print!("FAIL: {}\n", e);
+                                        }
+                                        _ => {}
+                                    }
+                                }
+                                None => {
+                                    if text.len() > 0 {
+                                        print!("{}\n", text);
+                                    }
+                                }
+                            }
                         }
                     }
                     Err(e) => {
@@ -185,6 +278,20 @@ impl TConversion for OpcConversion {
                 return t(sexp, sexp_as_bin(allocator, sexp).hex());
             });
     }
+
+    fn source_map(
+        &self,
+        allocator: &mut Allocator,
+        file: &Option<String>,
+        text: &String,
+        sexp: NodePtr,
+    ) -> Option<SourceMap> {
+        let filename = file.clone().unwrap_or_else(|| "*command*".to_string());
+        parse_sexp(Srcloc::start(&filename), text)
+            .ok()
+            .and_then(|forms| forms.get(0).cloned())
+            .map(|code| SourceMap::new(allocator, &FunctionMap::empty(), sexp, &code))
+    }
 }
 
 pub struct OpdConversion {}
@@ -230,6 +337,174 @@ pub fn opd(args: &Vec<String>) {
     );
 }
 
+fn read_program_arg(
+    allocator: &mut Allocator,
+    hex: bool,
+    text: &String,
+) -> Result<NodePtr, String> {
+    if hex {
+        let mut stream = Stream::new(Some(Bytes::new(Some(BytesFromType::Hex(text.to_string())))));
+        sexp_from_stream(allocator, &mut stream, Box::new(SimpleCreateCLVMObject {}))
+            .map(|x| x.1)
+            .map_err(|e| e.1)
+    } else {
+        read_ir(text)
+            .and_then(|ir_sexp| assemble_from_ir(allocator, Rc::new(ir_sexp)).map_err(|e| e.1))
+    }
+}
+
+pub fn curry(args: &Vec<String>) {
+    let mut s = Stream::new(None);
+    curry_tool(&mut s, args);
+    io::stdout().write_all(s.get_value().data());
+}
+
+pub fn curry_tool(stdout: &mut Stream, args: &Vec<String>) {
+    let props = TArgumentParserProps {
+        description: "Curry arguments into a clvm program, or uncurry one.".to_string(),
+        prog: "clvm_tools curry".to_string(),
+    };
+
+    let mut parser = ArgumentParser::new(Some(props));
+    parser.add_argument(
+        vec!["-a".to_string(), "--args".to_string()],
+        Argument::new()
+            .set_action(TArgOptionAction::Append)
+            .set_help("an argument to curry into the program".to_string())
+            .set_default(ArgumentValue::ArgArray(vec![])),
+    );
+    parser.add_argument(
+        vec!["-x".to_string(), "--hex".to_string()],
+        Argument::new()
+            .set_action(TArgOptionAction::StoreTrue)
+            .set_help("Read program and arguments as hexadecimal bytecode".to_string()),
+    );
+    parser.add_argument(
+        vec!["-u".to_string(), "--uncurry".to_string()],
+        Argument::new()
+            .set_action(TArgOptionAction::StoreTrue)
+            .set_help("Split a curried program into its program and arguments".to_string()),
+    );
+    parser.add_argument(
+        vec!["-H".to_string(), "--treehash".to_string()],
+        Argument::new()
+            .set_action(TArgOptionAction::StoreTrue)
+            .set_help("Show only sha256 tree hash of the curried program".to_string()),
+    );
+    parser.add_argument(
+        vec!["-d".to_string(), "--dump".to_string()],
+        Argument::new()
+            .set_action(TArgOptionAction::StoreTrue)
+            .set_help("dump hex version of final output".to_string()),
+    );
+    parser.add_argument(
+        vec!["path_or_code".to_string()],
+        Argument::new()
+            .set_type(Rc::new(PathOrCodeConv {}))
+            .set_help("filepath to clvm script, or a literal script".to_string()),
+    );
+
+    let arg_vec = args[1..].to_vec();
+    let parsedArgs: HashMap<String, ArgumentValue>;
+
+    match parser.parse_args(&arg_vec) {
+        Err(e) => {
+            stdout.write_string(format!("FAIL: {}\n", e));
+            return;
+        }
+        Ok(pa) => {
+            parsedArgs = pa;
+        }
+    }
+
+    let flag = |name: &str| match parsedArgs.get(name) {
+        Some(ArgumentValue::ArgBool(true)) => true,
+        _ => false,
+    };
+    let hex = flag("hex");
+
+    let mut allocator = Allocator::new();
+    let empty_map = HashMap::new();
+    let output_sexp = |allocator: &mut Allocator, sexp: NodePtr, as_data: bool| {
+        if flag("dump") {
+            sexp_as_bin(allocator, sexp).hex()
+        } else if as_data {
+            disassemble_with_kw(allocator, sexp, &empty_map)
+        } else {
+            disassemble(allocator, sexp)
+        }
+    };
+
+    let program = match parsedArgs.get("path_or_code") {
+        Some(ArgumentValue::ArgString(_, path_or_code)) => {
+            match read_program_arg(&mut allocator, hex, path_or_code) {
+                Ok(p) => p,
+                Err(e) => {
+                    stdout.write_string(format!("FAIL: {}\n", e));
+                    return;
+                }
+            }
+        }
+        _ => {
+            stdout.write_string(format!("FAIL: {}\n", "non-string argument"));
+            return;
+        }
+    };
+
+    if flag("uncurry") {
+        match uncurry(&mut allocator, program) {
+            Ok(Some(uncurried)) => {
+                let f = output_sexp(&mut allocator, *uncurried.first(), false);
+                let a = output_sexp(&mut allocator, *uncurried.rest(), true);
+                stdout.write_string(format!("{}\n{}\n", f, a));
+            }
+            Ok(None) => {
+                stdout.write_string("FAIL: program is not curried\n".to_string());
+            }
+            Err(e) => {
+                stdout.write_string(format!("FAIL: {}\n", e.1));
+            }
+        }
+        return;
+    }
+
+    let mut curry_args = Vec::new();
+    match parsedArgs.get("args") {
+        Some(ArgumentValue::ArgArray(v)) => {
+            for a in v {
+                if let ArgumentValue::ArgString(_, s) = a {
+                    match read_program_arg(&mut allocator, hex, s) {
+                        Ok(arg) => curry_args.push(arg),
+                        Err(e) => {
+                            stdout.write_string(format!("FAIL: {}\n", e));
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+
+    let result = enlist(&mut allocator, &curry_args)
+        .and_then(|arg_list| curry_program(&mut allocator, program, arg_list));
+
+    match result {
+        Ok(curried) => {
+            if flag("treehash") {
+                let hash = sha256tree(&mut allocator, curried.1).hex();
+                stdout.write_string(format!("{}\n", hash));
+            } else {
+                let out = output_sexp(&mut allocator, curried.1, false);
+                stdout.write_string(format!("{}\n", out));
+            }
+        }
+        Err(e) => {
+            stdout.write_string(format!("FAIL: {}\n", e.1));
+        }
+    }
+}
+
 struct StageImport {}
 
 impl ArgumentValueConv for StageImport {
@@ -245,16 +520,23 @@ impl ArgumentValueConv for StageImport {
     }
 }
 
-pub fn run(args: &Vec<String>) {
+fn launch_tool_and_exit(args: &Vec<String>, tool_name: &String, default_stage: u32) {
     let mut s = Stream::new(None);
-    launch_tool(&mut s, args, &"run".to_string(), 2);
-    io::stdout().write_all(s.get_value().data());
+    let mut e = Stream::new(None);
+    let code = launch_tool(&mut s, &mut e, args, tool_name, default_stage);
+    let _ = io::stdout().write_all(s.get_value().data());
+    let _ = io::stderr().write_all(e.get_value().data());
+    if code != 0 {
+        std::process::exit(code);
+    }
+}
+
+pub fn run(args: &Vec<String>) {
+    launch_tool_and_exit(args, &"run".to_string(), 2);
 }
 
 pub fn brun(args: &Vec<String>) {
-    let mut s = Stream::new(None);
-    launch_tool(&mut s, args, &"brun".to_string(), 0);
-    io::stdout().write_all(s.get_value().data());
+    launch_tool_and_exit(args, &"brun".to_string(), 0);
 }
 
 pub fn hex_to_modern_sexp_inner(
@@ -267,7 +549,7 @@ pub fn hex_to_modern_sexp_inner(
     let hash_str = hash.hex();
     let srcloc = symbol_table
         .get(&hash_str)
-        .map(|f| Srcloc::start(f))
+        .map(|f| parse_srcloc(f).unwrap_or_else(|| Srcloc::start(f)))
         .unwrap_or_else(|| loc.clone());
 
     match allocator.sexp(program) {
@@ -303,49 +585,20 @@ pub fn hex_to_modern_sexp(
     })
 }
 
-#[derive(Clone, Debug)]
-struct PriorResult {
-    reference: usize,
-    value: Rc<sexp::SExp>,
-}
+pub fn hex_to_located_sexp(
+    allocator: &mut Allocator,
+    source_map: &SourceMap,
+    loc: Srcloc,
+    input_program: &String,
+) -> Result<Rc<sexp::SExp>, RunFailure> {
+    let input_serialized = Bytes::new(Some(BytesFromType::Hex(input_program.to_string())));
 
-fn format_arg_inputs(args: &Vec<PriorResult>) -> String {
-    let value_strings: Vec<String> = args
-        .iter()
-        .map(|pr| {
-            return pr.reference.to_string();
-        })
-        .collect();
-    return value_strings.join(", ");
-}
+    let mut stream = Stream::new(Some(input_serialized.clone()));
+    let sexp = sexp_from_stream(allocator, &mut stream, Box::new(SimpleCreateCLVMObject {}))
+        .map(|x| x.1)
+        .map_err(|_| RunFailure::RunErr(loc.clone(), "Bad conversion from hex".to_string()))?;
 
-fn get_arg_associations(
-    associations: &HashMap<Number, PriorResult>,
-    args: Rc<sexp::SExp>,
-) -> Vec<PriorResult> {
-    let mut arg_exp: Rc<sexp::SExp> = args;
-    let mut result: Vec<PriorResult> = Vec::new();
-    loop {
-        match arg_exp.borrow() {
-            sexp::SExp::Cons(_, arg, rest) => {
-                match arg
-                    .get_number()
-                    .ok()
-                    .as_ref()
-                    .and_then(|n| associations.get(n))
-                {
-                    Some(n) => {
-                        result.push(n.clone());
-                    }
-                    _ => {}
-                }
-                arg_exp = rest.clone();
-            }
-            _ => {
-                return result;
-            }
-        }
-    }
+    source_map.located_sexp(allocator, loc, sexp)
 }
 
 pub fn cldb(args: &Vec<String>) {
@@ -384,6 +637,32 @@ pub fn cldb(args: &Vec<String>) {
             .set_type(Rc::new(PathOrCodeConv {}))
             .set_help("path to symbol file".to_string()),
     );
+    parser.add_argument(
+        vec!["--source-map".to_string()],
+        Argument::new()
+            .set_type(Rc::new(PathJoin {}))
+            .set_help("source map written with a hex program, to locate its code".to_string()),
+    );
+    parser.add_argument(
+        vec!["--coverage".to_string()],
+        Argument::new()
+            .set_type(Rc::new(PathJoin {}))
+            .set_help("add the lines and branches this run covers to this LCOV file".to_string()),
+    );
+    parser.add_argument(
+        vec!["--coverage-summary".to_string()],
+        Argument::new()
+            .set_action(TArgOptionAction::StoreTrue)
+            .set_help(
+                "show the lines and branches covered in each file of the LCOV file".to_string(),
+            ),
+    );
+    parser.add_argument(
+        vec!["-r".to_string(), "--repl".to_string()],
+        Argument::new()
+            .set_action(TArgOptionAction::StoreTrue)
+            .set_help("debug interactively, reading commands from stdin".to_string()),
+    );
     parser.add_argument(
         vec!["path_or_code".to_string()],
         Argument::new()
@@ -411,7 +690,6 @@ pub fn cldb(args: &Vec<String>) {
         &"".to_string(),
     ));
     let mut parsed_args_result: String = "".to_string();
-    let mut outputs_to_step = HashMap::<Number, PriorResult>::new();
 
     match parser.parse_args(&arg_vec) {
         Err(e) => {
@@ -472,6 +750,17 @@ pub fn cldb(args: &Vec<String>) {
             _ => None,
         });
 
+    let source_map = match parsedArgs.get("source_map") {
+        Some(ArgumentValue::ArgString(_, path)) => match read_source_map(path) {
+            Ok(m) => Some(m),
+            Err(e) => {
+                print!("FAIL: {}\n", e);
+                return;
+            }
+        },
+        _ => None,
+    };
+
     let do_optimize = parsedArgs
         .get("optimize")
         .map(|x| match x {
@@ -498,12 +787,17 @@ pub fn cldb(args: &Vec<String>) {
     let res = match parsedArgs.get("hex") {
         Some(ArgumentValue::ArgBool(true)) => {
             hex = true;
-            hex_to_modern_sexp(
-                &mut allocator,
-                &symbol_table.unwrap_or_else(|| HashMap::new()),
-                prog_srcloc.clone(),
-                &input_program,
-            )
+            match &source_map {
+                Some(m) => {
+                    hex_to_located_sexp(&mut allocator, m, prog_srcloc.clone(), &input_program)
+                }
+                None => hex_to_modern_sexp(
+                    &mut allocator,
+                    &symbol_table.clone().unwrap_or_else(|| HashMap::new()),
+                    prog_srcloc.clone(),
+                    &input_program,
+                ),
+            }
             .map_err(|e| CompileErr(prog_srcloc, "Failed to parse hex".to_string()))
         }
         _ => {
@@ -575,173 +869,473 @@ pub fn cldb(args: &Vec<String>) {
     let prim_map = Rc::new(prim_map_);
 
     let program_lines: Vec<String> = input_program.lines().map(|x| x.to_string()).collect();
-    let extract_text = |l: &Srcloc| {
-        let use_line = if l.line < 1 { None } else { Some(l.line - 1) };
-        let use_col = use_line.and_then(|_| if l.col < 1 { None } else { Some(l.col - 1) });
-        let end_col = use_col.map(|c| l.until.map(|u| u.1 - 1).unwrap_or_else(|| c + 1));
-        use_line
-            .and_then(|use_line| {
-                use_col.and_then(|use_col| {
-                    end_col.and_then(|end_col| Some((use_line, use_col, end_col)))
-                })
-            })
-            .and_then(|coords| {
-                let use_line = coords.0;
-                let mut use_col = coords.1;
-                let mut end_col = coords.2;
+    let step = start_step(program.clone(), args.clone());
+    let cldbenv = CldbRunEnv::new(input_file.clone(), program_lines.clone());
+    let mut cldbrun = CldbRun::new(runner.clone(), prim_map.clone(), Box::new(cldbenv), step);
 
-                if use_line >= program_lines.len() {
-                    None
-                } else {
-                    let line_text = program_lines[use_line].to_string();
-                    if (use_col >= line_text.len()) {
-                        None
-                    } else if (end_col >= line_text.len()) {
-                        end_col = line_text.len();
-                        Some(line_text[use_col..end_col].to_string())
-                    } else {
-                        Some(line_text[use_col..end_col].to_string())
-                    }
-                }
-            })
+    let coverage_path = match parsedArgs.get("coverage") {
+        Some(ArgumentValue::ArgString(_, path)) => Some(path.to_string()),
+        _ => None,
     };
+    if coverage_path.is_some() {
+        cldbrun.collect_coverage();
+    }
 
-    let whether_is_apply =
-        |s: &sexp::SExp,
-         collector: &mut BTreeMap<String, String>,
-         if_true: &dyn Fn(&mut BTreeMap<String, String>),
-         if_false: &dyn Fn(&mut BTreeMap<String, String>)| {
-            match s {
-                sexp::SExp::Integer(l, i) => {
-                    if *i == 2_i32.to_bigint().unwrap() {
-                        if_true(collector);
+    match parsedArgs.get("repl") {
+        Some(ArgumentValue::ArgBool(true)) => {
+            let functions = if hex {
+                FunctionMap::empty()
+            } else {
+                FunctionMap::from_source(opts.clone(), &input_program)
+                    .unwrap_or_else(|_| FunctionMap::empty())
+            };
+            let debugger = CldbDebugger::new(cldbrun, functions);
+            let mut repl = CldbRepl::new(debugger, Some(use_filename.clone()), program_lines);
+            let stdin = io::stdin();
+            loop {
+                print!("(cldb) ");
+                let _ = io::stdout().flush();
+
+                let mut line = String::new();
+                match stdin.read_line(&mut line) {
+                    Ok(0) | Err(_) => {
                         return;
                     }
+                    _ => {}
+                }
+
+                let mut out = Stream::new(None);
+                let keep_going = repl.command(&mut allocator, &line, &mut out);
+                let _ = io::stdout().write_all(out.get_value().data());
+                if !keep_going {
+                    return;
+                }
+            }
+        }
+        _ => {}
+    }
+
+    loop {
+        if cldbrun.is_ended() {
+            print!("{}\n", yamlette_string(output));
+            match (&coverage_path, cldbrun.coverage()) {
+                (Some(path), Some(hits)) => {
+                    match save_coverage(path, &Coverage::from_hits(&program, hits)) {
+                        Ok(total) => match parsedArgs.get("coverage_summary") {
+                            Some(ArgumentValue::ArgBool(true)) => {
+                                print!("{}", total.summary());
+                            }
+                            _ => {}
+                        },
+                        Err(e) => {
+                            print!("FAIL: {}\n", e);
+                        }
+                    }
                 }
                 _ => {}
             }
+            return;
+        }
+
+        match cldbrun.step(&mut allocator) {
+            Some(result) => {
+                output.push(result);
+            }
+            _ => {}
+        }
+    }
+}
 
-            if_false(collector);
+/* Serve the debug adapter protocol on stdin and stdout until the client
+ * disconnects or closes the connection.
+ */
+pub fn cldb_dap(_args: &Vec<String>) {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut server = DapServer::new();
+
+    while !server.is_finished() {
+        let request = match read_message(&mut input) {
+            Ok(Some(r)) => r,
+            Ok(None) => {
+                return;
+            }
+            Err(e) => {
+                eprintln!("cldb-dap: {}", e);
+                return;
+            }
         };
 
-    let add_context = |s: &sexp::SExp,
-                       c: &sexp::SExp,
-                       args: Option<Rc<sexp::SExp>>,
-                       context_result: &mut BTreeMap<String, String>| {
-        whether_is_apply(
-            s,
-            context_result,
-            &|context_result| match c {
-                sexp::SExp::Cons(_, a, b) => {
-                    context_result.insert("Env".to_string(), a.to_string());
-                    context_result.insert("Env-Args".to_string(), b.to_string());
-                }
-                _ => {
-                    context_result.insert("Function-Context".to_string(), c.to_string());
+        let mut stdout = io::stdout();
+        for reply in server.handle(&request) {
+            let _ = stdout.write_all(&encode_message(&reply));
+        }
+        let _ = stdout.flush();
+    }
+}
+
+/* Serve the language server protocol on stdin and stdout until the client
+ * sends exit or closes the connection.
+ */
+pub fn chialisp_lsp(_args: &Vec<String>) {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut server = LspServer::new();
+
+    while !server.is_finished() {
+        let msg = match read_message(&mut input) {
+            Ok(Some(m)) => m,
+            Ok(None) => {
+                return;
+            }
+            Err(e) => {
+                eprintln!("chialisp-lsp: {}", e);
+                return;
+            }
+        };
+
+        let mut stdout = io::stdout();
+        for reply in server.handle(&msg) {
+            let _ = stdout.write_all(&encode_message(&reply));
+        }
+        let _ = stdout.flush();
+    }
+}
+
+pub fn chialisp_fmt(args: &Vec<String>) {
+    let mut s = Stream::new(None);
+    let code = fmt_tool(&mut s, args);
+    io::stdout().write_all(s.get_value().data());
+    if code != 0 {
+        std::process::exit(code);
+    }
+}
+
+pub fn fmt_tool(stdout: &mut Stream, args: &Vec<String>) -> i32 {
+    let props = TArgumentParserProps {
+        description: "Format chialisp source, keeping comments.".to_string(),
+        prog: "chialisp-fmt".to_string(),
+    };
+
+    let mut parser = ArgumentParser::new(Some(props));
+    parser.add_argument(
+        vec!["-c".to_string(), "--check".to_string()],
+        Argument::new()
+            .set_action(TArgOptionAction::StoreTrue)
+            .set_help("List files that aren't formatted and exit with status 1".to_string()),
+    );
+    parser.add_argument(
+        vec!["-w".to_string(), "--write".to_string()],
+        Argument::new()
+            .set_action(TArgOptionAction::StoreTrue)
+            .set_help("Rewrite files in place instead of printing them".to_string()),
+    );
+    parser.add_argument(
+        vec!["--width".to_string()],
+        Argument::new()
+            .set_type(Rc::new(IntConversion::new(Rc::new(|| "help".to_string()))))
+            .set_default(ArgumentValue::ArgInt(DEFAULT_WIDTH as i64))
+            .set_help("line width to fit forms into".to_string()),
+    );
+    parser.add_argument(
+        vec!["files".to_string()],
+        Argument::new()
+            .set_n_args(NArgsSpec::KleeneStar)
+            .set_help("files to format, or stdin if none are given".to_string()),
+    );
+
+    let arg_vec = args[1..].to_vec();
+    let parsed_args = match parser.parse_args(&arg_vec) {
+        Err(e) => {
+            stdout.write_string(format!("FAIL: {}\n", e));
+            return 2;
+        }
+        Ok(pa) => pa,
+    };
+
+    let flag = |name: &str| match parsed_args.get(name) {
+        Some(ArgumentValue::ArgBool(true)) => true,
+        _ => false,
+    };
+    let width = match parsed_args.get("width") {
+        Some(ArgumentValue::ArgInt(w)) => *w as usize,
+        _ => DEFAULT_WIDTH,
+    };
+
+    let mut files = Vec::new();
+    match parsed_args.get("files") {
+        Some(ArgumentValue::ArgArray(v)) => {
+            for f in v.iter() {
+                if let ArgumentValue::ArgString(_, s) = f {
+                    files.push(s.clone());
                 }
-            },
-            &|context_result| match &args {
-                Some(a) => {
-                    context_result.insert("Arguments".to_string(), a.to_string());
+            }
+        }
+        _ => {}
+    }
+
+    let mut inputs = Vec::new();
+    if files.is_empty() {
+        let mut text = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut text) {
+            stdout.write_string(format!("FAIL: reading stdin: {}\n", e));
+            return 2;
+        }
+        inputs.push(("*stdin*".to_string(), text));
+    } else {
+        for f in files.iter() {
+            match fs::read_to_string(f) {
+                Ok(text) => inputs.push((f.clone(), text)),
+                Err(e) => {
+                    stdout.write_string(format!("FAIL: reading {}: {}\n", f, e));
+                    return 2;
                 }
-                _ => {}
-            },
-        );
-    };
+            }
+        }
+    }
 
-    let add_function = |input_file: Option<String>,
-                        s: &sexp::SExp,
-                        context_result: &mut BTreeMap<String, String>| {
-        whether_is_apply(s, context_result, &|context_result| {}, &|context_result| {
-            match extract_text(&s.loc()) {
-                Some(name) => {
-                    if Some(s.loc().file.to_string()) == input_file.clone() {
-                        context_result.insert("Function".to_string(), name);
-                    }
+    let mut result = 0;
+    for (name, text) in inputs.iter() {
+        let formatted = match format_source(name, text, width) {
+            Ok(f) => f,
+            Err(e) => {
+                stdout.write_string(format!("FAIL: {}: {}\n", e.0.to_string(), e.1));
+                return 2;
+            }
+        };
+
+        if flag("check") {
+            if &formatted != text {
+                stdout.write_string(format!("{}\n", name));
+                result = 1;
+            }
+        } else if flag("write") && !files.is_empty() {
+            if &formatted != text {
+                if let Err(e) = fs::write(name, &formatted) {
+                    stdout.write_string(format!("FAIL: writing {}: {}\n", name, e));
+                    return 2;
                 }
-                _ => {}
             }
-        });
+        } else {
+            stdout.write_string(formatted);
+        }
+    }
+
+    result
+}
+
+pub fn cltest(args: &Vec<String>) {
+    let mut s = Stream::new(None);
+    let code = cltest_tool(&mut s, args);
+    io::stdout().write_all(s.get_value().data());
+    if code != 0 {
+        std::process::exit(code);
+    }
+}
+
+// Add the test files at or under path to found, in a stable order.
+fn find_test_files(path: &String, found: &mut Vec<String>) -> Result<(), String> {
+    if Path::new(path).is_dir() {
+        let mut entries: Vec<String> = fs::read_dir(path)
+            .map_err(|e| format!("reading {}: {}", path, e))?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path().to_string_lossy().to_string())
+            .collect();
+        entries.sort();
+        for e in entries.iter() {
+            if Path::new(e).is_dir() || e.ends_with(TEST_FILE_SUFFIX) {
+                find_test_files(e, found)?;
+            }
+        }
+        Ok(())
+    } else if path.ends_with(TEST_FILE_SUFFIX) {
+        found.push(path.clone());
+        Ok(())
+    } else {
+        // A module, whose tests sit beside it.
+        let test_file = test_file_for_module(path);
+        if Path::new(&test_file).exists() {
+            found.push(test_file);
+            Ok(())
+        } else {
+            Err(format!(
+                "no tests for {}: {} doesn't exist",
+                path, test_file
+            ))
+        }
+    }
+}
+
+pub fn cltest_tool(stdout: &mut Stream, args: &Vec<String>) -> i32 {
+    let props = TArgumentParserProps {
+        description: "Run chialisp unit tests, reporting in TAP or JUnit XML.".to_string(),
+        prog: "cltest".to_string(),
+    };
+
+    let mut parser = ArgumentParser::new(Some(props));
+    parser.add_argument(
+        vec!["-i".to_string(), "--include".to_string()],
+        Argument::new()
+            .set_type(Rc::new(PathJoin {}))
+            .set_help("add a search path for included files".to_string())
+            .set_action(TArgOptionAction::Append)
+            .set_default(ArgumentValue::ArgArray(vec![])),
+    );
+    parser.add_argument(
+        vec!["--junit".to_string()],
+        Argument::new()
+            .set_action(TArgOptionAction::StoreTrue)
+            .set_help("Report in JUnit XML instead of TAP".to_string()),
+    );
+    parser.add_argument(
+        vec!["--coverage".to_string()],
+        Argument::new()
+            .set_type(Rc::new(PathJoin {}))
+            .set_help("Add the lines and branches the tests cover to this LCOV file".to_string()),
+    );
+    parser.add_argument(
+        vec!["paths".to_string()],
+        Argument::new()
+            .set_n_args(NArgsSpec::KleeneStar)
+            .set_help(
+                "test files, modules with tests beside them, or directories to find tests in; the current directory if none are given"
+                    .to_string(),
+            ),
+    );
+
+    let arg_vec = args[1..].to_vec();
+    let parsed_args = match parser.parse_args(&arg_vec) {
+        Err(e) => {
+            stdout.write_string(format!("FAIL: {}\n", e));
+            return 2;
+        }
+        Ok(pa) => pa,
     };
 
-    let mut step = start_step(program.clone(), args.clone());
-    let mut in_expr = false;
-    let mut to_print: BTreeMap<String, String> = BTreeMap::new();
+    let mut includes = Vec::new();
+    match parsed_args.get("include") {
+        Some(ArgumentValue::ArgArray(v)) => {
+            for p in v.iter() {
+                match p {
+                    ArgumentValue::ArgString(_, s) => includes.push(s.to_string()),
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    }
 
-    loop {
-        let new_step = run_step(&mut allocator, runner.clone(), prim_map.clone(), &step);
-
-        match &new_step {
-            Ok(RunStep::OpResult(l, x, p)) => {
-                if in_expr {
-                    let history_len = get_history_len(p.clone());
-                    to_print.insert("Result-Location".to_string(), l.to_string());
-                    to_print.insert("Value".to_string(), x.to_string());
-                    to_print.insert("Row".to_string(), output.len().to_string());
-                    match x.get_number().ok() {
-                        Some(n) => {
-                            outputs_to_step.insert(
-                                n,
-                                PriorResult {
-                                    reference: output.len(),
-                                    value: x.clone(),
-                                },
-                            );
-                        }
-                        _ => {}
-                    }
-                    in_expr = false;
-                    output.push(to_print.clone());
-                    to_print = BTreeMap::new();
-                    in_expr = false;
+    let mut paths = Vec::new();
+    match parsed_args.get("paths") {
+        Some(ArgumentValue::ArgArray(v)) => {
+            for p in v.iter() {
+                match p {
+                    ArgumentValue::ArgString(_, s) => paths.push(s.to_string()),
+                    _ => {}
                 }
             }
-            Ok(RunStep::Done(l, x)) => {
-                to_print.insert("Final-Location".to_string(), l.to_string());
-                to_print.insert("Final".to_string(), x.to_string());
-                output.push(to_print.clone());
-                print!("{}\n", yamlette_string(output));
-                return;
+        }
+        _ => {}
+    }
+    if paths.is_empty() {
+        paths.push(".".to_string());
+    }
+
+    let mut test_files = Vec::new();
+    for p in paths.iter() {
+        match find_test_files(p, &mut test_files) {
+            Err(e) => {
+                stdout.write_string(format!("FAIL: {}\n", e));
+                return 2;
             }
-            Ok(RunStep::Step(sexp, c, p)) => {}
-            Ok(RunStep::Op(sexp, c, a, None, p)) => {
-                let history_len = get_history_len(p.clone());
-                to_print.insert("Operator-Location".to_string(), a.loc().to_string());
-                to_print.insert("Operator".to_string(), sexp.to_string());
-                match sexp.get_number().ok() {
-                    Some(v) => {
-                        if v == 11_u32.to_bigint().unwrap() {
-                            let arg_associations =
-                                get_arg_associations(&outputs_to_step, a.clone());
-                            let args = format_arg_inputs(&arg_associations);
-                            to_print.insert("Argument-Refs".to_string(), args);
-                        }
-                    }
-                    _ => {}
+            _ => {}
+        }
+    }
+
+    let coverage_path = match parsed_args.get("coverage") {
+        Some(ArgumentValue::ArgString(_, path)) => Some(path.to_string()),
+        _ => None,
+    };
+    let mut coverage = Coverage::new();
+
+    let mut allocator = Allocator::new();
+    let runner = Rc::new(DefaultProgramRunner::new());
+    let mut suites = Vec::new();
+    for test_file in test_files.iter() {
+        let cases = match fs::read_to_string(test_file) {
+            Ok(text) => match parse_tests(test_file, &text) {
+                Ok(cases) => cases,
+                Err(e) => {
+                    stdout.write_string(format!("FAIL: {}: {}\n", e.0.to_string(), e.1));
+                    return 2;
                 }
-                add_context(sexp.borrow(), c.borrow(), Some(a.clone()), &mut to_print);
-                add_function(input_file.clone(), sexp, &mut to_print);
-                in_expr = true;
+            },
+            Err(e) => {
+                stdout.write_string(format!("FAIL: reading {}: {}\n", test_file, e));
+                return 2;
             }
-            Ok(RunStep::Op(sexp, c, a, Some(v), p)) => {}
-            Err(RunFailure::RunExn(l, s)) => {
-                to_print.insert("Throw-Location".to_string(), l.to_string());
-                to_print.insert("Throw".to_string(), s.to_string());
-                output.push(to_print.clone());
-                print!("{}\n", yamlette_string(output));
-                return;
+        };
+
+        let module_file = module_for_test_file(test_file).unwrap_or_else(|| test_file.clone());
+        let module = match fs::read_to_string(&module_file) {
+            Ok(text) => text,
+            Err(e) => {
+                stdout.write_string(format!("FAIL: reading {}: {}\n", module_file, e));
+                return 2;
             }
-            Err(RunFailure::RunErr(l, s)) => {
-                to_print.insert("Failure-Location".to_string(), l.to_string());
-                to_print.insert("Failure".to_string(), s.to_string());
-                output.push(to_print.clone());
-                print!("{}\n", yamlette_string(output));
-                return;
+        };
+
+        // A module's includes are looked for where -i says, then beside it and
+        // in the current directory.
+        let mut search_paths = includes.clone();
+        match Path::new(&module_file).parent() {
+            Some(dir) if dir != Path::new("") => {
+                search_paths.push(dir.to_string_lossy().to_string());
             }
             _ => {}
         }
+        search_paths.push(".".to_string());
 
-        step = new_step.unwrap_or_else(|_| step);
+        let opts = Rc::new(DefaultCompilerOpts::new(&module_file)).set_search_paths(&search_paths);
+        let results = run_tests(
+            &mut allocator,
+            runner.clone(),
+            opts,
+            &module,
+            &cases,
+            if coverage_path.is_some() {
+                Some(&mut coverage)
+            } else {
+                None
+            },
+        );
+        suites.push(TestSuite {
+            name: test_file.clone(),
+            results: results,
+        });
+    }
+
+    match &coverage_path {
+        Some(path) => match save_coverage(path, &coverage) {
+            Err(e) => {
+                stdout.write_string(format!("FAIL: {}\n", e));
+                return 2;
+            }
+            _ => {}
+        },
+        None => {}
+    }
+
+    let report = match parsed_args.get("junit") {
+        Some(ArgumentValue::ArgBool(true)) => junit_report(&suites),
+        _ => tap_report(&suites),
+    };
+    stdout.write_string(report);
+
+    if suites.iter().any(|s| s.failures() > 0) {
+        1
+    } else {
+        0
     }
 }
 
@@ -828,12 +1422,25 @@ fn write_sym_output(
     }
 }
 
+fn report_optimizer(stderr: &mut Stream) {
+    match take_optimizer_report() {
+        Some(report) => {
+            stderr.write_string(report.to_string());
+        }
+        None => {}
+    }
+}
+
+/* Run or brun with args, returning the exit status.  Output goes to stdout
+ * and diagnostics, such as compiler warnings, to stderr.
+ */
 pub fn launch_tool(
     stdout: &mut Stream,
+    stderr: &mut Stream,
     args: &Vec<String>,
     tool_name: &String,
     default_stage: u32,
-) {
+) -> i32 {
     let props = TArgumentParserProps {
         description: "Execute a clvm script.".to_string(),
         prog: format!("clvm_tools {}", tool_name),
@@ -844,7 +1451,10 @@ pub fn launch_tool(
         vec!["--strict".to_string()],
         Argument::new()
             .set_action(TArgOptionAction::StoreTrue)
-            .set_help("Unknown opcodes are always fatal errors in strict mode".to_string()),
+            .set_help(
+                "Unknown opcodes are always fatal errors in strict mode, as are unbound names when compiling"
+                    .to_string(),
+            ),
     );
     parser.add_argument(
         vec!["-x".to_string(), "--hex".to_string()],
@@ -889,6 +1499,45 @@ pub fn launch_tool(
             .set_action(TArgOptionAction::StoreTrue)
             .set_help("dump hex version of final output".to_string()),
     );
+    parser.add_argument(
+        vec!["--backtrace".to_string()],
+        Argument::new()
+            .set_action(TArgOptionAction::StoreTrue)
+            .set_help(
+                "On failure, show the function calls in progress instead of the trace, named by the symbol table or source map"
+                    .to_string(),
+            ),
+    );
+    parser.add_argument(
+        vec!["--profile".to_string()],
+        Argument::new()
+            .set_action(TArgOptionAction::StoreTrue)
+            .set_help(
+                "Show the cost spent in each function and on each source line, and the call tree, named by the symbol table or source map"
+                    .to_string(),
+            ),
+    );
+    parser.add_argument(
+        vec!["--profile-stacks".to_string()],
+        Argument::new()
+            .set_type(Rc::new(PathJoin {}))
+            .set_help("Write the cost of each call stack to this file in the collapsed format flamegraph tools read".to_string()),
+    );
+    parser.add_argument(
+        vec!["--coverage".to_string()],
+        Argument::new().set_type(Rc::new(PathJoin {})).set_help(
+            "Add the lines and branches this run covers to this LCOV file, locating code by the source map"
+                .to_string(),
+        ),
+    );
+    parser.add_argument(
+        vec!["--coverage-summary".to_string()],
+        Argument::new()
+            .set_action(TArgOptionAction::StoreTrue)
+            .set_help(
+                "Show the lines and branches covered in each file of the LCOV file".to_string(),
+            ),
+    );
     parser.add_argument(
         vec!["--quiet".to_string()],
         Argument::new()
@@ -901,6 +1550,19 @@ pub fn launch_tool(
             .set_type(Rc::new(PathJoin {}))
             .set_help(".SYM file generated by compiler".to_string()),
     );
+    parser.add_argument(
+        vec!["--source-map".to_string()],
+        Argument::new().set_type(Rc::new(PathJoin {})).set_help(
+            "source map written with the program's hex, to name its functions".to_string(),
+        ),
+    );
+    parser.add_argument(
+        vec!["-o".to_string(), "--output".to_string()],
+        Argument::new().set_type(Rc::new(PathJoin {})).set_help(
+            "write the compiled program as hex to this file, with a source map beside it"
+                .to_string(),
+        ),
+    );
     parser.add_argument(
         vec!["-n".to_string(), "--no-keywords".to_string()],
         Argument::new()
@@ -941,6 +1603,28 @@ pub fn launch_tool(
             .set_action(TArgOptionAction::StoreTrue)
             .set_help("run optimizer".to_string()),
     );
+    parser.add_argument(
+        vec!["--Werror".to_string()],
+        Argument::new()
+            .set_action(TArgOptionAction::StoreTrue)
+            .set_help("Treat compiler warnings as errors".to_string()),
+    );
+    parser.add_argument(
+        vec!["--opt-passes".to_string()],
+        Argument::new().set_help(format!(
+            "comma separated optimizer passes to run, from {}.  Classic compilation needs constant_optimizer and children_optimizer",
+            OPTIMIZER_PASSES.join(",")
+        )),
+    );
+    parser.add_argument(
+        vec!["--opt-report".to_string()],
+        Argument::new()
+            .set_action(TArgOptionAction::StoreTrue)
+            .set_help(
+                "report the rewrites, size change and cost change of each optimizer pass on stderr"
+                    .to_string(),
+            ),
+    );
 
     let arg_vec = args[1..].to_vec();
     let parsedArgs: HashMap<String, ArgumentValue>;
@@ -948,13 +1632,36 @@ pub fn launch_tool(
     match parser.parse_args(&arg_vec) {
         Err(e) => {
             stdout.write_string(format!("FAIL: {}\n", e));
-            return;
+            return 0;
         }
         Ok(pa) => {
             parsedArgs = pa;
         }
     }
 
+    let opt_passes = match parsedArgs.get("opt_passes") {
+        Some(ArgumentValue::ArgString(_, passes)) => Some(
+            passes
+                .split(',')
+                .filter(|p| p.len() > 0)
+                .map(|p| p.to_string())
+                .collect(),
+        ),
+        _ => None,
+    };
+    let opt_report = match parsedArgs.get("opt_report") {
+        Some(ArgumentValue::ArgBool(true)) => true,
+        _ => false,
+    };
+    // Held until launch_tool returns, however it returns.
+    let _optimizer_configuration = match configure_optimizer(opt_passes, opt_report) {
+        Err(e) => {
+            stdout.write_string(format!("FAIL: {}\n", e));
+            return 0;
+        }
+        Ok(c) => c,
+    };
+
     let empty_map = HashMap::new();
     let keywords = match parsedArgs.get("no_keywords") {
         None => KEYWORD_FROM_ATOM(),
@@ -996,7 +1703,7 @@ pub fn launch_tool(
     let time_parse_input;
 
     let mut input_program = "()".to_string();
-    let mut input_args = "()".to_string();
+    let mut input_args;
 
     match parsedArgs.get("path_or_code") {
         Some(ArgumentValue::ArgString(file, path_or_code)) => {
@@ -1009,7 +1716,11 @@ pub fn launch_tool(
     match parsedArgs.get("hex") {
         Some(_) => {
             let assembled_serialized =
-                Bytes::new(Some(BytesFromType::Hex(input_program.to_string())));
+                Bytes::new(Some(BytesFromType::Hex(input_program.trim().to_string())));
+            input_args = match parsedArgs.get("env") {
+                Some(ArgumentValue::ArgString(_, s)) => s.trim().to_string(),
+                _ => "".to_string(),
+            };
             if input_args.len() == 0 {
                 input_args = "80".to_string();
             }
@@ -1043,12 +1754,12 @@ pub fn launch_tool(
                     }
                     Err(e) => {
                         stdout.write_string(format!("FAIL: {}\n", e));
-                        return;
+                        return 0;
                     }
                 },
                 _ => {
                     stdout.write_string(format!("FAIL: {}\n", "non-string argument"));
-                    return;
+                    return 0;
                 }
             }
 
@@ -1078,7 +1789,32 @@ pub fn launch_tool(
     let mut symbol_table: Option<HashMap<String, String>> = None;
     let mut emit_symbol_output = false;
 
-    let symbol_table_clone = parsedArgs
+    let user_program = match input_sexp.map(|i| allocator.sexp(i)) {
+        Some(SExp::Pair(p, _)) => p,
+        _ => allocator.null(),
+    };
+
+    // A source map names the functions of the program it was written for.
+    let mut source_map = None;
+    let mut source_map_symbols = None;
+    let mut source_map_locations = HashMap::new();
+    match parsedArgs.get("source_map") {
+        Some(ArgumentValue::ArgString(_, path)) => match read_source_map(path) {
+            Ok(m) => {
+                let program = user_program;
+                source_map_locations = m.locations(&allocator, program);
+                source_map_symbols = Some(m.function_symbols(&mut allocator, program));
+                source_map = Some(m);
+            }
+            Err(e) => {
+                stdout.write_string(format!("FAIL: {}\n", e));
+                return 0;
+            }
+        },
+        _ => {}
+    }
+
+    let loaded_symbol_table = parsedArgs
         .get("symbol_table")
         .and_then(|jstring| match jstring {
             ArgumentValue::ArgString(_, s) => fs::read_to_string(s).ok().and_then(|s| {
@@ -1087,13 +1823,22 @@ pub fn launch_tool(
                 decoded_symbol_table
             }),
             _ => None,
-        })
-        .map(|st| {
-            emit_symbol_output = true;
-            symbol_table = Some(st.clone());
-            st
         });
 
+    let symbol_table_clone = match (loaded_symbol_table, source_map_symbols) {
+        (Some(mut st), Some(names)) => {
+            st.extend(names);
+            Some(st)
+        }
+        (Some(st), None) => Some(st),
+        (None, names) => names,
+    }
+    .map(|st| {
+        emit_symbol_output = true;
+        symbol_table = Some(st.clone());
+        st
+    });
+
     match parsedArgs.get("verbose") {
         Some(ArgumentValue::ArgBool(true)) => {
             emit_symbol_output = true;
@@ -1115,7 +1860,13 @@ pub fn launch_tool(
             .unwrap_or_else(|| false);
         let runner = Rc::new(DefaultProgramRunner::new());
         let use_filename = input_file.unwrap_or_else(|| "*command*".to_string());
-        let opts = Rc::new(DefaultCompilerOpts::new(&use_filename)).set_optimize(do_optimize);
+        let strict = match parsedArgs.get("strict") {
+            Some(ArgumentValue::ArgBool(true)) => true,
+            _ => false,
+        };
+        let opts = Rc::new(DefaultCompilerOpts::new(&use_filename))
+            .set_optimize(do_optimize)
+            .set_strict(strict);
 
         let unopt_res = compile_file(&mut allocator, runner.clone(), opts.clone(), &input_program);
         let res = if do_optimize {
@@ -1126,18 +1877,63 @@ pub fn launch_tool(
 
         match res {
             Ok(r) => {
-                print!("{}\n", r.to_string());
+                // Compiling succeeded, so anything found now is a warning.
+                let warnings = check_file(opts.clone(), &input_program);
+                let werror = match parsedArgs.get("Werror") {
+                    Some(ArgumentValue::ArgBool(true)) => true,
+                    _ => false,
+                };
+                for w in warnings.iter() {
+                    stderr.write_string(format!("{}\n", w.to_string()));
+                }
+                if werror && warnings.len() > 0 {
+                    return 1;
+                }
+
+                stdout.write_string(format!("{}\n", r.to_string()));
+                report_optimizer(stderr);
 
                 let mut st = HashMap::new();
                 build_symbol_table_mut(&mut st, &r);
                 write_sym_output(&st, &"main.sym".to_string());
+
+                match parsedArgs.get("output") {
+                    Some(ArgumentValue::ArgString(_, path)) => {
+                        let written = convert_to_clvm_rs(&mut allocator, r.clone())
+                            .map_err(|e| e.to_string())
+                            .and_then(|program| {
+                                let functions =
+                                    FunctionMap::from_source(opts.clone(), &input_program)
+                                        .unwrap_or_else(|_| FunctionMap::empty());
+                                let source_map =
+                                    SourceMap::new(&allocator, &functions, program, &r);
+                                let hex = sexp_as_bin(&mut allocator, program).hex();
+                                write_output(path, &hex, Some(&source_map))
+                            });
+                        match written {
+                            Err(e) => {
+                                stdout.write_string(format!("FAIL: {}\n", e));
+                            }
+                            _ => {}
+                        }
+                    }
+                    _ => {}
+                }
             }
             Err(c) => {
-                print!("{}: {}\n", c.0.to_string(), c.1);
+                // Report everything the frontend can find, not just the
+                // first error.
+                let diagnostics = check_file(opts.clone(), &input_program);
+                if diagnostics.len() == 0 {
+                    stdout.write_string(format!("{}: {}\n", c.0.to_string(), c.1));
+                }
+                for d in diagnostics.iter() {
+                    stdout.write_string(format!("{}\n", d.to_string()));
+                }
             }
         }
 
-        return;
+        return 0;
     }
 
     let mut pre_eval_f: Option<PreEval> = None;
@@ -1179,7 +1975,32 @@ pub fn launch_tool(
         })
     });
 
-    if emit_symbol_output {
+    let backtrace = match parsedArgs.get("backtrace") {
+        Some(ArgumentValue::ArgBool(true)) => true,
+        _ => false,
+    };
+
+    let profile = match parsedArgs.get("profile") {
+        Some(ArgumentValue::ArgBool(true)) => true,
+        _ => false,
+    };
+    let profile_stacks = match parsedArgs.get("profile_stacks") {
+        Some(ArgumentValue::ArgString(_, path)) => Some(path.to_string()),
+        _ => None,
+    };
+    let profiling = profile || profile_stacks.is_some();
+
+    // A profile takes the place of the trace a source map would turn on, but
+    // not of one asked for.
+    let verbose = match parsedArgs.get("verbose") {
+        Some(ArgumentValue::ArgBool(true)) => true,
+        _ => false,
+    };
+    if profiling && parsedArgs.get("symbol_table").is_none() && !verbose {
+        emit_symbol_output = false;
+    }
+
+    if emit_symbol_output || backtrace {
         let pre_eval_f_closure: Box<
             dyn Fn(
                 &mut Allocator,
@@ -1206,6 +2027,22 @@ pub fn launch_tool(
         pre_eval_f = Some(pre_eval_f_closure);
     }
 
+    let coverage_path = match parsedArgs.get("coverage") {
+        Some(ArgumentValue::ArgString(_, path)) => Some(path.to_string()),
+        _ => None,
+    };
+    if coverage_path.is_some() && source_map.is_none() {
+        stdout.write_string("FAIL: --coverage needs a --source-map to locate code\n".to_string());
+        return 0;
+    }
+    let profile_recorder = if profiling || coverage_path.is_some() {
+        let recorder = ProfileRecorder::new();
+        pre_eval_f = Some(recorder.pre_eval(pre_eval_f.take()));
+        Some(recorder)
+    } else {
+        None
+    };
+
     let run_script = match parsedArgs.get("stage") {
         Some(ArgumentValue::ArgInt(0)) => stages::brun(&mut allocator),
         _ => stages::run(&mut allocator),
@@ -1368,6 +2205,7 @@ pub fn launch_tool(
             output
         });
 
+    let failed = res.is_err();
     let output = collapse(res.map_err(|ex| {
         format!(
             "FAIL: {} {}",
@@ -1382,6 +2220,7 @@ pub fn launch_tool(
     }
 
     stdout.write_string(format!("{}\n", output));
+    report_optimizer(stderr);
 
     // Third part of our scheme: now that we have results from the forward pass
     // and the pass doing the post callbacks, we can integrate them in the main
@@ -1391,7 +2230,86 @@ pub fn launch_tool(
     let log_updates = log_updates.lock().unwrap().finish();
     fix_log(&mut allocator, &mut log_content, &log_updates);
 
-    if emit_symbol_output {
+    match (&profile_recorder, &coverage_path, &source_map) {
+        (Some(recorder), Some(path), Some(m)) => {
+            let covered = m
+                .located_sexp(
+                    &mut allocator,
+                    Srcloc::start(&"*program*".to_string()),
+                    user_program,
+                )
+                .map(|code| {
+                    let hits = CoverageHits::from_evals(
+                        &allocator,
+                        user_program,
+                        &code,
+                        &recorder.eval_counts(),
+                        &recorder.nested_eval_counts(),
+                    );
+                    Coverage::from_hits(&code, &hits)
+                })
+                .map_err(|_| "failed to locate the program's code".to_string())
+                .and_then(|coverage| save_coverage(path, &coverage));
+            match covered {
+                Ok(total) => match parsedArgs.get("coverage_summary") {
+                    Some(ArgumentValue::ArgBool(true)) => {
+                        stdout.write_string(total.summary());
+                    }
+                    _ => {}
+                },
+                Err(e) => {
+                    stdout.write_string(format!("FAIL: {}\n", e));
+                }
+            }
+        }
+        _ => {}
+    }
+
+    match &profile_recorder {
+        Some(recorder) => {
+            if profiling {
+                let profiled = Profile::new(
+                    &mut allocator,
+                    run_program.clone(),
+                    recorder,
+                    user_program,
+                    &symbol_table.clone().unwrap_or_else(|| HashMap::new()),
+                    &source_map_locations,
+                );
+                if profile {
+                    stdout.write_string(format!(
+                        "\n{}\n{}\n{}",
+                        profiled.flat_report(),
+                        profiled.line_report(),
+                        profiled.tree_report()
+                    ));
+                }
+                match &profile_stacks {
+                    Some(path) => match fs::write(path, profiled.collapsed_stacks()) {
+                        Err(_) => {
+                            stdout.write_string(format!("FAIL: failed to write {}\n", path));
+                        }
+                        _ => {}
+                    },
+                    None => {}
+                }
+            }
+        }
+        None => {}
+    }
+
+    if backtrace {
+        if failed {
+            trace_to_backtrace(
+                &mut allocator,
+                stdout,
+                &log_content,
+                symbol_table,
+                &source_map_locations,
+                &disassemble,
+            );
+        }
+    } else if emit_symbol_output {
         stdout.write_string(format!("\n"));
         trace_to_text(
             &mut allocator,
@@ -1410,6 +2328,8 @@ pub fn launch_tool(
             );
         }
     }
+
+    0
 }
 
 /*
@@ -1,58 +1,113 @@
-// import {assemble} from "./binutils";
-// import {run_program} from "../stages/stage_0";
-// import {match} from "./pattern_match";
-// import {Bytes, h, None, SExp, t} from "clvm";
-
-// /*
-// # CURRY_OBJ_CODE contains compiled code from the output of the following:
-// # run -i clvm_runtime '(mod (F . args) (include curry.clvm) (curry_args F args))'
-// # the text below has been hand-optimized to replace `((c (q X) Y))` with `(a (q X) Y)`
-// # and `(q 0)` with `0`
-//  */
-// export const CURRY_OBJ_CODE = assemble(
-//   // eslint-disable-next-line max-len
-//   "(a (q #a 4 (c 2 (c 5 (c 7 0)))) (c (q (c (q . 2) (c (c (q . 1) 5) (c (a 6 (c 2 (c 11 (q 1)))) 0))) #a (i 5 (q 4 (q . 4) (c (c (q . 1) 9) (c (a 6 (c 2 (c 13 (c 11 0)))) 0))) (q . 11)) 1) 1))"
-// );
-
-// export function curry(program: SExp, args: SExp){
-//   /*
-//     ;; A "curry" binds values to a function, making them constant,
-//     ;; and returning a new function that returns fewer arguments (since the
-//     ;; arguments are now fixed).
-//     ;; Example: (defun add2 (V1 V2) (+ V1 V2))  ; add two values
-//     ;; (curry add2 15) ; this yields a function that accepts ONE argument, and adds 15 to it
-//     `program`: an SExp
-//     `args`: an SExp that is a list of constants to be bound to `program`
-//    */
-//   args = SExp.to(t(program, args));
-//   return run_program(CURRY_OBJ_CODE, args);
-// }
-
-// export const UNCURRY_PATTERN_FUNCTION = assemble("(a (q . (: . function)) (: . core))");
-// export const UNCURRY_PATTERN_CORE = assemble("(c (q . (: . parm)) (: . core))");
-
-// export function uncurry(curried_program: SExp){
-//   let r = match(UNCURRY_PATTERN_FUNCTION, curried_program);
-//   if(!r){
-//     return r;
-//   }
-
-//   const f = r["function"];
-//   let core = r["core"];
-
-//   const args: SExp[] = [];
-//   // eslint-disable-next-line no-constant-condition
-//   while(true){
-//     r = match(UNCURRY_PATTERN_CORE, core);
-//     if(!r){
-//       break;
-//     }
-//     args.push(r["parm"]);
-//     core = r["core"];
-//   }
-
-//   if((core.as_javascript() as Bytes).equal_to(h("0x01"))){
-//     return t(f, SExp.to(args));
-//   }
-//   return None;
-// }
+use std::collections::HashMap;
+
+use clvm_rs::allocator::{Allocator, NodePtr, SExp};
+use clvm_rs::reduction::{EvalErr, Response};
+
+use crate::classic::clvm::__type_compatibility__::{t, Tuple};
+use crate::classic::clvm::sexp::enlist;
+use crate::classic::clvm_tools::binutils::assemble;
+use crate::classic::clvm_tools::pattern_match::match_sexp;
+use crate::classic::clvm_tools::stages::stage_0::{DefaultProgramRunner, TRunProgram};
+
+/*
+# CURRY_OBJ_CODE contains compiled code from the output of the following:
+# run -i clvm_runtime '(mod (F . args) (include curry.clvm) (curry_args F args))'
+# the text below has been hand-optimized to replace `((c (q X) Y))` with `(a (q X) Y)`
+# and `(q 0)` with `0`
+ */
+pub fn curry_obj_code<'a>(allocator: &'a mut Allocator) -> NodePtr {
+    return assemble(
+        allocator,
+        &"(a (q #a 4 (c 2 (c 5 (c 7 0)))) (c (q (c (q . 2) (c (c (q . 1) 5) (c (a 6 (c 2 (c 11 (q 1)))) 0))) #a (i 5 (q 4 (q . 4) (c (c (q . 1) 9) (c (a 6 (c 2 (c 13 (c 11 0)))) 0))) (q . 11)) 1) 1))".to_string()
+    ).unwrap();
+}
+
+pub fn curry<'a>(allocator: &'a mut Allocator, program: NodePtr, args: NodePtr) -> Response {
+    /*
+     * A "curry" binds values to a function, making them constant,
+     * and returning a new function that returns fewer arguments (since the
+     * arguments are now fixed).
+     * Example: (defun add2 (V1 V2) (+ V1 V2))  ; add two values
+     * (curry add2 15) ; this yields a function that accepts ONE argument, and adds 15 to it
+     * `program`: an SExp
+     * `args`: an SExp that is a list of constants to be bound to `program`
+     */
+    let curry_program = curry_obj_code(allocator);
+    let runner = DefaultProgramRunner::new();
+    return allocator
+        .new_pair(program, args)
+        .and_then(|curry_args| runner.run_program(allocator, curry_program, curry_args, None));
+}
+
+pub fn uncurry_pattern_function<'a>(allocator: &'a mut Allocator) -> NodePtr {
+    return assemble(
+        allocator,
+        &"(a (q . (: . function)) (: . core))".to_string(),
+    )
+    .unwrap();
+}
+
+pub fn uncurry_pattern_core<'a>(allocator: &'a mut Allocator) -> NodePtr {
+    return assemble(allocator, &"(c (q . (: . parm)) (: . core))".to_string()).unwrap();
+}
+
+pub fn uncurry<'a>(
+    allocator: &'a mut Allocator,
+    curried_program: NodePtr,
+) -> Result<Option<Tuple<NodePtr, NodePtr>>, EvalErr> {
+    /*
+     * The inverse of curry: given a program of the shape curry produces,
+     * return the original program and the list of bound arguments, or
+     * None if the program wasn't produced by curry.
+     */
+    let uncurry_function_pattern = uncurry_pattern_function(allocator);
+    let uncurry_core_pattern = uncurry_pattern_core(allocator);
+
+    let matched = match_sexp(
+        allocator,
+        uncurry_function_pattern,
+        curried_program,
+        HashMap::new(),
+    );
+
+    let (f, mut core) = match (
+        matched
+            .as_ref()
+            .and_then(|t1| t1.get("function").map(|i| *i)),
+        matched.as_ref().and_then(|t1| t1.get("core").map(|i| *i)),
+    ) {
+        (Some(f), Some(core)) => (f, core),
+        _ => {
+            return Ok(None);
+        }
+    };
+
+    let mut args = Vec::new();
+    loop {
+        let matched = match_sexp(allocator, uncurry_core_pattern, core, HashMap::new());
+        match (
+            matched.as_ref().and_then(|t1| t1.get("parm").map(|i| *i)),
+            matched.as_ref().and_then(|t1| t1.get("core").map(|i| *i)),
+        ) {
+            (Some(parm), Some(next_core)) => {
+                args.push(parm);
+                core = next_core;
+            }
+            _ => {
+                break;
+            }
+        }
+    }
+
+    match allocator.sexp(core) {
+        SExp::Atom(b) => {
+            if allocator.buf(&b).to_vec() == vec![1] {
+                return enlist(allocator, &args).map(|arg_list| Some(t(f, arg_list)));
+            }
+            return Ok(None);
+        }
+        _ => {
+            return Ok(None);
+        }
+    }
+}
@@ -10,6 +10,7 @@ use crate::classic::clvm::sexp::{enlist, mapM, proper_list, rest};
 
 use crate::classic::clvm_tools::sha256tree::sha256tree;
 use crate::classic::clvm_tools::stages::stage_0::TRunProgram;
+use crate::compiler::srcloc::parse_srcloc;
 
 // export const PRELUDE = `<html>
 // <head>
@@ -232,6 +233,83 @@ pub fn trace_to_table(
     );
 }
 
+/* Show the calls that were still in progress when a run failed, innermost
+ * first.  Each begins with the first unfinished evaluation in a new
+ * environment, which is where an apply entered the body of a function, and
+ * is placed at the last unfinished evaluation in that environment.  The
+ * symbol table may name the code run or give its location, as a symbol file
+ * from a modern compile does.
+ */
+pub fn trace_to_backtrace(
+    allocator: &mut Allocator,
+    stdout: &mut Stream,
+    trace: &Vec<NodePtr>,
+    symbol_table: Option<HashMap<String, String>>,
+    locations: &HashMap<String, String>,
+    disassemble_f: &dyn Fn(&mut Allocator, NodePtr) -> String,
+) {
+    let mut frames: Vec<(NodePtr, NodePtr, NodePtr)> = Vec::new();
+    for item in trace {
+        let item_vec = proper_list(allocator, *item, true).unwrap();
+        if item_vec.len() > 2 {
+            continue;
+        }
+
+        let (form, env) = (item_vec[0], item_vec[1]);
+        match frames.last_mut() {
+            Some(frame) => {
+                if frame.1 == env {
+                    frame.2 = form;
+                    continue;
+                }
+            }
+            None => {}
+        }
+        frames.push((form, env, form));
+    }
+
+    let describe = |allocator: &mut Allocator, form: NodePtr| {
+        let h = sha256tree(allocator, form).hex();
+        let symbol = symbol_table.as_ref().and_then(|st| st.get(&h).cloned());
+        let (name, symbol_loc) = match symbol {
+            Some(s) => match parse_srcloc(&s) {
+                Some(_) => (None, Some(s)),
+                None => (Some(s), None),
+            },
+            None => (None, None),
+        };
+        let loc = locations.get(&h).cloned().or(symbol_loc);
+        (h, name, loc)
+    };
+
+    if frames.len() == 0 {
+        return;
+    }
+
+    stdout.write_string("backtrace:\n".to_string());
+    for (i, (first, env, last)) in frames.iter().rev().enumerate() {
+        let (h, first_name, first_loc) = describe(allocator, *first);
+        let (_, last_name, last_loc) = describe(allocator, *last);
+        let name = first_name
+            .or(last_name)
+            .unwrap_or_else(|| h[..8].to_string());
+        let at = match last_loc.or(first_loc) {
+            Some(l) => format!(" at {}", l),
+            None => "".to_string(),
+        };
+
+        let args = rest(allocator, *env).unwrap_or_else(|_| allocator.null());
+        let name_atom = allocator.new_atom(name.as_bytes()).unwrap();
+        let call = allocator.new_pair(name_atom, args).unwrap();
+        stdout.write_string(format!(
+            "  #{} {}{}\n",
+            i,
+            disassemble_f(allocator, call),
+            at
+        ));
+    }
+}
+
 pub fn trace_pre_eval(
     allocator: &mut Allocator,
     append_log: &dyn Fn(&mut Allocator, NodePtr),
@@ -6,5 +6,6 @@ pub mod curry;
 pub mod debug;
 pub mod ir;
 pub mod pattern_match;
+pub mod profile;
 pub mod sha256tree;
 pub mod stages;
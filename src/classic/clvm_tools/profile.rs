@@ -0,0 +1,510 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use clvm_rs::allocator::{Allocator, NodePtr, SExp};
+use clvm_rs::cost::Cost;
+use clvm_rs::reduction::EvalErr;
+use clvm_rs::run_program::PreEval;
+
+use crate::classic::clvm::sexp::{enlist, proper_list};
+
+use crate::classic::clvm_tools::sha256tree::sha256tree;
+use crate::classic::clvm_tools::stages::stage_0::TRunProgram;
+use crate::compiler::srcloc::parse_srcloc;
+
+const MAIN_FUNCTION: &str = "main";
+const UNKNOWN_LINE: &str = "(unknown)";
+
+enum ProfileEvent {
+    Enter(NodePtr, NodePtr),
+    Leave(Option<NodePtr>),
+}
+
+/* Records the evaluations a run makes, in order and with their nesting, so
+ * that its cost can be attributed afterward.
+ */
+#[derive(Clone)]
+pub struct ProfileRecorder {
+    events: Rc<RefCell<Vec<ProfileEvent>>>,
+}
+
+impl ProfileRecorder {
+    pub fn new() -> Self {
+        ProfileRecorder {
+            events: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    // Record evaluations, calling the chained callback, if any, as well.
+    pub fn pre_eval(&self, chained: Option<PreEval>) -> PreEval {
+        let events = self.events.clone();
+        Box::new(move |allocator, sexp, args| {
+            let chained_post = match &chained {
+                Some(f) => f(allocator, sexp, args)?,
+                None => None,
+            };
+            events.borrow_mut().push(ProfileEvent::Enter(sexp, args));
+            let leave_events = events.clone();
+            Ok(Some(Box::new(move |result: Option<NodePtr>| {
+                leave_events.borrow_mut().push(ProfileEvent::Leave(result));
+                match &chained_post {
+                    Some(post) => post(result),
+                    None => {}
+                }
+            })))
+        })
+    }
+
+    // How many times each node was evaluated.
+    pub fn eval_counts(&self) -> HashMap<NodePtr, u64> {
+        let mut counts = HashMap::new();
+        for event in self.events.borrow().iter() {
+            match event {
+                ProfileEvent::Enter(form, _) => {
+                    *counts.entry(*form).or_insert(0) += 1;
+                }
+                ProfileEvent::Leave(_) => {}
+            }
+        }
+        counts
+    }
+
+    // How many times each node was evaluated directly inside the evaluation
+    // of another, by the outer node and then the inner one.
+    pub fn nested_eval_counts(&self) -> HashMap<(NodePtr, NodePtr), u64> {
+        let mut counts = HashMap::new();
+        let mut open: Vec<NodePtr> = Vec::new();
+        for event in self.events.borrow().iter() {
+            match event {
+                ProfileEvent::Enter(form, _) => {
+                    match open.last() {
+                        Some(outer) => {
+                            *counts.entry((*outer, *form)).or_insert(0) += 1;
+                        }
+                        None => {}
+                    }
+                    open.push(*form);
+                }
+                ProfileEvent::Leave(_) => {
+                    open.pop();
+                }
+            }
+        }
+        counts
+    }
+}
+
+struct Eval {
+    form: NodePtr,
+    env: NodePtr,
+    result: Option<NodePtr>,
+    children: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CostEntry {
+    pub self_cost: Cost,
+    pub total_cost: Cost,
+    pub calls: u64,
+}
+
+/* The cost of a run broken down by the function that was running, the source
+ * line of the code being evaluated and the stack of function calls it was
+ * evaluated under.  Functions are named, and code located, by tree hash from
+ * a symbol table; a function is entered where code it names starts being
+ * evaluated in a new environment.
+ */
+pub struct Profile {
+    pub total: Cost,
+    pub functions: HashMap<String, CostEntry>,
+    pub lines: HashMap<String, Cost>,
+    pub stacks: BTreeMap<Vec<String>, CostEntry>,
+}
+
+fn line_of(loc: &Option<Rc<String>>) -> String {
+    loc.as_ref()
+        .and_then(|l| parse_srcloc(l))
+        .map(|l| format!("{}({})", l.file, l.line))
+        .unwrap_or_else(|| UNKNOWN_LINE.to_string())
+}
+
+// What running program in env costs, or nothing if it fails.
+fn cost_of(
+    allocator: &mut Allocator,
+    runner: &Rc<dyn TRunProgram>,
+    program: NodePtr,
+    env: NodePtr,
+) -> Cost {
+    runner
+        .run_program(allocator, program, env, None)
+        .map(|r| r.0)
+        .unwrap_or(0)
+}
+
+/* The costs the interpreter charges for evaluating code itself rather than
+ * for the operators it applies, measured on the smallest programs that
+ * incur them.
+ */
+struct EvalCosts {
+    quote: Cost,
+    apply: Cost,
+}
+
+impl EvalCosts {
+    fn new(allocator: &mut Allocator, runner: &Rc<dyn TRunProgram>) -> Result<Self, EvalErr> {
+        let nil = allocator.null();
+        let one = allocator.one();
+        let quote_kw = allocator.new_atom(&[1])?;
+        let apply_kw = allocator.new_atom(&[2])?;
+
+        // (q)
+        let quote_nil = allocator.new_pair(quote_kw, nil)?;
+        let quote = cost_of(allocator, runner, quote_nil, nil);
+
+        // (a (q . 1) (q))
+        let quote_one = allocator.new_pair(quote_kw, one)?;
+        let apply_args = enlist(allocator, &vec![quote_one, quote_nil])?;
+        let apply_program = allocator.new_pair(apply_kw, apply_args)?;
+        let apply_all = cost_of(allocator, runner, apply_program, nil);
+        let lookup = cost_of(allocator, runner, one, nil);
+
+        Ok(EvalCosts {
+            quote,
+            apply: apply_all.saturating_sub(2 * quote + lookup),
+        })
+    }
+
+    /* What evaluating eval cost beyond the evaluations inside it.  Lookups and
+     * quotes have none inside them and are run again as they are.  Anything
+     * else applies an operator to the results it got, so the operator is
+     * applied again to those results, quoted.
+     */
+    fn own_cost(
+        &self,
+        allocator: &mut Allocator,
+        runner: &Rc<dyn TRunProgram>,
+        evals: &[Eval],
+        eval: &Eval,
+    ) -> Result<Cost, EvalErr> {
+        let (op, operands) = match allocator.sexp(eval.form) {
+            SExp::Pair(op, operands) => (op, operands),
+            SExp::Atom(_) => {
+                return Ok(cost_of(allocator, runner, eval.form, eval.env));
+            }
+        };
+        let op_name = match allocator.sexp(op) {
+            SExp::Atom(b) => allocator.buf(&b).to_vec(),
+            SExp::Pair(_, _) => {
+                return Ok(0);
+            }
+        };
+        if op_name == [1] {
+            return Ok(cost_of(allocator, runner, eval.form, eval.env));
+        }
+
+        let operand_count = proper_list(allocator, operands, true)
+            .map(|l| l.len())
+            .unwrap_or(0);
+        if op_name == [2] {
+            // The program applied is evaluated inside, once apply is paid for.
+            return Ok(if eval.children.len() > operand_count {
+                self.apply
+            } else {
+                0
+            });
+        }
+
+        if eval.result.is_none() || eval.children.len() != operand_count {
+            return Ok(0);
+        }
+        let quote_kw = allocator.new_atom(&[1])?;
+        let mut args = Vec::new();
+        for c in eval.children.iter() {
+            let value = match evals[*c].result {
+                Some(v) => v,
+                None => {
+                    return Ok(0);
+                }
+            };
+            args.push(allocator.new_pair(quote_kw, value)?);
+        }
+        let quoted_args = enlist(allocator, &args)?;
+        let applied = allocator.new_pair(op, quoted_args)?;
+        let nil = allocator.null();
+        Ok(cost_of(allocator, runner, applied, nil)
+            .saturating_sub(self.quote * operand_count as Cost))
+    }
+}
+
+fn percent(cost: Cost, total: Cost) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        cost as f64 * 100.0 / total as f64
+    }
+}
+
+impl Profile {
+    /* Attribute the cost of the run recorded to the evaluation of program
+     * within it, or to the whole run if program was never evaluated.  Each
+     * evaluation's own cost is found from the results recorded for it and
+     * the evaluations inside it, so evaluations that failed cost what they
+     * spent before failing.
+     */
+    pub fn new(
+        allocator: &mut Allocator,
+        runner: Rc<dyn TRunProgram>,
+        recorder: &ProfileRecorder,
+        program: NodePtr,
+        symbols: &HashMap<String, String>,
+        locations: &HashMap<String, String>,
+    ) -> Self {
+        let mut evals: Vec<Eval> = Vec::new();
+        let mut open = Vec::new();
+        for event in recorder.events.borrow().iter() {
+            match event {
+                ProfileEvent::Enter(form, env) => {
+                    let index = evals.len();
+                    match open.last() {
+                        Some(parent) => {
+                            let parent: usize = *parent;
+                            evals[parent].children.push(index);
+                        }
+                        None => {}
+                    }
+                    evals.push(Eval {
+                        form: *form,
+                        env: *env,
+                        result: None,
+                        children: Vec::new(),
+                    });
+                    open.push(index);
+                }
+                ProfileEvent::Leave(result) => match open.pop() {
+                    Some(index) => {
+                        evals[index].result = *result;
+                    }
+                    None => {}
+                },
+            }
+        }
+
+        let mut profile = Profile {
+            total: 0,
+            functions: HashMap::new(),
+            lines: HashMap::new(),
+            stacks: BTreeMap::new(),
+        };
+        if evals.len() == 0 {
+            return profile;
+        }
+
+        let root = evals.iter().position(|e| e.form == program).unwrap_or(0);
+
+        // Evaluations come after the ones they are inside, so each one's
+        // total is known by the time the one it is inside needs it.
+        let mut inclusive = vec![0; evals.len()];
+        let mut own = vec![0; evals.len()];
+        match EvalCosts::new(allocator, &runner) {
+            Ok(eval_costs) => {
+                for index in (0..evals.len()).rev() {
+                    own[index] = eval_costs
+                        .own_cost(allocator, &runner, &evals, &evals[index])
+                        .unwrap_or(0);
+                    inclusive[index] = own[index]
+                        + evals[index]
+                            .children
+                            .iter()
+                            .map(|c| inclusive[*c])
+                            .sum::<Cost>();
+                }
+            }
+            Err(_) => {
+                return profile;
+            }
+        }
+
+        let mut hashes: HashMap<NodePtr, String> = HashMap::new();
+        let mut hash_of = |allocator: &mut Allocator, form: NodePtr| match allocator.sexp(form) {
+            SExp::Pair(_, _) => Some(
+                hashes
+                    .entry(form)
+                    .or_insert_with(|| sha256tree(allocator, form).hex())
+                    .clone(),
+            ),
+            _ => None,
+        };
+
+        let mut names = HashMap::new();
+        let mut code_locations = HashMap::new();
+        for (h, s) in symbols.iter() {
+            match parse_srcloc(s) {
+                Some(_) => {
+                    code_locations.insert(h.clone(), Rc::new(s.clone()));
+                }
+                None => {
+                    names.insert(h.clone(), s.clone());
+                }
+            }
+        }
+        for (h, l) in locations.iter() {
+            code_locations.insert(h.clone(), Rc::new(l.clone()));
+        }
+
+        profile.total = inclusive[root];
+        let main_stack = vec![MAIN_FUNCTION.to_string()];
+        {
+            let main = profile
+                .functions
+                .entry(MAIN_FUNCTION.to_string())
+                .or_insert_with(Default::default);
+            main.calls = 1;
+            main.total_cost = profile.total;
+        }
+        {
+            let main = profile
+                .stacks
+                .entry(main_stack.clone())
+                .or_insert_with(Default::default);
+            main.calls = 1;
+            main.total_cost = profile.total;
+        }
+
+        // Walk the evaluations with the stack of calls each runs under and the
+        // environment of the innermost one.
+        let mut work = vec![(root, Rc::new(main_stack), evals[root].env, None)];
+        while let Some((index, stack, frame_env, loc)) = work.pop() {
+            let eval = &evals[index];
+            let this_cost = inclusive[index];
+            let self_cost = own[index];
+
+            let hash = hash_of(allocator, eval.form);
+            let name = hash.as_ref().and_then(|h| names.get(h)).cloned();
+            let loc = hash
+                .as_ref()
+                .and_then(|h| code_locations.get(h))
+                .cloned()
+                .or(loc);
+
+            let (stack, frame_env) = match name {
+                Some(n) => {
+                    let current = &stack[stack.len() - 1];
+                    if frame_env != eval.env || *current != n {
+                        let recursive = stack.contains(&n);
+                        let mut new_stack = (*stack).clone();
+                        new_stack.push(n.clone());
+
+                        let function = profile.functions.entry(n).or_insert_with(Default::default);
+                        function.calls += 1;
+                        if !recursive {
+                            function.total_cost += this_cost;
+                        }
+
+                        let frame = profile
+                            .stacks
+                            .entry(new_stack.clone())
+                            .or_insert_with(Default::default);
+                        frame.calls += 1;
+                        frame.total_cost += this_cost;
+
+                        (Rc::new(new_stack), eval.env)
+                    } else {
+                        (stack, frame_env)
+                    }
+                }
+                None => (stack, frame_env),
+            };
+
+            profile
+                .functions
+                .entry(stack[stack.len() - 1].clone())
+                .or_insert_with(Default::default)
+                .self_cost += self_cost;
+            profile
+                .stacks
+                .entry((*stack).clone())
+                .or_insert_with(Default::default)
+                .self_cost += self_cost;
+            *profile.lines.entry(line_of(&loc)).or_insert(0) += self_cost;
+
+            for c in eval.children.iter().rev() {
+                work.push((*c, stack.clone(), frame_env, loc.clone()));
+            }
+        }
+
+        profile
+    }
+
+    // Functions by the cost spent in their own code, most expensive first.
+    pub fn flat_report(&self) -> String {
+        let mut functions: Vec<(&String, &CostEntry)> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.self_cost.cmp(&a.1.self_cost).then(a.0.cmp(b.0)));
+
+        let mut out = format!("flat profile, total cost {}\n", self.total);
+        out.push_str(&format!(
+            "{:>12} {:>7} {:>12} {:>7}  {}\n",
+            "self", "self%", "total", "calls", "function"
+        ));
+        for (name, cost) in functions.iter() {
+            out.push_str(&format!(
+                "{:>12} {:>6.1}% {:>12} {:>7}  {}\n",
+                cost.self_cost,
+                percent(cost.self_cost, self.total),
+                cost.total_cost,
+                cost.calls,
+                name
+            ));
+        }
+        out
+    }
+
+    // Source lines by the cost of the code on them, most expensive first.
+    pub fn line_report(&self) -> String {
+        let mut lines: Vec<(&String, &Cost)> = self.lines.iter().filter(|(_, c)| **c > 0).collect();
+        lines.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+        let mut out = "cost by line\n".to_string();
+        out.push_str(&format!("{:>12} {:>7}  {}\n", "self", "self%", "line"));
+        for (line, cost) in lines.iter() {
+            out.push_str(&format!(
+                "{:>12} {:>6.1}%  {}\n",
+                cost,
+                percent(**cost, self.total),
+                line
+            ));
+        }
+        out
+    }
+
+    // Each distinct stack of calls, indented under its caller.
+    pub fn tree_report(&self) -> String {
+        let mut out = "call tree\n".to_string();
+        out.push_str(&format!(
+            "{:>12} {:>12} {:>7}  {}\n",
+            "total", "self", "calls", "function"
+        ));
+        for (stack, cost) in self.stacks.iter() {
+            out.push_str(&format!(
+                "{:>12} {:>12} {:>7}  {}{}\n",
+                cost.total_cost,
+                cost.self_cost,
+                cost.calls,
+                "  ".repeat(stack.len() - 1),
+                stack[stack.len() - 1]
+            ));
+        }
+        out
+    }
+
+    // The collapsed stack format read by flamegraph tools.
+    pub fn collapsed_stacks(&self) -> String {
+        let mut out = String::new();
+        for (stack, cost) in self.stacks.iter() {
+            if cost.self_cost > 0 {
+                out.push_str(&format!("{} {}\n", stack.join(";"), cost.self_cost));
+            }
+        }
+        out
+    }
+}
@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::PathBuf;
 use std::rc::Rc;
@@ -12,7 +13,7 @@ use clvm_rs::reduction::{EvalErr, Reduction, Response};
 
 use crate::classic::clvm::__type_compatibility__::{bi_one, bi_zero};
 use crate::classic::clvm::sexp::{
-    atom, enlist, equal_to, first, foldM, mapM, non_nil, proper_list,
+    atom, enlist, equal_to, first, foldM, mapM, non_nil, proper_list, sexp_as_bin,
 };
 use crate::classic::clvm_tools::binutils::{assemble_from_ir, disassemble};
 use crate::classic::clvm_tools::ir::reader::read_ir;
@@ -591,6 +592,271 @@ fn apply_null_optimizer<'a>(
     return Ok(t1.map(|_| allocator.null()).unwrap_or_else(|| r));
 }
 
+/// The optimizer passes, in the order they're tried.
+pub const OPTIMIZER_PASSES: [&str; 8] = [
+    "cons_optimizer",
+    "constant_optimizer",
+    "cons_q_a_optimizer",
+    "var_change_optimizer_cons_eval",
+    "children_optimizer",
+    "path_optimizer",
+    "quote_null_optimizer",
+    "apply_null_optimizer",
+];
+
+/// What one optimizer pass did: how many rewrites it made and how much they
+/// changed the serialized size in bytes and the estimated cost of
+/// evaluating the rewritten expressions.  Changes made by passes run inside
+/// another pass, such as children_optimizer, are counted for those passes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PassReport {
+    pub name: String,
+    pub rewrites: usize,
+    pub size_change: i64,
+    pub cost_change: i64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OptimizerReport {
+    pub passes: Vec<PassReport>,
+}
+
+impl OptimizerReport {
+    pub fn new() -> Self {
+        OptimizerReport {
+            passes: OPTIMIZER_PASSES
+                .iter()
+                .map(|name| PassReport {
+                    name: name.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PassReport> {
+        self.passes.iter().find(|p| p.name == name)
+    }
+
+    pub fn total(&self) -> PassReport {
+        let mut total = PassReport {
+            name: "total".to_string(),
+            ..Default::default()
+        };
+        for p in self.passes.iter() {
+            total.rewrites += p.rewrites;
+            total.size_change += p.size_change;
+            total.cost_change += p.cost_change;
+        }
+        total
+    }
+
+    pub fn to_string(&self) -> String {
+        let mut out = format!(
+            "{:<32} {:>8} {:>8} {:>10}\n",
+            "pass", "rewrites", "size", "cost"
+        );
+        for p in self.passes.iter().chain(vec![self.total()].iter()) {
+            out += &format!(
+                "{:<32} {:>8} {:>8} {:>10}\n",
+                p.name, p.rewrites, p.size_change, p.cost_change
+            );
+        }
+        out
+    }
+}
+
+// Which passes run and what they did.  The passes call back into
+// optimize_sexp and run inside the opt operator during compilation, so this
+// is kept per thread rather than passed along.
+struct OptimizerState {
+    enabled: Option<HashSet<String>>,
+    report: Option<OptimizerReport>,
+    // The size and cost changes already credited to some pass.
+    accounted: (i64, i64),
+}
+
+thread_local! {
+    static OPTIMIZER_STATE: RefCell<OptimizerState> = RefCell::new(OptimizerState {
+        enabled: None,
+        report: None,
+        accounted: (0, 0),
+    });
+}
+
+/// Puts the optimizer back to running every pass without recording when
+/// dropped, so a configuration can't outlive the run it was made for.
+pub struct OptimizerConfiguration {
+    _private: (),
+}
+
+impl Drop for OptimizerConfiguration {
+    fn drop(&mut self) {
+        OPTIMIZER_STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.enabled = None;
+            state.report = None;
+            state.accounted = (0, 0);
+        });
+    }
+}
+
+/// Choose the passes optimize_sexp uses on this thread, or all of them with
+/// None, and whether to record what they do, for as long as the returned
+/// configuration is held.  Fails with the name of a pass that doesn't exist.
+pub fn configure_optimizer(
+    enabled: Option<HashSet<String>>,
+    report: bool,
+) -> Result<OptimizerConfiguration, String> {
+    for name in enabled.iter().flatten() {
+        if !OPTIMIZER_PASSES.contains(&name.as_str()) {
+            return Err(format!("unknown optimizer pass {}", name));
+        }
+    }
+
+    OPTIMIZER_STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.enabled = enabled;
+        state.report = if report {
+            Some(OptimizerReport::new())
+        } else {
+            None
+        };
+        state.accounted = (0, 0);
+    });
+    Ok(OptimizerConfiguration { _private: () })
+}
+
+/// The report recorded since configure_optimizer, if one was asked for.
+pub fn take_optimizer_report() -> Option<OptimizerReport> {
+    OPTIMIZER_STATE.with(|state| state.borrow_mut().report.take())
+}
+
+fn pass_enabled(name: &str) -> bool {
+    OPTIMIZER_STATE.with(|state| {
+        state
+            .borrow()
+            .enabled
+            .as_ref()
+            .map(|e| e.contains(name))
+            .unwrap_or_else(|| true)
+    })
+}
+
+fn reporting() -> Option<(i64, i64)> {
+    OPTIMIZER_STATE.with(|state| {
+        let state = state.borrow();
+        state.report.as_ref().map(|_| state.accounted)
+    })
+}
+
+// Credit a rewrite to a pass, less whatever passes it ran itself were
+// already credited with since accounted_before.
+fn record_rewrite(name: &str, accounted_before: (i64, i64), size_change: i64, cost_change: i64) {
+    OPTIMIZER_STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let own_size = size_change - (state.accounted.0 - accounted_before.0);
+        let own_cost = cost_change - (state.accounted.1 - accounted_before.1);
+        state.accounted = (state.accounted.0 + own_size, state.accounted.1 + own_cost);
+        for pass in state.report.iter_mut().flat_map(|r| r.passes.iter_mut()) {
+            if pass.name == name {
+                pass.rewrites += 1;
+                pass.size_change += own_size;
+                pass.cost_change += own_cost;
+            }
+        }
+    });
+}
+
+// Base and per argument costs charged by clvm for each operator, by opcode.
+fn operator_cost(op: u8) -> (Cost, Cost) {
+    match op {
+        2 => (90, 0),
+        3 => (33, 0),
+        4 => (50, 0),
+        5 | 6 => (30, 0),
+        7 => (19, 0),
+        9 | 10 => (117, 0),
+        11 => (87, 134),
+        12 => (1, 0),
+        13 => (173, 0),
+        14 => (142, 135),
+        16 | 17 => (99, 320),
+        18 => (92, 885),
+        19 => (988, 0),
+        20 => (1116, 0),
+        21 => (498, 0),
+        22 => (596, 0),
+        23 => (277, 0),
+        24 | 25 | 26 => (100, 264),
+        27 => (331, 0),
+        29 => (101094, 1343980),
+        30 => (1325730, 0),
+        32 | 33 | 34 => (200, 300),
+        _ => (0, 0),
+    }
+}
+
+/// An estimate of the cost of evaluating an expression once, from the base
+/// costs of the operators it uses.  Byte length charges aren't counted, and
+/// an applied program is counted when it's quoted in place.
+pub fn estimate_cost(allocator: &mut Allocator, r: NodePtr) -> Cost {
+    match allocator.sexp(r) {
+        SExp::Atom(b) => {
+            // An environment lookup.
+            let bits = allocator.buf(&b).len() as Cost * 8;
+            40 + 4 * bits
+        }
+        SExp::Pair(op, args) => {
+            let opcode = match allocator.sexp(op) {
+                SExp::Atom(b) => {
+                    let buf = allocator.buf(&b);
+                    if buf.len() == 1 {
+                        Some(buf[0])
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            };
+
+            if opcode == Some(1) {
+                return 20;
+            }
+
+            let arg_list = proper_list(allocator, args, true).unwrap_or_else(|| vec![]);
+            let (base, per_arg) = opcode.map(operator_cost).unwrap_or_else(|| (0, 0));
+            let mut cost = base + per_arg * arg_list.len() as Cost;
+            for a in arg_list.iter() {
+                cost += estimate_cost(allocator, *a);
+            }
+
+            if opcode == Some(2) && arg_list.len() > 0 {
+                match allocator.sexp(arg_list[0]) {
+                    SExp::Pair(q, code) => {
+                        if is_quote_op(allocator, q) {
+                            cost += estimate_cost(allocator, code);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            cost
+        }
+    }
+}
+
+fn is_quote_op(allocator: &mut Allocator, op: NodePtr) -> bool {
+    match allocator.sexp(op) {
+        SExp::Atom(b) => {
+            let buf = allocator.buf(&b);
+            buf.len() == 1 && buf[0] == 1
+        }
+        _ => false,
+    }
+}
+
 struct OptimizerRunner<'a> {
     pub name: String,
     to_run: &'a dyn Fn(&mut Allocator, NodePtr, Rc<dyn TRunProgram>) -> Result<NodePtr, EvalErr>,
@@ -658,13 +924,25 @@ pub fn optimize_sexp_<'a>(
             }
             SExp::Pair(_, _) => {
                 for opt in OPTIMIZERS.iter() {
+                    if !pass_enabled(&opt.name) {
+                        continue;
+                    }
+
                     name = opt.name.clone();
+                    let accounted_before = reporting();
                     match opt.invoke(allocator, r, eval_f.clone()) {
                         Err(e) => {
                             return Err(e);
                         }
                         Ok(res) => {
                             if !equal_to(allocator, r, res) {
+                                accounted_before.map(|before| {
+                                    let size_change = sexp_as_bin(allocator, res).length() as i64
+                                        - sexp_as_bin(allocator, r).length() as i64;
+                                    let cost_change = estimate_cost(allocator, res) as i64
+                                        - estimate_cost(allocator, r) as i64;
+                                    record_rewrite(&name, before, size_change, cost_change);
+                                });
                                 r = res;
                                 break;
                             }
@@ -890,3 +1168,59 @@ fn test_optimize_1() {
         "(q 4 (q . \"opt\") (c (c (q . \"com\") (c (c (q . 1) 2) (q (29041 (\"unquote\" (\"macros\"))) (29041 (\"unquote\" (\"symbols\")))))) ()))".to_string()
     );
 }
+
+fn test_optimizer_report(src: String, passes: Option<Vec<&str>>) -> (String, OptimizerReport) {
+    let enabled = passes.map(|p| p.iter().map(|x| x.to_string()).collect());
+    let _configuration = configure_optimizer(enabled, true).unwrap();
+    let optimized = test_optimizer(src);
+    (optimized, take_optimizer_report().unwrap())
+}
+
+#[test]
+fn optimizer_report_counts_rewrites() {
+    let src = "(a (q 16 5 (q . 1)) (c (q . 3) 1))".to_string();
+    let (optimized, report) = test_optimizer_report(src, None);
+    assert_eq!(optimized, "(+ 2 (q . 1))");
+
+    let total = report.total();
+    assert!(total.rewrites > 0);
+    assert!(total.size_change < 0);
+    assert!(total.cost_change < 0);
+    assert_eq!(
+        report
+            .passes
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<&str>>(),
+        OPTIMIZER_PASSES.to_vec()
+    );
+}
+
+#[test]
+fn optimizer_report_selected_passes() {
+    let src = "(a (q 16 5 (q . 1)) (c (q . 3) 1))".to_string();
+    let (optimized, report) = test_optimizer_report(src.clone(), Some(vec!["path_optimizer"]));
+    assert_eq!(optimized, src);
+    assert_eq!(report.total().rewrites, 0);
+}
+
+#[test]
+fn optimizer_rejects_unknown_pass() {
+    let enabled = Some(vec!["fast_optimizer".to_string()].into_iter().collect());
+    assert_eq!(
+        configure_optimizer(enabled, false).err(),
+        Some("unknown optimizer pass fast_optimizer".to_string())
+    );
+}
+
+#[test]
+fn estimate_cost_counts_operators() {
+    let mut allocator = Allocator::new();
+    let src = "(+ (q . 3) 2)".to_string();
+    let assembled = assemble_from_ir(&mut allocator, Rc::new(read_ir(&src).unwrap())).unwrap();
+    // + with two arguments, a quote and a lookup of path 2.
+    assert_eq!(
+        estimate_cost(&mut allocator, assembled),
+        99 + 2 * 320 + 20 + 72
+    );
+}
@@ -0,0 +1,924 @@
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use clvm_rs::allocator::Allocator;
+
+use num_bigint::ToBigInt;
+
+use crate::classic::clvm_tools::stages::stage_0::TRunProgram;
+
+use crate::compiler::clvm::{
+    run,
+    run_step,
+    RunStep
+};
+use crate::compiler::comptypes::{
+    CompileErr,
+    CompileForm,
+    CompilerOpts,
+    HelperForm
+};
+use crate::compiler::coverage::CoverageHits;
+use crate::compiler::frontend::frontend;
+use crate::compiler::gensym::ungensym;
+use crate::compiler::runtypes::RunFailure;
+use crate::compiler::sexp::{
+    decode_string,
+    parse_sexp,
+    SExp
+};
+use crate::compiler::srcloc::Srcloc;
+use crate::util::Number;
+
+#[derive(Clone, Debug)]
+pub struct PriorResult {
+    pub reference: usize,
+    pub value: Rc<SExp>
+}
+
+fn format_arg_inputs(args: &Vec<PriorResult>) -> String {
+    let value_strings: Vec<String> = args.iter().map(|pr| {
+        return pr.reference.to_string();
+    }).collect();
+    return value_strings.join(", ");
+}
+
+fn get_arg_associations(
+    associations: &HashMap<Number, PriorResult>,
+    args: Rc<SExp>
+) -> Vec<PriorResult> {
+    let mut arg_exp: Rc<SExp> = args;
+    let mut result: Vec<PriorResult> = Vec::new();
+    loop {
+        match arg_exp.borrow() {
+            SExp::Cons(_, arg, rest) => {
+                match arg.get_number().ok().as_ref().and_then(|n| associations.get(n)) {
+                    Some(n) => { result.push(n.clone()); },
+                    _ => { }
+                }
+                arg_exp = rest.clone();
+            },
+            _ => { return result; }
+        }
+    }
+}
+
+fn whether_is_apply(
+    s: &SExp,
+    collector: &mut BTreeMap<String, String>,
+    if_true: &dyn Fn(&mut BTreeMap<String, String>),
+    if_false: &dyn Fn(&mut BTreeMap<String, String>)
+) {
+    match s {
+        SExp::Integer(_, i) => {
+            if *i == 2_i32.to_bigint().unwrap() {
+                if_true(collector);
+                return;
+            }
+        },
+        _ => { }
+    }
+
+    if_false(collector);
+}
+
+/* Information about the program being debugged that isn't part of the clvm
+ * state itself.  Decorates each row of output with context a user would want
+ * to see.
+ */
+pub trait CldbEnvironment {
+    fn add_context(&self, s: &SExp, c: &SExp, args: Option<Rc<SExp>>, context_result: &mut BTreeMap<String, String>);
+    fn add_function(&self, s: &SExp, context_result: &mut BTreeMap<String, String>);
+}
+
+pub struct CldbRunEnv {
+    input_file: Option<String>,
+    program_lines: Vec<String>
+}
+
+impl CldbRunEnv {
+    pub fn new(input_file: Option<String>, program_lines: Vec<String>) -> Self {
+        CldbRunEnv {
+            input_file: input_file,
+            program_lines: program_lines
+        }
+    }
+
+    fn extract_text(&self, l: &Srcloc) -> Option<String> {
+        let use_line = if l.line < 1 { None } else { Some(l.line - 1) };
+        let use_col = use_line.and_then(|_| if l.col < 1 { None } else { Some(l.col - 1) });
+        let end_col = use_col.map(|c| l.until.map(|u| u.1 - 1).unwrap_or_else(|| c + 1));
+
+        match (use_line, use_col, end_col) {
+            (Some(use_line), Some(use_col), Some(end_col)) => {
+                if use_line >= self.program_lines.len() {
+                    return None;
+                }
+
+                let line_text = self.program_lines[use_line].to_string();
+                if use_col >= line_text.len() {
+                    None
+                } else if end_col >= line_text.len() {
+                    Some(line_text[use_col..].to_string())
+                } else if end_col < use_col {
+                    None
+                } else {
+                    Some(line_text[use_col..end_col].to_string())
+                }
+            },
+            _ => None
+        }
+    }
+}
+
+impl CldbEnvironment for CldbRunEnv {
+    fn add_context(&self, s: &SExp, c: &SExp, args: Option<Rc<SExp>>, context_result: &mut BTreeMap<String, String>) {
+        whether_is_apply(
+            s,
+            context_result,
+            &|context_result| {
+                match c {
+                    SExp::Cons(_, a, b) => {
+                        context_result.insert("Env".to_string(), a.to_string());
+                        context_result.insert("Env-Args".to_string(), b.to_string());
+                    },
+                    _ => {
+                        context_result.insert("Function-Context".to_string(), c.to_string());
+                    }
+                }
+            },
+            &|context_result| {
+                match &args {
+                    Some(a) => {
+                        context_result.insert("Arguments".to_string(), a.to_string());
+                    },
+                    _ => { }
+                }
+            }
+        );
+    }
+
+    fn add_function(&self, s: &SExp, context_result: &mut BTreeMap<String, String>) {
+        whether_is_apply(
+            s,
+            context_result,
+            &|_| { },
+            &|context_result| {
+                match self.extract_text(&s.loc()) {
+                    Some(name) => {
+                        if Some(s.loc().file.to_string()) == self.input_file {
+                            context_result.insert("Function".to_string(), name);
+                        }
+                    },
+                    _ => { }
+                }
+            }
+        );
+    }
+}
+
+/* A clvm run that can be advanced one step at a time, producing the same rows
+ * that cldb prints.  Used by cldb itself and by the wasm stepping api.
+ */
+pub struct CldbRun {
+    runner: Rc<dyn TRunProgram>,
+    prim_map: Rc<HashMap<Vec<u8>, Rc<SExp>>>,
+    env: Box<dyn CldbEnvironment>,
+
+    step: RunStep,
+    ended: bool,
+    final_result: Option<Rc<SExp>>,
+
+    to_print: BTreeMap<String, String>,
+    in_expr: bool,
+    row: usize,
+
+    outputs_to_step: HashMap<Number, PriorResult>,
+
+    coverage: Option<CoverageHits>
+}
+
+impl CldbRun {
+    pub fn new(
+        runner: Rc<dyn TRunProgram>,
+        prim_map: Rc<HashMap<Vec<u8>, Rc<SExp>>>,
+        env: Box<dyn CldbEnvironment>,
+        step: RunStep
+    ) -> Self {
+        CldbRun {
+            runner: runner,
+            prim_map: prim_map,
+            env: env,
+            step: step,
+            ended: false,
+            final_result: None,
+            to_print: BTreeMap::new(),
+            in_expr: false,
+            row: 0,
+            outputs_to_step: HashMap::new(),
+            coverage: None
+        }
+    }
+
+    // Count the code evaluated from here on for a coverage report.
+    pub fn collect_coverage(&mut self) {
+        if self.coverage.is_none() {
+            self.coverage = Some(CoverageHits::new());
+        }
+    }
+
+    pub fn coverage(&self) -> Option<&CoverageHits> {
+        self.coverage.as_ref()
+    }
+
+    pub fn is_ended(&self) -> bool {
+        self.ended
+    }
+
+    pub fn final_result(&self) -> Option<Rc<SExp>> {
+        self.final_result.clone()
+    }
+
+    pub fn current_step(&self) -> RunStep {
+        self.step.clone()
+    }
+
+    // Advance the machine by one step.  If that completes a row of output,
+    // return it.
+    pub fn step(&mut self, allocator: &mut Allocator) -> Option<BTreeMap<String, String>> {
+        let mut produce_result = false;
+        let mut result = None;
+
+        if self.ended {
+            return None;
+        }
+
+        match &mut self.coverage {
+            Some(hits) => { hits.record_step(&self.step); },
+            None => { }
+        }
+
+        let new_step = run_step(
+            allocator,
+            self.runner.clone(),
+            self.prim_map.clone(),
+            &self.step
+        );
+
+        match &new_step {
+            Ok(RunStep::OpResult(l, x, _p)) => {
+                if self.in_expr {
+                    self.to_print.insert("Result-Location".to_string(), l.to_string());
+                    self.to_print.insert("Value".to_string(), x.to_string());
+                    self.to_print.insert("Row".to_string(), self.row.to_string());
+                    match x.get_number().ok() {
+                        Some(n) => {
+                            self.outputs_to_step.insert(n, PriorResult {
+                                reference: self.row,
+                                value: x.clone()
+                            });
+                        },
+                        _ => { }
+                    }
+                    self.in_expr = false;
+                    produce_result = true;
+                }
+            },
+            Ok(RunStep::Done(l, x)) => {
+                self.to_print.insert("Final-Location".to_string(), l.to_string());
+                self.to_print.insert("Final".to_string(), x.to_string());
+
+                self.ended = true;
+                self.final_result = Some(x.clone());
+                produce_result = true;
+            },
+            Ok(RunStep::Step(_sexp, _c, _p)) => { },
+            Ok(RunStep::Op(sexp, c, a, None, _p)) => {
+                self.to_print.insert("Operator-Location".to_string(), a.loc().to_string());
+                self.to_print.insert("Operator".to_string(), sexp.to_string());
+                match sexp.get_number().ok() {
+                    Some(v) => {
+                        if v == 11_u32.to_bigint().unwrap() {
+                            let arg_associations =
+                                get_arg_associations(&self.outputs_to_step, a.clone());
+                            let args = format_arg_inputs(&arg_associations);
+                            self.to_print.insert("Argument-Refs".to_string(), args);
+                        }
+                    },
+                    _ => { }
+                }
+                self.env.add_context(sexp.borrow(), c.borrow(), Some(a.clone()), &mut self.to_print);
+                self.env.add_function(sexp, &mut self.to_print);
+                self.in_expr = true;
+            },
+            Ok(RunStep::Op(_sexp, _c, _a, Some(_v), _p)) => { },
+            Err(RunFailure::RunExn(l, s)) => {
+                self.to_print.insert("Throw-Location".to_string(), l.to_string());
+                self.to_print.insert("Throw".to_string(), s.to_string());
+
+                self.ended = true;
+                produce_result = true;
+            },
+            Err(RunFailure::RunErr(l, s)) => {
+                self.to_print.insert("Failure-Location".to_string(), l.to_string());
+                self.to_print.insert("Failure".to_string(), s.to_string());
+
+                self.ended = true;
+                produce_result = true;
+            }
+        }
+
+        if produce_result {
+            let mut swap_table = BTreeMap::new();
+            std::mem::swap(&mut swap_table, &mut self.to_print);
+            self.row += 1;
+            result = Some(swap_table);
+        }
+
+        match new_step {
+            Ok(step) => { self.step = step; },
+            _ => { }
+        }
+
+        return result;
+    }
+}
+
+fn loc_order(l: &Srcloc) -> (String, usize, usize) {
+    (l.file.to_string(), l.line, l.col)
+}
+
+fn arg_paths_inner(
+    result: &mut Vec<(String, Number)>,
+    args: &SExp,
+    path: Number,
+    bit: Number
+) {
+    match args {
+        SExp::Atom(_, name) => {
+            result.push((decode_string(&ungensym(name)), path));
+        },
+        SExp::Cons(_, a, b) => {
+            let next_bit = bit.clone() * 2_i32.to_bigint().unwrap();
+            arg_paths_inner(result, a.borrow(), path.clone() + bit.clone(), next_bit.clone());
+            arg_paths_inner(result, b.borrow(), path + next_bit.clone(), next_bit);
+        },
+        _ => { }
+    }
+}
+
+/* The names bound by an argument list, in order, with the env path each
+ * lives at.  The environment of compiled code is (defuns . args), so the
+ * arguments start at path 3.
+ */
+pub fn arg_names(args: &SExp) -> Vec<(String, Number)> {
+    let mut result = Vec::new();
+    arg_paths_inner(&mut result, args, 3_i32.to_bigint().unwrap(), 2_i32.to_bigint().unwrap());
+    result
+}
+
+fn arg_paths(args: &SExp) -> HashMap<Vec<u8>, Number> {
+    arg_names(args).into_iter().map(|(name, path)| (name.as_bytes().to_vec(), path)).collect()
+}
+
+#[derive(Clone, Debug)]
+struct FunctionExtent {
+    name: String,
+    start: Srcloc,
+    end: Option<Srcloc>,
+    args: Rc<SExp>
+}
+
+/* Attributes locations seen at runtime to the defun whose source text
+ * contains them.  Each defun owns the text from its start up to the next
+ * toplevel form in the same file.
+ */
+#[derive(Clone, Debug)]
+pub struct FunctionMap {
+    functions: Vec<FunctionExtent>,
+    main_args: Rc<SExp>
+}
+
+impl FunctionMap {
+    pub fn empty() -> Self {
+        FunctionMap {
+            functions: Vec::new(),
+            main_args: Rc::new(SExp::Nil(Srcloc::start(&"*args*".to_string())))
+        }
+    }
+
+    pub fn new(form: &CompileForm) -> Self {
+        let mut starts: Vec<(Option<(String, Rc<SExp>)>, Srcloc)> = form.helpers.iter().map(|h| {
+            match h {
+                HelperForm::Defun(l, name, _, args, _) => {
+                    (Some((decode_string(name), args.clone())), l.clone())
+                },
+                _ => (None, h.loc())
+            }
+        }).collect();
+        starts.push((None, form.exp.loc()));
+        starts.sort_by(|a, b| loc_order(&a.1).cmp(&loc_order(&b.1)));
+
+        let mut functions = Vec::new();
+        for i in 0..starts.len() {
+            match &starts[i].0 {
+                Some((name, args)) => {
+                    let end = starts.iter().skip(i + 1).
+                        find(|s| s.1.file == starts[i].1.file).
+                        map(|s| s.1.clone());
+                    functions.push(FunctionExtent {
+                        name: name.clone(),
+                        start: starts[i].1.clone(),
+                        end: end,
+                        args: args.clone()
+                    });
+                },
+                _ => { }
+            }
+        }
+
+        FunctionMap {
+            functions: functions,
+            main_args: form.args.clone()
+        }
+    }
+
+    pub fn from_source(opts: Rc<dyn CompilerOpts>, content: &String) -> Result<Self, CompileErr> {
+        let pre_forms =
+            parse_sexp(Srcloc::start(&opts.filename()), content).map_err(|e| {
+                CompileErr(e.0, e.1)
+            })?;
+        frontend(opts, pre_forms).map(|form| FunctionMap::new(&form))
+    }
+
+    pub fn has_function(&self, name: &String) -> bool {
+        self.functions.iter().any(|f| f.name == *name)
+    }
+
+    fn extent_at(&self, loc: &Srcloc) -> Option<&FunctionExtent> {
+        let at = loc_order(loc);
+        self.functions.iter().find(|f| {
+            let after_start = *f.start.file == *loc.file && loc_order(&f.start) <= at;
+            let before_end = f.end.as_ref().map(|e| at < loc_order(e)).unwrap_or_else(|| true);
+            after_start && before_end
+        })
+    }
+
+    pub fn function_at(&self, loc: &Srcloc) -> Option<String> {
+        self.extent_at(loc).map(|f| f.name.clone())
+    }
+
+    // The argument list in scope at loc.
+    pub fn args_at(&self, loc: &Srcloc) -> Rc<SExp> {
+        self.extent_at(loc).map(|f| f.args.clone()).unwrap_or_else(|| self.main_args.clone())
+    }
+}
+
+pub fn step_location(step: &RunStep) -> Option<Srcloc> {
+    match step {
+        RunStep::Done(l, _) => Some(l.clone()),
+        RunStep::OpResult(l, _, _) => Some(l.clone()),
+        RunStep::Step(sexp, _, _) => Some(sexp.loc()),
+        RunStep::Op(head, _, _, _, _) => Some(head.loc())
+    }
+}
+
+pub fn step_context(step: &RunStep) -> Option<Rc<SExp>> {
+    match step {
+        RunStep::Done(_, _) => None,
+        RunStep::OpResult(_, _, p) => step_context(p.borrow()),
+        RunStep::Step(_, context, _) => Some(context.clone()),
+        RunStep::Op(_, context, _, _, _) => Some(context.clone())
+    }
+}
+
+/* One frame of the pending computation: an operator waiting on its
+ * arguments, along with the environment it runs in.
+ */
+pub struct StackFrame {
+    pub head: Rc<SExp>,
+    pub context: Rc<SExp>,
+    pub args: Rc<SExp>,
+    pub remaining: usize
+}
+
+pub fn stack_frames(step: &RunStep) -> Vec<StackFrame> {
+    let mut frames = Vec::new();
+    let mut at = Rc::new(step.clone());
+
+    loop {
+        let next = match at.borrow() {
+            RunStep::Done(_, _) => { return frames; },
+            RunStep::OpResult(_, _, p) => p.clone(),
+            RunStep::Step(_, _, p) => p.clone(),
+            RunStep::Op(head, context, args, remaining, p) => {
+                frames.push(StackFrame {
+                    head: head.clone(),
+                    context: context.clone(),
+                    args: args.clone(),
+                    remaining: remaining.as_ref().map(|r| r.len()).unwrap_or_else(|| 0)
+                });
+                p.clone()
+            }
+        };
+        at = next;
+    }
+}
+
+// The arguments part of a (defuns . args) environment.
+pub fn env_args(context: &SExp) -> Rc<SExp> {
+    match context {
+        SExp::Cons(_, _, b) => b.clone(),
+        _ => Rc::new(context.clone())
+    }
+}
+
+/* A function call in progress, innermost first in call_stack.  loc is where
+ * execution is in that call.
+ */
+#[derive(Clone, Debug)]
+pub struct CallFrame {
+    pub name: String,
+    pub loc: Srcloc,
+    pub context: Rc<SExp>
+}
+
+fn is_apply(head: &SExp) -> bool {
+    match head {
+        SExp::Integer(_, i) => *i == 2_i32.to_bigint().unwrap(),
+        _ => false
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Breakpoint {
+    // Stop when execution arrives at the file and line of the Srcloc.
+    Location(Srcloc),
+    // Stop when the named defun is called.
+    FunctionEntry(String),
+    // Stop just before an operator with this opcode is applied.
+    Operator(Number)
+}
+
+impl Breakpoint {
+    pub fn to_string(&self) -> String {
+        match self {
+            Breakpoint::Location(l) => format!("line {}:{}", l.file, l.line),
+            Breakpoint::FunctionEntry(name) => format!("function {}", name),
+            Breakpoint::Operator(op) => format!("operator {}", op)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Watch {
+    pub text: String,
+    pub expr: Rc<SExp>
+}
+
+/* Breakpoints and watch expressions over a CldbRun, so that tools can run a
+ * program until something interesting happens rather than inspecting each
+ * step themselves.
+ *
+ * Watch expressions are clvm evaluated against the current environment, with
+ * argument names of the current function replaced by their paths.
+ */
+pub struct CldbDebugger {
+    run: CldbRun,
+    functions: FunctionMap,
+    breakpoints: Vec<Option<Breakpoint>>,
+    watches: Vec<Option<Watch>>,
+    last_line: Option<(String, usize)>
+}
+
+impl CldbDebugger {
+    pub fn new(run: CldbRun, functions: FunctionMap) -> Self {
+        CldbDebugger {
+            run: run,
+            functions: functions,
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+            last_line: None
+        }
+    }
+
+    pub fn is_ended(&self) -> bool {
+        self.run.is_ended()
+    }
+
+    pub fn final_result(&self) -> Option<Rc<SExp>> {
+        self.run.final_result()
+    }
+
+    pub fn current_step(&self) -> RunStep {
+        self.run.current_step()
+    }
+
+    pub fn functions(&self) -> &FunctionMap {
+        &self.functions
+    }
+
+    // Breakpoints are numbered from 1 in the order they're added.
+    pub fn add_breakpoint(&mut self, bp: Breakpoint) -> usize {
+        self.breakpoints.push(Some(bp));
+        self.breakpoints.len()
+    }
+
+    pub fn remove_breakpoint(&mut self, id: usize) -> bool {
+        if id > 0 && id <= self.breakpoints.len() && self.breakpoints[id - 1].is_some() {
+            self.breakpoints[id - 1] = None;
+            return true;
+        }
+
+        false
+    }
+
+    pub fn get_breakpoint(&self, id: usize) -> Option<Breakpoint> {
+        if id > 0 && id <= self.breakpoints.len() {
+            return self.breakpoints[id - 1].clone();
+        }
+
+        None
+    }
+
+    pub fn breakpoints(&self) -> Vec<(usize, Breakpoint)> {
+        self.breakpoints.iter().enumerate().filter_map(|(i, b)| {
+            b.as_ref().map(|b| (i + 1, b.clone()))
+        }).collect()
+    }
+
+    // Watches are numbered from 1 like breakpoints.
+    pub fn add_watch(&mut self, text: &String) -> Result<usize, CompileErr> {
+        let parsed = parse_sexp(Srcloc::start(&"*watch*".to_string()), text).map_err(|e| {
+            CompileErr(e.0, e.1)
+        })?;
+        if parsed.len() != 1 {
+            return Err(CompileErr(
+                Srcloc::start(&"*watch*".to_string()),
+                format!("expected one expression in {}", text)
+            ));
+        }
+
+        self.watches.push(Some(Watch { text: text.clone(), expr: parsed[0].clone() }));
+        Ok(self.watches.len())
+    }
+
+    pub fn remove_watch(&mut self, id: usize) -> bool {
+        if id > 0 && id <= self.watches.len() && self.watches[id - 1].is_some() {
+            self.watches[id - 1] = None;
+            return true;
+        }
+
+        false
+    }
+
+    pub fn watches(&self) -> Vec<(usize, Watch)> {
+        self.watches.iter().enumerate().filter_map(|(i, w)| {
+            w.as_ref().map(|w| (i + 1, w.clone()))
+        }).collect()
+    }
+
+    /* Rewrite argument names in expr to the env paths they live at.  As in
+     * chialisp, literal numbers and strings stand for themselves, so they're
+     * quoted.
+     */
+    fn substitute_args(&self, names: &HashMap<Vec<u8>, Number>, expr: Rc<SExp>) -> Rc<SExp> {
+        match expr.borrow() {
+            SExp::Integer(l, _) | SExp::QuotedString(l, _, _) => {
+                Rc::new(SExp::Cons(
+                    l.clone(),
+                    Rc::new(SExp::Integer(l.clone(), 1_i32.to_bigint().unwrap())),
+                    expr.clone()
+                ))
+            },
+            SExp::Atom(l, name) => {
+                match names.get(name) {
+                    Some(path) => Rc::new(SExp::Integer(l.clone(), path.clone())),
+                    _ => expr.clone()
+                }
+            },
+            SExp::Cons(l, head, rest) => {
+                let quoted = match head.borrow() {
+                    SExp::Atom(_, q) => *q == "q".as_bytes().to_vec(),
+                    SExp::Integer(_, q) => *q == 1_i32.to_bigint().unwrap(),
+                    _ => false
+                };
+                if quoted {
+                    return expr.clone();
+                }
+
+                let new_head = match head.borrow() {
+                    SExp::Cons(_, _, _) => self.substitute_args(names, head.clone()),
+                    _ => head.clone()
+                };
+                let mut new_args = Vec::new();
+                let mut at = rest.clone();
+                loop {
+                    let next = match at.borrow() {
+                        SExp::Cons(_, a, b) => {
+                            new_args.push(self.substitute_args(names, a.clone()));
+                            b.clone()
+                        },
+                        _ => { break; }
+                    };
+                    at = next;
+                }
+
+                let mut result = at;
+                for a in new_args.iter().rev() {
+                    result = Rc::new(SExp::Cons(l.clone(), a.clone(), result));
+                }
+                Rc::new(SExp::Cons(l.clone(), new_head, result))
+            },
+            _ => expr.clone()
+        }
+    }
+
+    // Evaluate an expression against the environment of the current step.
+    pub fn evaluate(&self, allocator: &mut Allocator, expr: Rc<SExp>) -> Result<Rc<SExp>, RunFailure> {
+        let step = self.run.current_step();
+        match (step_location(&step), step_context(&step)) {
+            (Some(l), Some(context)) => self.evaluate_in(allocator, &l, context, expr),
+            _ => Err(RunFailure::RunErr(expr.loc(), "the program has finished".to_string()))
+        }
+    }
+
+    /* Evaluate an expression in context, with the argument names of the
+     * function containing loc in scope.
+     */
+    pub fn evaluate_in(
+        &self,
+        allocator: &mut Allocator,
+        loc: &Srcloc,
+        context: Rc<SExp>,
+        expr: Rc<SExp>
+    ) -> Result<Rc<SExp>, RunFailure> {
+        let names = arg_paths(self.functions.args_at(loc).borrow());
+        let to_run = self.substitute_args(&names, expr);
+        run(allocator, self.run.runner.clone(), self.run.prim_map.clone(), to_run, context)
+    }
+
+    // The value of each argument of the function running in frame.
+    pub fn frame_arguments(
+        &self,
+        allocator: &mut Allocator,
+        frame: &CallFrame
+    ) -> Vec<(String, Result<Rc<SExp>, RunFailure>)> {
+        arg_names(self.functions.args_at(&frame.loc).borrow()).into_iter().map(|(name, path)| {
+            let path_expr = Rc::new(SExp::Integer(frame.loc.clone(), path));
+            let value = run(
+                allocator,
+                self.run.runner.clone(),
+                self.run.prim_map.clone(),
+                path_expr,
+                frame.context.clone()
+            );
+            (name, value)
+        }).collect()
+    }
+
+    // Frames that share an environment belong to the same function call.
+    fn calls(&self) -> Vec<(Srcloc, Rc<SExp>)> {
+        let step = self.run.current_step();
+        let mut calls: Vec<(Srcloc, Rc<SExp>)> = Vec::new();
+
+        match (step_location(&step), step_context(&step)) {
+            (Some(l), Some(c)) => { calls.push((l, c)); },
+            _ => { }
+        }
+
+        for f in stack_frames(&step) {
+            let same_call = calls.last().map(|c| Rc::ptr_eq(&c.1, &f.context)).unwrap_or_else(|| false);
+            if !same_call {
+                calls.push((f.head.loc(), f.context.clone()));
+            }
+        }
+
+        calls
+    }
+
+    // How many calls are in progress, without naming them as call_stack does.
+    pub fn call_depth(&self) -> usize {
+        self.calls().len()
+    }
+
+    pub fn call_stack(&self) -> Vec<CallFrame> {
+        self.calls().into_iter().map(|(loc, context)| {
+            CallFrame {
+                name: self.functions.function_at(&loc).unwrap_or_else(|| "(mod)".to_string()),
+                loc: loc,
+                context: context
+            }
+        }).collect()
+    }
+
+    pub fn evaluate_watches(&self, allocator: &mut Allocator) -> Vec<(usize, Watch, Result<Rc<SExp>, RunFailure>)> {
+        self.watches().into_iter().map(|(i, w)| {
+            let result = self.evaluate(allocator, w.expr.clone());
+            (i, w, result)
+        }).collect()
+    }
+
+    // Check the step we just arrived at against the breakpoints.
+    fn breakpoint_hit(&mut self, prev: &RunStep) -> Option<usize> {
+        let current = self.run.current_step();
+
+        match &current {
+            RunStep::Op(head, _, _, None, _) => {
+                let op = head.get_number().ok();
+                for (i, bp) in self.breakpoints.iter().enumerate() {
+                    match bp {
+                        Some(Breakpoint::Operator(n)) => {
+                            if op.as_ref() == Some(n) {
+                                return Some(i + 1);
+                            }
+                        },
+                        _ => { }
+                    }
+                }
+                return None;
+            },
+            RunStep::Step(_, _, _) => { },
+            _ => { return None; }
+        }
+
+        let arrived = match step_location(&current) {
+            Some(l) => l,
+            _ => { return None; }
+        };
+
+        let this_line = (arrived.file.to_string(), arrived.line);
+        let new_line = self.last_line.as_ref() != Some(&this_line);
+        self.last_line = Some(this_line);
+
+        // Compiled function bodies begin with an apply of their own code at
+        // the body's location, so only an apply from elsewhere is a call.
+        let entered_function = match prev {
+            RunStep::Op(head, _, _, None, _) => {
+                let head_loc = head.loc();
+                let from_elsewhere = *head_loc.file != *arrived.file ||
+                    head_loc.line != arrived.line ||
+                    head_loc.col != arrived.col;
+                if is_apply(head.borrow()) && from_elsewhere {
+                    self.functions.function_at(&arrived)
+                } else {
+                    None
+                }
+            },
+            _ => None
+        };
+
+        for (i, bp) in self.breakpoints.iter().enumerate() {
+            match bp {
+                Some(Breakpoint::Location(l)) => {
+                    if new_line && l.line == arrived.line && *l.file == *arrived.file {
+                        return Some(i + 1);
+                    }
+                },
+                Some(Breakpoint::FunctionEntry(name)) => {
+                    if entered_function.as_ref() == Some(name) {
+                        return Some(i + 1);
+                    }
+                },
+                _ => { }
+            }
+        }
+
+        None
+    }
+
+    /* Run one step.  Returns the row that completed, if any, and the id of
+     * the breakpoint that the new step triggers, if any.
+     */
+    pub fn step(&mut self, allocator: &mut Allocator) -> (Option<BTreeMap<String, String>>, Option<usize>) {
+        let prev = self.run.current_step();
+        let row = self.run.step(allocator);
+        let hit = if self.run.is_ended() {
+            None
+        } else {
+            self.breakpoint_hit(&prev)
+        };
+        (row, hit)
+    }
+
+    /* Run until a breakpoint triggers or the program ends, returning the rows
+     * produced on the way and the breakpoint, if one was hit.
+     */
+    pub fn run_until_break(&mut self, allocator: &mut Allocator) -> (Vec<BTreeMap<String, String>>, Option<usize>) {
+        let mut rows = Vec::new();
+        while !self.run.is_ended() {
+            let (row, hit) = self.step(allocator);
+            match row {
+                Some(r) => { rows.push(r); },
+                _ => { }
+            }
+
+            if hit.is_some() {
+                return (rows, hit);
+            }
+        }
+
+        (rows, None)
+    }
+}
@@ -0,0 +1,444 @@
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+
+use clvm_rs::allocator::Allocator;
+use num_bigint::ToBigInt;
+
+use crate::classic::clvm::__type_compatibility__::Stream;
+
+use crate::compiler::cldb::{
+    env_args,
+    stack_frames,
+    step_context,
+    step_location,
+    Breakpoint,
+    CldbDebugger
+};
+use crate::compiler::clvm::RunStep;
+use crate::compiler::prims;
+use crate::compiler::sexp::{
+    parse_sexp,
+    SExp
+};
+use crate::compiler::srcloc::Srcloc;
+use crate::util::Number;
+
+fn describe_step(step: &RunStep) -> String {
+    match step {
+        RunStep::Done(_, x) => format!("done {}", x.to_string()),
+        RunStep::OpResult(_, x, _) => format!("result {}", x.to_string()),
+        RunStep::Step(sexp, _, _) => format!("eval {}", sexp.to_string()),
+        RunStep::Op(head, _, args, Some(remaining), _) => {
+            format!(
+                "evaluating arguments of {} ({} left) {}",
+                head.to_string(),
+                remaining.len(),
+                args.to_string()
+            )
+        },
+        RunStep::Op(head, _, args, None, _) => {
+            format!("apply {} to {}", head.to_string(), args.to_string())
+        }
+    }
+}
+
+const HELP_TEXT: &'static str = indoc! {"
+    step, s              run one step of the clvm machine
+    next, n              run until the current operation produces a value
+    continue, c          run until a breakpoint is hit or the program ends
+    break, b LINE        stop when execution arrives at LINE of the program
+    break, b FILE:LINE   stop when execution arrives at LINE of FILE
+    break, b FUNCTION    stop when FUNCTION is entered
+    break, b op OP       stop before operator OP (a name or number) runs
+    delete, d N          remove breakpoint N
+    watch EXPR           print the value of EXPR whenever execution stops
+    unwatch N            remove watch N
+    info, i              list breakpoints and watches
+    print, p env         print the current environment
+    print, p stack       print the pending operations
+    print, p EXPR        evaluate EXPR in the current environment
+    backtrace, bt        print the function calls that are in progress
+    where, w             print the current location
+    help, h              print this help
+    quit, q              leave the debugger
+"};
+
+/* An interactive debugger over a CldbDebugger.  Commands are given as lines
+ * of text and their output is written to a Stream, so the driver decides
+ * where input comes from and output goes.
+ */
+pub struct CldbRepl {
+    debugger: CldbDebugger,
+    input_file: Option<String>,
+    program_lines: Vec<String>,
+    last_command: String
+}
+
+impl CldbRepl {
+    pub fn new(
+        debugger: CldbDebugger,
+        input_file: Option<String>,
+        program_lines: Vec<String>
+    ) -> Self {
+        CldbRepl {
+            debugger: debugger,
+            input_file: input_file,
+            program_lines: program_lines,
+            last_command: "".to_string()
+        }
+    }
+
+    pub fn is_ended(&self) -> bool {
+        self.debugger.is_ended()
+    }
+
+    fn write_row(&self, out: &mut Stream, row: &BTreeMap<String, String>) {
+        for (k, v) in row.iter() {
+            out.write_string(format!("  {}: {}\n", k, v));
+        }
+    }
+
+    fn write_location(&self, out: &mut Stream) {
+        let step = self.debugger.current_step();
+        let loc = step_location(&step);
+
+        match &loc {
+            Some(l) => {
+                out.write_string(format!("{}: {}\n", l.to_string(), describe_step(&step)));
+            },
+            None => {
+                out.write_string(format!("{}\n", describe_step(&step)));
+            }
+        }
+
+        match loc {
+            Some(l) => {
+                if Some(l.file.to_string()) == self.input_file &&
+                    l.line > 0 && l.line <= self.program_lines.len() {
+                    out.write_string(format!("{:>5} | {}\n", l.line, self.program_lines[l.line - 1]));
+                }
+            },
+            _ => { }
+        }
+    }
+
+    fn write_watches(&self, allocator: &mut Allocator, out: &mut Stream) {
+        for (i, w, result) in self.debugger.evaluate_watches(allocator) {
+            match result {
+                Ok(v) => {
+                    out.write_string(format!("watch {}: {} = {}\n", i, w.text, v.to_string()));
+                },
+                Err(e) => {
+                    out.write_string(format!("watch {}: {} failed: {}\n", i, w.text, e.to_string()));
+                }
+            }
+        }
+    }
+
+    fn finished(&self, out: &mut Stream) {
+        out.write_string("the program has finished\n".to_string());
+    }
+
+    fn report_stop(&self, allocator: &mut Allocator, out: &mut Stream, hit: Option<usize>) {
+        match hit.and_then(|i| self.debugger.get_breakpoint(i).map(|b| (i, b))) {
+            Some((i, b)) => {
+                out.write_string(format!("breakpoint {}, {}\n", i, b.to_string()));
+            },
+            _ => { }
+        }
+        self.write_location(out);
+        self.write_watches(allocator, out);
+    }
+
+    fn step(&mut self, allocator: &mut Allocator, out: &mut Stream) {
+        if self.debugger.is_ended() {
+            return self.finished(out);
+        }
+
+        let (row, hit) = self.debugger.step(allocator);
+        match row {
+            Some(r) => { self.write_row(out, &r); },
+            _ => { }
+        }
+
+        if self.debugger.is_ended() {
+            return self.finished(out);
+        }
+
+        self.report_stop(allocator, out, hit);
+    }
+
+    fn next(&mut self, allocator: &mut Allocator, out: &mut Stream) {
+        if self.debugger.is_ended() {
+            return self.finished(out);
+        }
+
+        loop {
+            let (row, hit) = self.debugger.step(allocator);
+            let produced = row.is_some();
+            match row {
+                Some(r) => { self.write_row(out, &r); },
+                _ => { }
+            }
+
+            if self.debugger.is_ended() {
+                return self.finished(out);
+            }
+
+            if produced || hit.is_some() {
+                return self.report_stop(allocator, out, hit);
+            }
+        }
+    }
+
+    fn cont(&mut self, allocator: &mut Allocator, out: &mut Stream) {
+        if self.debugger.is_ended() {
+            return self.finished(out);
+        }
+
+        let (rows, hit) = self.debugger.run_until_break(allocator);
+        if self.debugger.is_ended() {
+            match rows.last() {
+                Some(r) => { self.write_row(out, r); },
+                _ => { }
+            }
+            return self.finished(out);
+        }
+
+        self.report_stop(allocator, out, hit);
+    }
+
+    // Operators can be given by number or by their name in the prim map.
+    fn operator_number(&self, spec: &str) -> Option<Number> {
+        match spec.parse::<u32>() {
+            Ok(n) => { return n.to_bigint(); },
+            _ => { }
+        }
+
+        prims::prim_map().get(&spec.as_bytes().to_vec()).and_then(|p| p.get_number().ok())
+    }
+
+    fn add_breakpoint(&mut self, out: &mut Stream, words: &Vec<&str>) {
+        let spec = words[words.len() - 1];
+        let bp = if words.len() == 3 && words[1] == "op" {
+            match self.operator_number(spec) {
+                Some(n) => Some(Breakpoint::Operator(n)),
+                None => {
+                    out.write_string(format!("no operator named {}\n", spec));
+                    return;
+                }
+            }
+        } else if words.len() != 2 {
+            None
+        } else {
+            match spec.rfind(':') {
+                Some(idx) => {
+                    match spec[idx+1..].parse::<usize>() {
+                        Ok(line) => {
+                            let mut loc = Srcloc::start(&spec[..idx].to_string());
+                            loc.line = line;
+                            Some(Breakpoint::Location(loc))
+                        },
+                        _ => None
+                    }
+                },
+                None => {
+                    match (spec.parse::<usize>(), &self.input_file) {
+                        (Ok(line), Some(f)) => {
+                            let mut loc = Srcloc::start(f);
+                            loc.line = line;
+                            Some(Breakpoint::Location(loc))
+                        },
+                        (Ok(_), None) => None,
+                        _ => {
+                            if self.debugger.functions().has_function(&spec.to_string()) {
+                                Some(Breakpoint::FunctionEntry(spec.to_string()))
+                            } else {
+                                out.write_string(format!("no function named {}\n", spec));
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+        };
+
+        match bp {
+            Some(b) => {
+                let description = b.to_string();
+                let id = self.debugger.add_breakpoint(b);
+                out.write_string(format!("breakpoint {} at {}\n", id, description));
+            },
+            None => {
+                out.write_string(format!("can't set a breakpoint at {}\n", spec));
+            }
+        }
+    }
+
+    fn delete_breakpoint(&mut self, out: &mut Stream, spec: &str) {
+        match spec.parse::<usize>() {
+            Ok(n) => {
+                if self.debugger.remove_breakpoint(n) {
+                    out.write_string(format!("deleted breakpoint {}\n", n));
+                    return;
+                }
+            },
+            _ => { }
+        }
+
+        out.write_string(format!("no breakpoint {}\n", spec));
+    }
+
+    fn add_watch(&mut self, allocator: &mut Allocator, out: &mut Stream, text: &String) {
+        match self.debugger.add_watch(text) {
+            Ok(id) => {
+                out.write_string(format!("watch {}: {}\n", id, text));
+                self.write_watches(allocator, out);
+            },
+            Err(e) => {
+                out.write_string(format!("can't watch {}: {}\n", text, e.1));
+            }
+        }
+    }
+
+    fn delete_watch(&mut self, out: &mut Stream, spec: &str) {
+        match spec.parse::<usize>() {
+            Ok(n) => {
+                if self.debugger.remove_watch(n) {
+                    out.write_string(format!("deleted watch {}\n", n));
+                    return;
+                }
+            },
+            _ => { }
+        }
+
+        out.write_string(format!("no watch {}\n", spec));
+    }
+
+    fn info(&self, out: &mut Stream) {
+        let breakpoints = self.debugger.breakpoints();
+        let watches = self.debugger.watches();
+
+        if breakpoints.len() == 0 {
+            out.write_string("no breakpoints\n".to_string());
+        }
+
+        for (i, b) in breakpoints.iter() {
+            out.write_string(format!("breakpoint {}: {}\n", i, b.to_string()));
+        }
+
+        for (i, w) in watches.iter() {
+            out.write_string(format!("watch {}: {}\n", i, w.text));
+        }
+    }
+
+    fn print_env(&self, out: &mut Stream) {
+        match step_context(&self.debugger.current_step()) {
+            Some(context) => {
+                match context.borrow() {
+                    SExp::Cons(_, a, b) => {
+                        out.write_string(format!("Env: {}\n", a.to_string()));
+                        out.write_string(format!("Env-Args: {}\n", b.to_string()));
+                    },
+                    _ => {
+                        out.write_string(format!("Env: {}\n", context.to_string()));
+                    }
+                }
+            },
+            None => {
+                out.write_string("no environment\n".to_string());
+            }
+        }
+    }
+
+    fn print_stack(&self, out: &mut Stream) {
+        let frames = stack_frames(&self.debugger.current_step());
+        if frames.len() == 0 {
+            out.write_string("the stack is empty\n".to_string());
+        }
+
+        for (i, f) in frames.iter().enumerate() {
+            out.write_string(format!(
+                "#{} {}: {} args {} ({} left)\n",
+                i,
+                f.head.loc().to_string(),
+                f.head.to_string(),
+                f.args.to_string(),
+                f.remaining
+            ));
+        }
+    }
+
+    fn print_expr(&self, allocator: &mut Allocator, out: &mut Stream, text: &String) {
+        let parsed = parse_sexp(Srcloc::start(&"*print*".to_string()), text);
+        match parsed {
+            Ok(exprs) => {
+                if exprs.len() != 1 {
+                    out.write_string(format!("expected one expression in {}\n", text));
+                    return;
+                }
+
+                match self.debugger.evaluate(allocator, exprs[0].clone()) {
+                    Ok(v) => { out.write_string(format!("{}\n", v.to_string())); },
+                    Err(e) => { out.write_string(format!("failed: {}\n", e.to_string())); }
+                }
+            },
+            Err(e) => {
+                out.write_string(format!("can't parse {}: {}\n", text, e.1));
+            }
+        }
+    }
+
+    fn backtrace(&self, out: &mut Stream) {
+        for (i, f) in self.debugger.call_stack().iter().enumerate() {
+            out.write_string(format!(
+                "#{} {} at {} args {}\n",
+                i,
+                f.name,
+                f.loc.to_string(),
+                env_args(&f.context).to_string()
+            ));
+        }
+    }
+
+    // Handle one line of input.  Returns false when the user asked to quit.
+    pub fn command(&mut self, allocator: &mut Allocator, line: &String, out: &mut Stream) -> bool {
+        let mut use_line = line.trim().to_string();
+        if use_line.len() == 0 {
+            use_line = self.last_command.clone();
+        } else {
+            self.last_command = use_line.clone();
+        }
+
+        let words: Vec<&str> = use_line.split_whitespace().collect();
+        if words.len() == 0 {
+            return true;
+        }
+
+        // Everything after the command word, for commands taking expressions.
+        let rest = use_line[words[0].len()..].trim().to_string();
+
+        match (words[0], words.len()) {
+            ("step", 1) | ("s", 1) => self.step(allocator, out),
+            ("next", 1) | ("n", 1) => self.next(allocator, out),
+            ("continue", 1) | ("c", 1) => self.cont(allocator, out),
+            ("break", _) | ("b", _) => self.add_breakpoint(out, &words),
+            ("delete", 2) | ("d", 2) => self.delete_breakpoint(out, words[1]),
+            ("watch", _) => self.add_watch(allocator, out, &rest),
+            ("unwatch", 2) => self.delete_watch(out, words[1]),
+            ("info", 1) | ("i", 1) => self.info(out),
+            ("print", 2) | ("p", 2) if words[1] == "env" => self.print_env(out),
+            ("print", 2) | ("p", 2) if words[1] == "stack" => self.print_stack(out),
+            ("print", _) | ("p", _) => self.print_expr(allocator, out, &rest),
+            ("backtrace", 1) | ("bt", 1) => self.backtrace(out),
+            ("where", 1) | ("w", 1) => self.write_location(out),
+            ("help", 1) | ("h", 1) => { out.write_string(HELP_TEXT.to_string()); },
+            ("quit", 1) | ("q", 1) => { return false; },
+            _ => {
+                out.write_string(format!("unrecognized command {}, try help\n", use_line));
+            }
+        }
+
+        return true;
+    }
+}
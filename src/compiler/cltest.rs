@@ -0,0 +1,348 @@
+use std::borrow::Borrow;
+use std::rc::Rc;
+
+use clvm_rs::allocator::Allocator;
+
+use serde_json::json;
+
+use crate::classic::clvm_tools::stages::stage_0::TRunProgram;
+
+use crate::compiler::clvm::{
+    run_step,
+    start_step,
+    RunStep
+};
+use crate::compiler::compiler::compile_file;
+use crate::compiler::comptypes::{
+    CompileErr,
+    CompilerOpts
+};
+use crate::compiler::coverage::{
+    Coverage,
+    CoverageHits
+};
+use crate::compiler::runtypes::RunFailure;
+use crate::compiler::sexp::{
+    decode_string,
+    parse_sexp,
+    SExp
+};
+use crate::compiler::srcloc::Srcloc;
+
+// The tests for module.clsp live beside it in module.test.clsp.
+pub const TEST_FILE_SUFFIX: &str = ".test.clsp";
+pub const MODULE_SUFFIX: &str = ".clsp";
+
+// A case that hasn't finished after this many steps fails rather than
+// running forever.
+pub const CASE_STEP_LIMIT: usize = 1000000;
+
+#[derive(Clone, Debug)]
+pub enum Expectation {
+    Value(Rc<SExp>),
+    // A raise, of the given value if there is one.
+    Raise(Option<Rc<SExp>>)
+}
+
+/* One case from a test file: arguments to run the module under test with
+ * and what it should do.  A test file holds forms like
+ *
+ *   (deftest "three" (3) 6)
+ *   (deftest-raise "negative" (-1))
+ *   (deftest-raise "too big" (100) ("too big" 100))
+ */
+#[derive(Clone, Debug)]
+pub struct TestCase {
+    pub name: String,
+    pub loc: Srcloc,
+    pub args: Rc<SExp>,
+    pub expect: Expectation
+}
+
+#[derive(Clone, Debug)]
+pub struct TestResult {
+    pub name: String,
+    pub loc: Srcloc,
+    // Where the case went wrong and how, if it did.
+    pub failure: Option<(Srcloc, String)>
+}
+
+// The results of the cases in one test file.
+#[derive(Clone, Debug)]
+pub struct TestSuite {
+    pub name: String,
+    pub results: Vec<TestResult>
+}
+
+impl TestSuite {
+    pub fn failures(&self) -> usize {
+        self.results.iter().filter(|r| r.failure.is_some()).count()
+    }
+}
+
+// The module a test file tests, if it's named as a test file.
+pub fn module_for_test_file(test_file: &String) -> Option<String> {
+    test_file.strip_suffix(TEST_FILE_SUFFIX).map(|m| format!("{}{}", m, MODULE_SUFFIX))
+}
+
+// The test file for a module.
+pub fn test_file_for_module(module: &String) -> String {
+    let base = module.strip_suffix(MODULE_SUFFIX).unwrap_or(module);
+    format!("{}{}", base, TEST_FILE_SUFFIX)
+}
+
+fn test_name(name: &SExp) -> Result<String, CompileErr> {
+    match name {
+        SExp::QuotedString(_,_,s) => Ok(decode_string(s)),
+        SExp::Atom(_,s) => Ok(decode_string(s)),
+        _ => Err(CompileErr(name.loc(), format!("test name should be a string, not {}", name.to_string())))
+    }
+}
+
+fn parse_test(form: Rc<SExp>) -> Result<TestCase, CompileErr> {
+    let items = match form.proper_list() {
+        Some(items) => items,
+        None => {
+            return Err(CompileErr(form.loc(), format!("expected a test, got {}", form.to_string())));
+        }
+    };
+    let kind = match items.get(0) {
+        Some(SExp::Atom(_,kind)) => decode_string(kind),
+        _ => "".to_string()
+    };
+
+    let expect =
+        if kind == "deftest" && items.len() == 4 {
+            Expectation::Value(Rc::new(items[3].clone()))
+        } else if kind == "deftest-raise" && items.len() == 3 {
+            Expectation::Raise(None)
+        } else if kind == "deftest-raise" && items.len() == 4 {
+            Expectation::Raise(Some(Rc::new(items[3].clone())))
+        } else if kind == "deftest" || kind == "deftest-raise" {
+            return Err(CompileErr(
+                form.loc(),
+                format!("{} takes a name, arguments and {}", kind, if kind == "deftest" {
+                    "the value expected"
+                } else {
+                    "optionally the value raised"
+                })
+            ));
+        } else {
+            return Err(CompileErr(
+                form.loc(),
+                format!("expected deftest or deftest-raise, got {}", form.to_string())
+            ));
+        };
+
+    Ok(TestCase {
+        name: test_name(&items[1])?,
+        loc: form.loc(),
+        args: Rc::new(items[2].clone()),
+        expect: expect
+    })
+}
+
+// Read the cases in a test file.
+pub fn parse_tests(file: &String, content: &String) -> Result<Vec<TestCase>, CompileErr> {
+    let forms = parse_sexp(Srcloc::start(file), content).map_err(|e| CompileErr(e.0, e.1))?;
+    let mut cases = Vec::new();
+    for f in forms.iter() {
+        cases.push(parse_test(f.clone())?);
+    }
+    Ok(cases)
+}
+
+fn check_outcome(
+    case: &TestCase,
+    outcome: Result<Rc<SExp>, RunFailure>
+) -> Option<(Srcloc, String)> {
+    match (&case.expect, outcome) {
+        (Expectation::Value(want), Ok(got)) => {
+            if got.equal_to(want.borrow()) {
+                None
+            } else {
+                Some((case.loc.clone(), format!("expected {} but got {}", want.to_string(), got.to_string())))
+            }
+        },
+        (Expectation::Value(want), Err(RunFailure::RunExn(l,raised))) => {
+            Some((l, format!("expected {} but raised {}", want.to_string(), raised.to_string())))
+        },
+        (Expectation::Value(want), Err(RunFailure::RunErr(l,e))) => {
+            Some((l, format!("expected {} but failed: {}", want.to_string(), e)))
+        },
+        (Expectation::Raise(want), Err(RunFailure::RunExn(l,raised))) => {
+            match want {
+                Some(w) => {
+                    if raised.equal_to(w.borrow()) {
+                        None
+                    } else {
+                        Some((l, format!("expected a raise of {} but raised {}", w.to_string(), raised.to_string())))
+                    }
+                },
+                None => None
+            }
+        },
+        (Expectation::Raise(_), Ok(got)) => {
+            Some((case.loc.clone(), format!("expected a raise but got {}", got.to_string())))
+        },
+        (Expectation::Raise(_), Err(RunFailure::RunErr(l,e))) => {
+            Some((l, format!("expected a raise but failed: {}", e)))
+        }
+    }
+}
+
+// Run one case against program, counting what it covers in hits if given.
+fn run_case(
+    allocator: &mut Allocator,
+    runner: Rc<dyn TRunProgram>,
+    opts: Rc<dyn CompilerOpts>,
+    program: Rc<SExp>,
+    case: &TestCase,
+    mut hits: Option<&mut CoverageHits>
+) -> Result<Rc<SExp>, RunFailure> {
+    let mut step = start_step(program, case.args.clone());
+
+    for _ in 0..CASE_STEP_LIMIT {
+        match hits.as_mut() {
+            Some(h) => { h.record_step(&step); },
+            None => { }
+        }
+        step = run_step(allocator, runner.clone(), opts.prim_map(), &step)?;
+        match step {
+            RunStep::Done(_,x) => { return Ok(x); },
+            _ => { }
+        }
+    }
+
+    Err(RunFailure::RunErr(
+        case.loc.clone(),
+        format!("didn't finish in {} steps", CASE_STEP_LIMIT)
+    ))
+}
+
+/* Compile module, the source of the module under test named by opts, and
+ * run each case against it.  If coverage is given, what the cases covered
+ * is added to it.
+ */
+pub fn run_tests(
+    allocator: &mut Allocator,
+    runner: Rc<dyn TRunProgram>,
+    opts: Rc<dyn CompilerOpts>,
+    module: &String,
+    cases: &Vec<TestCase>,
+    coverage: Option<&mut Coverage>
+) -> Vec<TestResult> {
+    let program =
+        match compile_file(allocator, runner.clone(), opts.clone(), module) {
+            Ok(p) => Rc::new(p),
+            Err(CompileErr(l, e)) => {
+                return cases.iter().map(|c| TestResult {
+                    name: c.name.clone(),
+                    loc: c.loc.clone(),
+                    failure: Some((l.clone(), format!("module didn't compile: {}", e)))
+                }).collect();
+            }
+        };
+
+    let mut hits = CoverageHits::new();
+    let mut results = Vec::new();
+    for c in cases.iter() {
+        let outcome = run_case(
+            allocator,
+            runner.clone(),
+            opts.clone(),
+            program.clone(),
+            c,
+            if coverage.is_some() { Some(&mut hits) } else { None }
+        );
+        results.push(TestResult {
+            name: c.name.clone(),
+            loc: c.loc.clone(),
+            failure: check_outcome(c, outcome)
+        });
+    }
+
+    match coverage {
+        Some(total) => { total.merge(&Coverage::from_hits(program.borrow(), &hits)); },
+        None => { }
+    }
+
+    results
+}
+
+// A report in the Test Anything Protocol.
+pub fn tap_report(suites: &Vec<TestSuite>) -> String {
+    let count: usize = suites.iter().map(|s| s.results.len()).sum();
+    let mut out = format!("TAP version 13\n1..{}\n", count);
+    let mut number = 0;
+    for s in suites.iter() {
+        for r in s.results.iter() {
+            number += 1;
+            match &r.failure {
+                None => {
+                    out.push_str(&format!("ok {} - {}: {}\n", number, s.name, r.name));
+                },
+                Some((l, message)) => {
+                    out.push_str(&format!("not ok {} - {}: {}\n", number, s.name, r.name));
+                    out.push_str("  ---\n");
+                    out.push_str(&format!("  message: {}\n", json!(message)));
+                    out.push_str(&format!("  at: {}\n", json!(l.to_string())));
+                    if *l != r.loc {
+                        out.push_str(&format!("  test: {}\n", json!(r.loc.to_string())));
+                    }
+                    out.push_str("  ...\n");
+                }
+            }
+        }
+    }
+    out
+}
+
+fn xml_escape(s: &String) -> String {
+    s.replace('&', "&amp;").
+        replace('<', "&lt;").
+        replace('>', "&gt;").
+        replace('"', "&quot;").
+        replace('\'', "&apos;")
+}
+
+// A report in the JUnit XML format most CI systems read.
+pub fn junit_report(suites: &Vec<TestSuite>) -> String {
+    let count: usize = suites.iter().map(|s| s.results.len()).sum();
+    let failures: usize = suites.iter().map(|s| s.failures()).sum();
+    let mut out = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_string();
+    out.push_str(&format!("<testsuites tests=\"{}\" failures=\"{}\">\n", count, failures));
+    for s in suites.iter() {
+        let name = xml_escape(&s.name);
+        out.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\">\n",
+            name,
+            s.results.len(),
+            s.failures()
+        ));
+        for r in s.results.iter() {
+            let case = format!(
+                "    <testcase name=\"{}\" classname=\"{}\" file=\"{}\" line=\"{}\"",
+                xml_escape(&r.name),
+                name,
+                xml_escape(&r.loc.file.to_string()),
+                r.loc.line
+            );
+            match &r.failure {
+                None => { out.push_str(&format!("{}/>\n", case)); },
+                Some((l, message)) => {
+                    out.push_str(&format!("{}>\n", case));
+                    out.push_str(&format!(
+                        "      <failure message=\"{}\">at {}</failure>\n",
+                        xml_escape(message),
+                        xml_escape(&l.to_string())
+                    ));
+                    out.push_str("    </testcase>\n");
+                }
+            }
+        }
+        out.push_str("  </testsuite>\n");
+    }
+    out.push_str("</testsuites>\n");
+    out
+}
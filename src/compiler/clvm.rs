@@ -20,6 +20,7 @@ use crate::classic::clvm_tools::stages::stage_0::{
     TRunProgram
 };
 
+use crate::compiler::coverage::CoverageHits;
 use crate::compiler::prims;
 use crate::compiler::runtypes::RunFailure;
 use crate::compiler::sexp::{
@@ -283,14 +284,24 @@ fn apply_op(
     let converted_app = convert_to_clvm_rs(allocator, application.clone())?;
     let converted_args = convert_to_clvm_rs(allocator, wrapped_args.clone())?;
 
-    runner.run_program(
+    match runner.run_program(
         allocator,
         converted_app,
         converted_args,
         None
-    ).map_err(|e| {
-        RunFailure::RunErr(head.loc(), format!("{} in {} {}", e.1, application.to_string(), wrapped_args.to_string()))
-    }).and_then(|v| convert_from_clvm_rs(allocator, head.loc(), v.1))
+    ) {
+        Ok(v) => convert_from_clvm_rs(allocator, head.loc(), v.1),
+        // x raises its arguments, which whoever runs the program may want to
+        // tell apart from other failures.
+        Err(e) => {
+            if e.1 == "clvm raise" {
+                let raised = convert_from_clvm_rs(allocator, head.loc(), e.0)?;
+                Err(RunFailure::RunExn(head.loc(), raised))
+            } else {
+                Err(RunFailure::RunErr(head.loc(), format!("{} in {} {}", e.1, application.to_string(), wrapped_args.to_string())))
+            }
+        }
+    }
 }
 
 fn atom_value(head: Rc<SExp>) -> Result<Number, RunFailure> {
@@ -603,6 +614,29 @@ pub fn run(
     }
 }
 
+/* Run as run does, counting in hits each evaluation of code at a location,
+ * for a coverage report.
+ */
+pub fn run_with_coverage(
+    allocator: &mut Allocator,
+    runner: Rc<dyn TRunProgram>,
+    prim_map: Rc<HashMap<Vec<u8>, Rc<SExp>>>,
+    sexp_: Rc<SExp>,
+    context_: Rc<SExp>,
+    hits: &mut CoverageHits
+) -> Result<Rc<SExp>, RunFailure> {
+    let mut step = start_step(sexp_.clone(), context_.clone());
+
+    loop {
+        hits.record_step(&step);
+        step = run_step(allocator, runner.clone(), prim_map.clone(), &step)?;
+        match step {
+            RunStep::Done(_,x) => { return Ok(x); },
+            _ => { }
+        }
+    }
+}
+
 pub fn parse_and_run(
     allocator: &mut Allocator,
     runner: Rc<dyn TRunProgram>,
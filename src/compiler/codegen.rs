@@ -19,7 +19,10 @@ use crate::compiler::debug::{
     build_swap_table_mut,
     relabel
 };
-use crate::compiler::gensym::gensym;
+use crate::compiler::gensym::{
+    gensym,
+    ungensym
+};
 use crate::compiler::compiler::run_optimizer;
 use crate::compiler::comptypes::{
     Binding,
@@ -42,7 +45,10 @@ use crate::compiler::comptypes::{
 };
 use crate::compiler::frontend::compile_bodyform;
 use crate::compiler::inline::replace_in_inline;
-use crate::compiler::optimize::optimize_expr;
+use crate::compiler::optimize::{
+    optimize_body_forms,
+    optimize_expr
+};
 use crate::compiler::prims::{
     primapply,
     primcons,
@@ -189,6 +195,60 @@ fn create_name_lookup(
     })
 }
 
+// Edit distance between two names, for suggesting what was meant.
+fn edit_distance(a: &[u8], b: &[u8]) -> usize {
+    let mut row: Vec<usize> = (0..b.len() + 1).collect();
+    for i in 0..a.len() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for j in 0..b.len() {
+            let subst = diag + if a[i] == b[j] { 0 } else { 1 };
+            diag = row[j + 1];
+            row[j + 1] = subst.min(row[j] + 1).min(diag + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn env_names(env: Rc<SExp>, names: &mut Vec<Vec<u8>>) {
+    match env.borrow() {
+        SExp::Atom(_,name) => { names.push(name.clone()); },
+        SExp::Cons(_,f,r) => {
+            env_names(f.clone(), names);
+            env_names(r.clone(), names);
+        },
+        _ => { }
+    }
+}
+
+// The known name closest to name, if one is close enough to be a likely
+// misspelling.  Primitives are included when looking for something to call.
+fn closest_name(compiler: &PrimaryCodegen, name: &Vec<u8>, callable: bool) -> Option<Vec<u8>> {
+    let mut candidates = Vec::new();
+    env_names(compiler.env.clone(), &mut candidates);
+    candidates.extend(compiler.constants.keys().cloned());
+    candidates.extend(compiler.defuns.keys().cloned());
+    candidates.extend(compiler.inlines.keys().cloned());
+    candidates.extend(compiler.macros.keys().cloned());
+    candidates.extend(compiler.parentfns.iter().cloned());
+    if callable {
+        candidates.extend(compiler.prims.keys().cloned());
+    }
+
+    candidates.iter().map(|c| ungensym(c)).
+        filter(|c| c != name).
+        map(|c| (edit_distance(name, &c), c)).
+        filter(|(d,_)| *d <= 2 && *d < name.len()).
+        min_by(|a,b| a.0.cmp(&b.0).then(a.1.cmp(&b.1))).
+        map(|(_,c)| c)
+}
+
+fn suggestion(compiler: &PrimaryCodegen, name: &Vec<u8>, callable: bool) -> String {
+    closest_name(compiler, name, callable).map(|c| {
+        format!(" (did you mean {}?)", decode_string(&c))
+    }).unwrap_or_else(|| "".to_string())
+}
+
 fn lookup_prim(
     compiler: &PrimaryCodegen,
     l: Srcloc,
@@ -276,7 +336,7 @@ fn codegen_to_sexp(
 }
 
 pub fn get_callable(
-    _opts: Rc<dyn CompilerOpts>,
+    opts: Rc<dyn CompilerOpts>,
     compiler: &PrimaryCodegen,
     l: Srcloc,
     atom: Rc<SExp>
@@ -307,10 +367,18 @@ pub fn get_callable(
                 },
                 (_, _, _, _, true, _) => Ok(Callable::RunCompiler),
                 (_, _, _, _, _, true) => Ok(Callable::EnvPath),
-                _ => Err(CompileErr(
-                    l.clone(),
-                    format!("no such callable '{}'", decode_string(name))
-                ))
+                _ => {
+                    let hint =
+                        if opts.strict() {
+                            suggestion(compiler, name, true)
+                        } else {
+                            "".to_string()
+                        };
+                    Err(CompileErr(
+                        l.clone(),
+                        format!("no such callable '{}'{}", decode_string(name), hint)
+                    ))
+                }
             }
         },
         SExp::Integer(_,v) => {
@@ -407,6 +475,122 @@ fn cons_up(at: Rc<SExp>) -> Rc<SExp> {
     }
 }
 
+// The number of arguments an argument shape takes, and whether it takes
+// more after that through a tail name.
+fn arg_count(args: Rc<SExp>) -> (usize, bool) {
+    match args.borrow() {
+        SExp::Cons(_,_,r) => {
+            let (count, variadic) = arg_count(r.clone());
+            (count + 1, variadic)
+        },
+        SExp::Nil(_) => (0, false),
+        _ => (0, true)
+    }
+}
+
+fn check_arity(
+    compiler: &PrimaryCodegen,
+    l: Srcloc,
+    name: &Vec<u8>,
+    call_args: &Vec<Rc<BodyForm>>
+) -> Result<(), CompileErr> {
+    if compiler.constants.contains_key(name) {
+        return Ok(());
+    }
+
+    let (defined, args) =
+        match compiler.function_args.get(name) {
+            Some(found) => found.clone(),
+            None => { return Ok(()); }
+        };
+
+    let (want, variadic) = arg_count(args);
+    if call_args.len() == want || (variadic && call_args.len() > want) {
+        return Ok(());
+    }
+
+    Err(CompileErr(
+        l,
+        format!(
+            "wrong number of arguments for function {}, want {}{} given {} (defined at {}({}):{})",
+            decode_string(name),
+            if variadic { "at least " } else { "" },
+            want,
+            call_args.len(),
+            defined.file,
+            defined.line,
+            defined.col
+        )
+    ))
+}
+
+// When a defun calls itself, every argument it passes through unchanged
+// at the end of the argument list is already in place in its own
+// environment, so that tail of the list can be reused by path rather than
+// consed up again one argument at a time.  Returns the index of the first
+// argument in the reusable tail, if there is one.
+fn shared_args_tail(
+    compiler: &PrimaryCodegen,
+    name: &Vec<u8>,
+    call_args: &Vec<Rc<BodyForm>>
+) -> Option<usize> {
+    let (defun_name, defun_args) = compiler.current_defun.as_ref()?;
+    if defun_name != name {
+        return None;
+    }
+
+    let mut unchanged = Vec::new();
+    let mut shape = defun_args.clone();
+    for arg in call_args.iter() {
+        let (here, rest) =
+            match shape.borrow() {
+                SExp::Cons(_,h,r) => (h.clone(), r.clone()),
+                _ => { return None; }
+            };
+
+        let passed_through =
+            match (arg.borrow(), here.borrow()) {
+                (BodyForm::Value(SExp::Atom(_,a)), SExp::Atom(_,b)) => {
+                    a == b && !compiler.constants.contains_key(a)
+                },
+                _ => false
+            };
+
+        unchanged.push(passed_through);
+        shape = rest;
+    }
+
+    // The defun must take exactly the arguments given for its environment
+    // to line up with the call's.
+    match shape.borrow() {
+        SExp::Nil(_) => { },
+        _ => { return None; }
+    }
+
+    let mut shared_from = unchanged.len();
+    while shared_from > 0 && unchanged[shared_from - 1] {
+        shared_from -= 1;
+    }
+
+    if shared_from < unchanged.len() {
+        Some(shared_from)
+    } else {
+        None
+    }
+}
+
+// The environment path to the arguments following the first n.
+fn args_tail_path(l: Srcloc, n: usize) -> Rc<SExp> {
+    Rc::new(SExp::Integer(l, (bi_one() << (n + 2)) - bi_one()))
+}
+
+fn with_args_tail(args: Rc<SExp>, tail: Rc<SExp>) -> Rc<SExp> {
+    match args.borrow() {
+        SExp::Cons(l,h,r) => Rc::new(SExp::Cons(l.clone(), h.clone(), with_args_tail(r.clone(), tail))),
+        _ => tail
+    }
+}
+
 fn process_defun_call(
     _opts: Rc<dyn CompilerOpts>,
     _compiler: &PrimaryCodegen,
@@ -480,6 +664,7 @@ fn compile_call(
                 },
 
                 Callable::CallInline(l,inline) => {
+                    check_arity(compiler, l.clone(), an, &tl)?;
                     replace_in_inline(
                         allocator,
                         runner,
@@ -493,15 +678,30 @@ fn compile_call(
                 },
 
                 Callable::CallDefun(l,lookup) => {
+                    check_arity(compiler, l.clone(), an, &tl)?;
+                    let shared_from =
+                        if opts.optimize() {
+                            shared_args_tail(compiler, an, &tl)
+                        } else {
+                            None
+                        };
+                    let changed_args: Vec<Rc<BodyForm>> =
+                        tl.iter().take(shared_from.unwrap_or(tl.len())).map(|x| x.clone()).collect();
+
                     generate_args_code(
                         allocator,
                         runner,
                         opts.clone(),
                         compiler,
                         l.clone(),
-                        &tl
+                        &changed_args
                     ).and_then(|args| {
-                        process_defun_call(opts.clone(), compiler, l.clone(), Rc::new(args), Rc::new(lookup))
+                        let args_code =
+                            match shared_from {
+                                Some(n) => with_args_tail(Rc::new(args), args_tail_path(l.clone(), n)),
+                                None => Rc::new(args)
+                            };
+                        process_defun_call(opts.clone(), compiler, l.clone(), args_code, Rc::new(lookup))
                     })
                 },
 
@@ -612,6 +812,17 @@ pub fn generate_expr_code(
                         create_name_lookup(compiler, l.clone(), atom).map(|f| {
                             Ok(CompiledCode(l.clone(), f))
                         }).unwrap_or_else(|_| {
+                            if opts.strict() {
+                                return Err(CompileErr(
+                                    l.clone(),
+                                    format!(
+                                        "unbound identifier {}{}",
+                                        decode_string(atom),
+                                        suggestion(compiler, atom, false)
+                                    )
+                                ));
+                            }
+
                             // Pass through atoms that don't look up on behalf of
                             // macros, as it's possible that a macro returned
                             // something that's canonically a name in number form.
@@ -629,11 +840,20 @@ pub fn generate_expr_code(
                 // a very narrow data representation, we'll need to
                 // accomodate bare numbers coming back in place of identifiers.
                 // I'm considering ways to make this better.
+                //
+                // A number is never an unbound identifier, even in strict
+                // mode.
                 SExp::Integer(l,i) => {
+                    let number_opts =
+                        if opts.strict() {
+                            opts.set_strict(false)
+                        } else {
+                            opts
+                        };
                     generate_expr_code(
                         allocator,
                         runner,
-                        opts,
+                        number_opts,
                         compiler,
                         Rc::new(BodyForm::Value(SExp::Atom(l.clone(),u8_from_number(i.clone()))))
                     )
@@ -765,9 +985,12 @@ fn codegen_(
                     &InlineFunction { args: args.clone(), body: body.clone() }
                 ))
             } else {
+                let mut defun_compiler = compiler.clone();
+                defun_compiler.current_defun = Some((name.clone(), args.clone()));
+
                 let updated_opts =
                     opts.
-                    set_compiler(compiler.clone()).
+                    set_compiler(defun_compiler).
                     set_in_defun(true).
                     set_stdenv(false).
                     set_start_env(Some(
@@ -852,7 +1075,9 @@ pub fn empty_compiler(
         env: Rc::new(SExp::Cons(l.clone(), nil_rc.clone(), nil_rc.clone())),
         to_process: Vec::new(),
         final_expr: Rc::new(BodyForm::Quoted(nil.clone())),
-        final_code: None
+        final_code: None,
+        current_defun: None,
+        function_args: HashMap::new()
     }
 }
 
@@ -978,6 +1203,15 @@ fn start_codegen(opts: Rc<dyn CompilerOpts>, comp: CompileForm) -> PrimaryCodege
     let live_helpers =
         let_helpers_with_expr.iter().filter(|x| is_defun(x)).map(|x| x.clone()).collect();
 
+    for h in let_helpers_with_expr.iter() {
+        match h {
+            HelperForm::Defun(l,name,_,args,_) => {
+                use_compiler.function_args.insert(name.clone(), (l.clone(), args.clone()));
+            },
+            _ => { }
+        }
+    }
+
     use_compiler.env =
         match opts.start_env() {
             Some(env) => env,
@@ -1099,7 +1333,7 @@ fn finalize_env_(
     }
 }
 
-fn finalize_env(
+pub fn finalize_env(
     allocator: &mut Allocator,
     runner: Rc<dyn TRunProgram>,
     opts: Rc<dyn CompilerOpts>,
@@ -1152,8 +1386,17 @@ pub fn codegen(
     opts: Rc<dyn CompilerOpts>,
     cmod: &CompileForm
 ) -> Result<SExp, CompileErr> {
+    // Defuns are compiled as programs of their own, but they've been through
+    // these passes with the rest of the program already.
+    let optimized_cmod =
+        if opts.optimize() && opts.compiler().is_none() {
+            optimize_body_forms(opts.clone(), cmod)
+        } else {
+            cmod.clone()
+        };
+
     let mut compiler =
-        dummy_functions(&start_codegen(opts.clone(), cmod.clone()))?;
+        dummy_functions(&start_codegen(opts.clone(), optimized_cmod))?;
 
     let to_process = compiler.to_process.clone();
     for f in to_process {
@@ -25,16 +25,22 @@ use crate::compiler::codegen::codegen;
 use crate::compiler::comptypes::{
     CompileErr,
     CompilerOpts,
+    Diagnostic,
     PrimaryCodegen
 };
-use crate::compiler::frontend::frontend;
+use crate::compiler::frontend::{
+    frontend,
+    frontend_diagnostics
+};
 use crate::compiler::prims;
 use crate::compiler::runtypes::RunFailure;
 use crate::compiler::sexp::{
     SExp,
-    parse_sexp
+    parse_sexp,
+    parse_sexp_recover
 };
 use crate::compiler::srcloc::Srcloc;
+use crate::compiler::typecheck::typecheck;
 
 #[derive(Clone)]
 #[derive(Debug)]
@@ -45,6 +51,7 @@ pub struct DefaultCompilerOpts {
     pub in_defun: bool,
     pub stdenv: bool,
     pub optimize: bool,
+    pub strict: bool,
     pub start_env: Option<Rc<SExp>>,
     pub prim_map: Rc<HashMap<Vec<u8>, Rc<SExp>>>
 }
@@ -61,9 +68,34 @@ pub fn compile_file(
         })?;
 
     frontend(opts.clone(), pre_forms).
+        and_then(|g| typecheck(&g).map(|_| g)).
         and_then(|g| codegen(allocator, runner, opts.clone(), &g))
 }
 
+// Every problem the parser and frontend can find in content, rather than
+// just the first.
+pub fn check_file(
+    opts: Rc<dyn CompilerOpts>,
+    content: &String
+) -> Vec<Diagnostic> {
+    let (pre_forms, parse_errors) =
+        parse_sexp_recover(Srcloc::start(&opts.filename()), content);
+    let mut diagnostics: Vec<Diagnostic> = parse_errors.into_iter().map(|e| {
+        Diagnostic::error(CompileErr(e.0, e.1))
+    }).collect();
+
+    if pre_forms.len() > 0 || diagnostics.len() == 0 {
+        let (form, mut frontend_found) = frontend_diagnostics(opts, pre_forms);
+        diagnostics.append(&mut frontend_found);
+        match form.map(|f| typecheck(&f)) {
+            Some(Err(e)) => { diagnostics.push(Diagnostic::error(e)); },
+            _ => { }
+        }
+    }
+
+    diagnostics
+}
+
 pub fn run_optimizer(
     allocator: &mut Allocator,
     runner: Rc<dyn TRunProgram>,
@@ -108,6 +140,7 @@ impl CompilerOpts for DefaultCompilerOpts {
     fn in_defun(&self) -> bool { self.in_defun }
     fn stdenv(&self) -> bool { self.stdenv }
     fn optimize(&self) -> bool { self.optimize }
+    fn strict(&self) -> bool { self.strict }
     fn start_env(&self) -> Option<Rc<SExp>> { self.start_env.clone() }
     fn prim_map(&self) -> Rc<HashMap<Vec<u8>, Rc<SExp>>> { self.prim_map.clone() }
 
@@ -131,6 +164,11 @@ impl CompilerOpts for DefaultCompilerOpts {
         copy.optimize = optimize;
         return Rc::new(copy);
     }
+    fn set_strict(&self, strict: bool) -> Rc<dyn CompilerOpts> {
+        let mut copy = self.clone();
+        copy.strict = strict;
+        return Rc::new(copy);
+    }
     fn set_compiler(&self, new_compiler: PrimaryCodegen) -> Rc<dyn CompilerOpts> {
         let mut copy = self.clone();
         copy.compiler = Some(new_compiler);
@@ -167,9 +205,9 @@ impl CompilerOpts for DefaultCompilerOpts {
         for dir in self.include_dirs.iter() {
             let mut p = PathBuf::from(dir);
             p.push(filename.clone());
-            match fs::read_to_string(p) {
+            match fs::read_to_string(&p) {
                 Err(e) => { continue; },
-                Ok(content) => { return Ok((filename, content)); }
+                Ok(content) => { return Ok((p.to_string_lossy().to_string(), content)); }
             }
         }
         return Err(CompileErr(Srcloc::start(&inc_from), format!("could not find {} to include", filename)));
@@ -196,6 +234,7 @@ impl DefaultCompilerOpts {
             in_defun: false,
             stdenv: true,
             optimize: false,
+            strict: false,
             start_env: None,
             prim_map: Rc::new(prim_map)
         }
@@ -19,6 +19,41 @@ use crate::compiler::srcloc::{
 #[derive(Debug)]
 pub struct CompileErr(pub Srcloc, pub String);
 
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+// One of possibly several problems found in a single compile.
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub loc: Srcloc,
+    pub severity: Severity,
+    pub message: String
+}
+
+impl Diagnostic {
+    pub fn error(e: CompileErr) -> Self {
+        Diagnostic { loc: e.0, severity: Severity::Error, message: e.1 }
+    }
+
+    pub fn warning(loc: Srcloc, message: String) -> Self {
+        Diagnostic { loc: loc, severity: Severity::Warning, message: message }
+    }
+
+    // Errors print as a CompileErr always has.
+    pub fn to_string(&self) -> String {
+        match self.severity {
+            Severity::Error => format!("{}: {}", self.loc.to_string(), self.message),
+            Severity::Warning => format!("{}: warning: {}", self.loc.to_string(), self.message)
+        }
+    }
+}
+
 #[derive(Clone)]
 #[derive(Debug)]
 pub struct CompiledCode(pub Srcloc, pub Rc<SExp>);
@@ -84,7 +119,33 @@ pub struct CompileForm {
     pub loc: Srcloc,
     pub args: Rc<SExp>,
     pub helpers: Vec<HelperForm>,
-    pub exp: Rc<BodyForm>
+    pub exp: Rc<BodyForm>,
+    pub types: HashMap<Vec<u8>, FunctionType>
+}
+
+// The types a defun's arguments and result can be annotated with.  They're
+// only checked, and are gone by the time code is generated.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum TypeAnnotation {
+    Any,
+    Atom,
+    Int,
+    Bytes32,
+    G1,
+    List,
+    Pair
+}
+
+// What a function was annotated with: each top level argument by its
+// original name, and the result.
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct FunctionType {
+    pub loc: Srcloc,
+    pub args: Vec<(Vec<u8>, TypeAnnotation)>,
+    pub result: TypeAnnotation
 }
 
 #[derive(Clone)]
@@ -106,7 +167,13 @@ pub struct PrimaryCodegen {
     pub env: Rc<SExp>,
     pub to_process: Vec<HelperForm>,
     pub final_expr: Rc<BodyForm>,
-    pub final_code: Option<CompiledCode>
+    pub final_code: Option<CompiledCode>,
+    // The name and argument shape of the defun whose body is being
+    // generated, if any.
+    pub current_defun: Option<(Vec<u8>, Rc<SExp>)>,
+    // Where each defun and inline function was defined and the argument
+    // shape it takes, so calls can be checked against it.
+    pub function_args: HashMap<Vec<u8>, (Srcloc, Rc<SExp>)>
 }
 
 pub trait CompilerOpts {
@@ -115,6 +182,7 @@ pub trait CompilerOpts {
     fn in_defun(&self) -> bool;
     fn stdenv(&self) -> bool;
     fn optimize(&self) -> bool;
+    fn strict(&self) -> bool;
     fn start_env(&self) -> Option<Rc<SExp>>;
     fn prim_map(&self) -> Rc<HashMap<Vec<u8>, Rc<SExp>>>;
 
@@ -122,6 +190,7 @@ pub trait CompilerOpts {
     fn set_in_defun(&self, new_in_defun: bool) -> Rc<dyn CompilerOpts>;
     fn set_stdenv(&self, new_stdenv: bool) -> Rc<dyn CompilerOpts>;
     fn set_optimize(&self, opt: bool) -> Rc<dyn CompilerOpts>;
+    fn set_strict(&self, strict: bool) -> Rc<dyn CompilerOpts>;
     fn set_compiler(&self, new_compiler: PrimaryCodegen) -> Rc<dyn CompilerOpts>;
     fn set_start_env(&self, start_env: Option<Rc<SExp>>) -> Rc<dyn CompilerOpts>;
 
@@ -130,37 +199,98 @@ pub trait CompilerOpts {
 }
 
 /* Frontend uses this to accumulate frontend forms */
+#[derive(Clone)]
 #[derive(Debug)]
 pub struct ModAccum {
     pub loc: Srcloc,
     pub helpers: Vec<HelperForm>,
+    pub types: HashMap<Vec<u8>, FunctionType>,
+    // Helpers given with override, and whether each has displaced another
+    // definition of its name yet.
+    pub overrides: HashMap<Vec<u8>, bool>,
     pub exp_form: Option<CompileForm>
 }
 
+fn start_of(l: &Srcloc) -> String {
+    format!("{}({}):{}", l.file, l.line, l.col)
+}
+
 impl ModAccum {
     pub fn set_final(&self, c: &CompileForm) -> Self {
         ModAccum {
             loc: self.loc.clone(),
             helpers: self.helpers.clone(),
+            types: self.types.clone(),
+            overrides: self.overrides.clone(),
             exp_form: Some(c.clone())
         }
     }
 
-    pub fn add_helper(&self, h: HelperForm) -> Self {
-        let mut hs = self.helpers.clone();
-        hs.push(h.clone());
+    /* Add a helper, checking that its name isn't already defined.  A helper
+     * given with override instead takes the place of the one other
+     * definition of its name, whichever order they come in.  The standard
+     * macros every mod starts with can be redefined without override.
+     */
+    pub fn merge_helper(
+        &self,
+        h: HelperForm,
+        signature: Option<FunctionType>,
+        is_override: bool
+    ) -> Result<Self, CompileErr> {
+        let name = h.name();
+        let mut merged = self.clone();
+        let existing = self.helpers.iter().position(|x| x.name() == name);
+        let replaces_standard =
+            existing.map(|i| *self.helpers[i].loc().file == "*macros*").unwrap_or(false);
+
+        let keep_new =
+            match (existing, is_override, self.overrides.get(&name)) {
+                (None, _, _) => {
+                    merged.helpers.push(h.clone());
+                    true
+                },
+                (Some(i), false, None) if replaces_standard => {
+                    merged.helpers[i] = h.clone();
+                    true
+                },
+                (Some(i), true, None) => {
+                    merged.helpers[i] = h.clone();
+                    true
+                },
+                (Some(_), false, Some(false)) => false,
+                (Some(i), _, _) => {
+                    return Err(CompileErr(
+                        h.loc(),
+                        format!(
+                            "duplicate definition of {} (at {} and {})",
+                            decode_string(&name),
+                            start_of(&self.helpers[i].loc()),
+                            start_of(&h.loc())
+                        )
+                    ));
+                }
+            };
+
+        if is_override || existing.is_some() {
+            merged.overrides.insert(name.clone(), existing.is_some());
+        }
 
-        ModAccum {
-            loc: self.loc.clone(),
-            helpers: hs,
-            exp_form: self.exp_form.clone()
+        if keep_new {
+            match signature {
+                Some(t) => { merged.types.insert(name, t); },
+                None => { merged.types.remove(&name); }
+            }
         }
+
+        Ok(merged)
     }
 
     pub fn new(loc: Srcloc) -> ModAccum {
         ModAccum {
             loc: loc,
             helpers: Vec::new(),
+            types: HashMap::new(),
+            overrides: HashMap::new(),
             exp_form: None
         }
     }
@@ -248,6 +378,20 @@ impl HelperForm {
     }
 }
 
+impl TypeAnnotation {
+    pub fn to_string(&self) -> String {
+        match self {
+            TypeAnnotation::Any => "Any",
+            TypeAnnotation::Atom => "Atom",
+            TypeAnnotation::Int => "Int",
+            TypeAnnotation::Bytes32 => "Bytes32",
+            TypeAnnotation::G1 => "G1",
+            TypeAnnotation::List => "List",
+            TypeAnnotation::Pair => "Pair"
+        }.to_string()
+    }
+}
+
 impl BodyForm {
     pub fn loc(&self) -> Srcloc {
         match self {
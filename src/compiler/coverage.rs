@@ -0,0 +1,383 @@
+use std::borrow::Borrow;
+use std::collections::{
+    BTreeMap,
+    HashMap
+};
+
+use clvm_rs::allocator;
+use clvm_rs::allocator::{
+    Allocator,
+    NodePtr
+};
+
+use num_bigint::ToBigInt;
+
+use crate::compiler::clvm::RunStep;
+use crate::compiler::sexp::SExp;
+use crate::compiler::srcloc::Srcloc;
+
+// Code in these files is made up by the compiler rather than read from a
+// source file, so there's nothing to report coverage of.
+const GENERATED_FILES: &[&str] = &["*macros*", "*prims*", "*nil*", "*args*"];
+
+fn is_generated(loc: &Srcloc) -> bool {
+    GENERATED_FILES.iter().any(|f| *loc.file == *f)
+}
+
+fn loc_key(loc: &Srcloc) -> (String, usize, usize) {
+    (loc.file.to_string(), loc.line, loc.col)
+}
+
+/* How many times the code starting at each location was evaluated. */
+#[derive(Clone, Debug, Default)]
+pub struct CoverageHits {
+    counts: HashMap<(String, usize, usize), u64>
+}
+
+impl CoverageHits {
+    pub fn new() -> Self {
+        CoverageHits { counts: HashMap::new() }
+    }
+
+    pub fn add(&mut self, loc: &Srcloc, count: u64) {
+        *self.counts.entry(loc_key(loc)).or_insert(0) += count;
+    }
+
+    pub fn record(&mut self, loc: &Srcloc) {
+        self.add(loc, 1);
+    }
+
+    // Each step that starts evaluating an expression is a hit on its code.
+    pub fn record_step(&mut self, step: &RunStep) {
+        match step {
+            RunStep::Step(sexp, _, _) => { self.record(&sexp.loc()); },
+            _ => { }
+        }
+    }
+
+    fn add_evals(
+        &mut self,
+        allocator: &Allocator,
+        program: NodePtr,
+        code: &SExp,
+        evals: &HashMap<NodePtr, u64>,
+        nested: &HashMap<(NodePtr, NodePtr), u64>
+    ) {
+        match (allocator.sexp(program), code) {
+            // Atoms may be shared between places in the program, so only the
+            // evaluation of a cons, which includes any of its atoms, says
+            // where code ran.
+            (allocator::SExp::Pair(a,b), SExp::Cons(l,ca,cb)) => {
+                match evals.get(&program) {
+                    Some(n) => { self.add(l, *n); },
+                    None => { }
+                }
+                // An arm that is an atom is only told apart by the decision
+                // applying it.
+                match (decision(code), decision_arms(allocator, program)) {
+                    (Some((_, then_arm, else_arm)), Some((then_ptr, else_ptr))) => {
+                        for (arm, ptr) in [(then_arm, then_ptr), (else_arm, else_ptr)].iter() {
+                            match (arm, nested.get(&(program, *ptr))) {
+                                (SExp::Cons(_,_,_), _) => { },
+                                (_, Some(n)) => { self.add(&arm.loc(), *n); },
+                                _ => { }
+                            }
+                        }
+                    },
+                    _ => { }
+                }
+                self.add_evals(allocator, a, ca.borrow(), evals, nested);
+                self.add_evals(allocator, b, cb.borrow(), evals, nested);
+            },
+            _ => { }
+        }
+    }
+
+    /* Hits from a run of program by clvm_rs, given how many times each of
+     * its nodes was evaluated, how many times each was evaluated directly
+     * inside another and code, a located copy of program.
+     */
+    pub fn from_evals(
+        allocator: &Allocator,
+        program: NodePtr,
+        code: &SExp,
+        evals: &HashMap<NodePtr, u64>,
+        nested: &HashMap<(NodePtr, NodePtr), u64>
+    ) -> Self {
+        let mut hits = CoverageHits::new();
+        hits.add_evals(allocator, program, code, evals, nested);
+        hits
+    }
+
+    pub fn count(&self, loc: &Srcloc) -> u64 {
+        self.counts.get(&loc_key(loc)).cloned().unwrap_or(0)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileCoverage {
+    // Hits by line.
+    pub lines: BTreeMap<usize, u64>,
+    // Hits by the line and column of a decision and which arm was taken,
+    // 0 for the first and 1 for the second.
+    pub branches: BTreeMap<(usize, usize, usize), u64>
+}
+
+impl FileCoverage {
+    pub fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|n| **n > 0).count()
+    }
+
+    pub fn branches_hit(&self) -> usize {
+        self.branches.values().filter(|n| **n > 0).count()
+    }
+}
+
+/* Line and branch coverage of source files, kept the way LCOV keeps it so
+ * that runs can be merged with each other and with LCOV files.
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Coverage {
+    pub files: BTreeMap<String, FileCoverage>
+}
+
+fn number_is(sexp: &SExp, n: i32) -> bool {
+    match sexp {
+        SExp::Cons(_,_,_) => false,
+        _ => sexp.get_number().ok() == Some(n.to_bigint().unwrap())
+    }
+}
+
+// The code x in (q . x).
+fn quoted_code(sexp: &SExp) -> Option<&SExp> {
+    match sexp {
+        SExp::Cons(_,q,x) => {
+            if number_is(q.borrow(), 1) {
+                Some(x.borrow())
+            } else {
+                None
+            }
+        },
+        _ => None
+    }
+}
+
+// The items of a proper list, borrowed from it.
+fn list_items(sexp: &SExp) -> Option<Vec<&SExp>> {
+    let mut items = Vec::new();
+    let mut rest = sexp;
+    loop {
+        match rest {
+            SExp::Cons(_,a,b) => {
+                items.push(a.borrow());
+                rest = b.borrow();
+            },
+            SExp::Nil(_) => { return Some(items); },
+            _ => { return None; }
+        }
+    }
+}
+
+// The condition and arms of an if, which is compiled to
+// (a (i C (q . A) (q . B)) env) so that only the arm chosen is evaluated.
+fn decision(sexp: &SExp) -> Option<(&SExp, &SExp, &SExp)> {
+    let apply = list_items(sexp)?;
+    if apply.len() != 3 || !number_is(apply[0], 2) {
+        return None;
+    }
+    let choose = list_items(apply[1])?;
+    if choose.len() != 4 || !number_is(choose[0], 3) {
+        return None;
+    }
+    Some((choose[1], quoted_code(choose[2])?, quoted_code(choose[3])?))
+}
+
+// The arms of a decision in program, whose located copy decision accepts.
+fn decision_arms(allocator: &Allocator, program: NodePtr) -> Option<(NodePtr, NodePtr)> {
+    let pair = |p: NodePtr| match allocator.sexp(p) {
+        allocator::SExp::Pair(a,b) => Some((a,b)),
+        _ => None
+    };
+    let (_, apply_args) = pair(program)?;
+    let (choose, _) = pair(apply_args)?;
+    let (_, choose_args) = pair(choose)?;
+    let (_, arms) = pair(choose_args)?;
+    let (quoted_then, rest) = pair(arms)?;
+    let (quoted_else, _) = pair(rest)?;
+    Some((pair(quoted_then)?.1, pair(quoted_else)?.1))
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage { files: BTreeMap::new() }
+    }
+
+    fn file(&mut self, loc: &Srcloc) -> &mut FileCoverage {
+        self.files.entry(loc.file.to_string()).or_insert_with(Default::default)
+    }
+
+    fn add_code(&mut self, code: &SExp, hits: &CoverageHits) {
+        let loc = code.loc();
+        if !is_generated(&loc) {
+            let count = hits.count(&loc);
+            let line = self.file(&loc).lines.entry(loc.line).or_insert(0);
+            *line = std::cmp::max(*line, count);
+        }
+
+        match decision(code) {
+            Some((c, then_arm, else_arm)) => {
+                let cond_loc = c.loc();
+                if !is_generated(&cond_loc) {
+                    let then_count = hits.count(&then_arm.loc());
+                    let else_count = hits.count(&else_arm.loc());
+                    let file = self.file(&cond_loc);
+                    file.branches.insert((cond_loc.line, cond_loc.col, 0), then_count);
+                    file.branches.insert((cond_loc.line, cond_loc.col, 1), else_count);
+                }
+            },
+            None => { }
+        }
+
+        match code {
+            SExp::Cons(_,a,b) => {
+                self.add_code(a.borrow(), hits);
+                self.add_code(b.borrow(), hits);
+            },
+            _ => { }
+        }
+    }
+
+    /* The coverage of code by the runs that made hits.  A line's hits are
+     * those of the most evaluated code on it; code that was never evaluated
+     * leaves its line uncovered unless other code on it was.
+     */
+    pub fn from_hits(code: &SExp, hits: &CoverageHits) -> Self {
+        let mut coverage = Coverage::new();
+        coverage.add_code(code, hits);
+        coverage
+    }
+
+    // Add the hits of other to these, as for another run of the same code.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (name, theirs) in other.files.iter() {
+            let ours = self.files.entry(name.clone()).or_insert_with(Default::default);
+            for (line, n) in theirs.lines.iter() {
+                *ours.lines.entry(*line).or_insert(0) += n;
+            }
+            for (branch, n) in theirs.branches.iter() {
+                *ours.branches.entry(*branch).or_insert(0) += n;
+            }
+        }
+    }
+
+    pub fn to_lcov(&self) -> String {
+        let mut out = String::new();
+        for (name, file) in self.files.iter() {
+            out.push_str("TN:\n");
+            out.push_str(&format!("SF:{}\n", name));
+            for ((line, block, arm), n) in file.branches.iter() {
+                out.push_str(&format!("BRDA:{},{},{},{}\n", line, block, arm, n));
+            }
+            out.push_str(&format!("BRF:{}\n", file.branches.len()));
+            out.push_str(&format!("BRH:{}\n", file.branches_hit()));
+            for (line, n) in file.lines.iter() {
+                out.push_str(&format!("DA:{},{}\n", line, n));
+            }
+            out.push_str(&format!("LF:{}\n", file.lines.len()));
+            out.push_str(&format!("LH:{}\n", file.lines_hit()));
+            out.push_str("end_of_record\n");
+        }
+        out
+    }
+
+    /* Read the line and branch records of an LCOV file.  Other records are
+     * ignored, since the totals they give are worked out again when written.
+     */
+    pub fn from_lcov(text: &String) -> Result<Self, String> {
+        let mut coverage = Coverage::new();
+        let mut current: Option<String> = None;
+        for (i, l) in text.lines().enumerate() {
+            let bad_record = || format!("bad lcov record at line {}: {}", i + 1, l);
+            let parts: Vec<&str> = l.trim().splitn(2, ':').collect();
+            let fields: Vec<u64> =
+                if parts.len() == 2 && (parts[0] == "DA" || parts[0] == "BRDA") {
+                    // A line may be followed by a checksum of its text.
+                    let wanted = if parts[0] == "DA" { 2 } else { 4 };
+                    let mut fields = Vec::new();
+                    for f in parts[1].split(',').take(wanted) {
+                        // A branch whose decision was never reached has no
+                        // count.
+                        let n = if f == "-" { Ok(0) } else { f.parse::<u64>() };
+                        fields.push(n.map_err(|_| bad_record())?);
+                    }
+                    fields
+                } else {
+                    Vec::new()
+                };
+
+            match (parts[0], &current) {
+                ("SF", _) => {
+                    if parts.len() != 2 {
+                        return Err(bad_record());
+                    }
+                    let name = parts[1].to_string();
+                    coverage.files.entry(name.clone()).or_insert_with(Default::default);
+                    current = Some(name);
+                },
+                ("end_of_record", _) => { current = None; },
+                ("DA", Some(name)) => {
+                    if fields.len() != 2 {
+                        return Err(bad_record());
+                    }
+                    let file = coverage.files.get_mut(name).unwrap();
+                    *file.lines.entry(fields[0] as usize).or_insert(0) += fields[1];
+                },
+                ("BRDA", Some(name)) => {
+                    if fields.len() != 4 {
+                        return Err(bad_record());
+                    }
+                    let file = coverage.files.get_mut(name).unwrap();
+                    let key = (fields[0] as usize, fields[1] as usize, fields[2] as usize);
+                    *file.branches.entry(key).or_insert(0) += fields[3];
+                },
+                ("DA", None) | ("BRDA", None) => { return Err(bad_record()); },
+                _ => { }
+            }
+        }
+        Ok(coverage)
+    }
+
+    // Lines and branches covered in each file, then in all of them.
+    pub fn summary(&self) -> String {
+        let ratio = |hit: usize, found: usize| {
+            let pct = if found == 0 { 100.0 } else { hit as f64 * 100.0 / found as f64 };
+            format!("{:>11} {:>6.1}%", format!("{}/{}", hit, found), pct)
+        };
+
+        let mut out = "coverage summary\n".to_string();
+        out.push_str(&format!(
+            "{:>11} {:>7} {:>11} {:>7}  {}\n",
+            "lines", "lines%", "branches", "branch%", "file"
+        ));
+        let (mut lines, mut lines_hit, mut branches, mut branches_hit) = (0, 0, 0, 0);
+        for (name, file) in self.files.iter() {
+            out.push_str(&format!(
+                "{} {}  {}\n",
+                ratio(file.lines_hit(), file.lines.len()),
+                ratio(file.branches_hit(), file.branches.len()),
+                name
+            ));
+            lines += file.lines.len();
+            lines_hit += file.lines_hit();
+            branches += file.branches.len();
+            branches_hit += file.branches_hit();
+        }
+        out.push_str(&format!(
+            "{} {}  {}\n",
+            ratio(lines_hit, lines),
+            ratio(branches_hit, branches),
+            "total"
+        ));
+        out
+    }
+}
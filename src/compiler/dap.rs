@@ -0,0 +1,610 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fs;
+use std::io::BufRead;
+use std::path::Path;
+use std::rc::Rc;
+
+use clvm_rs::allocator::Allocator;
+
+use serde_json::{json, Value};
+
+use crate::classic::clvm_tools::cmds::hex_to_modern_sexp;
+use crate::classic::clvm_tools::stages::stage_0::DefaultProgramRunner;
+
+use crate::compiler::cldb::{
+    step_location,
+    Breakpoint,
+    CldbDebugger,
+    CldbRun,
+    CldbRunEnv,
+    FunctionMap
+};
+use crate::compiler::clvm::{start_step, RunStep};
+use crate::compiler::compiler::{compile_file, DefaultCompilerOpts};
+use crate::compiler::comptypes::{CompileErr, CompilerOpts};
+use crate::compiler::prims;
+use crate::compiler::sexp::{parse_sexp, SExp};
+use crate::compiler::srcloc::Srcloc;
+
+/* A Debug Adapter Protocol server over the cldb debugger.  Messages are
+ * framed with a Content-Length header as the protocol specifies, and
+ * DapServer::handle turns one request into the responses and events to send
+ * back, so the server can be driven from stdio or from a scripted client.
+ *
+ * Lines and columns are 1-based, which is the protocol's default.
+ */
+
+// Read one message, returning None at the end of input.
+pub fn read_message(input: &mut dyn BufRead) -> Result<Option<Value>, String> {
+    let mut content_length = None;
+
+    loop {
+        let mut header = String::new();
+        let got = input.read_line(&mut header).map_err(|e| e.to_string())?;
+        if got == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim();
+        if header.len() == 0 {
+            if content_length.is_some() {
+                break;
+            }
+            continue;
+        }
+
+        match header.split_once(':') {
+            Some((name, value)) => {
+                if name.trim().eq_ignore_ascii_case("content-length") {
+                    content_length = Some(value.trim().parse::<usize>().map_err(|_| {
+                        format!("bad content length {}", value.trim())
+                    })?);
+                }
+            },
+            _ => {
+                return Err(format!("bad header {}", header));
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length.unwrap_or_else(|| 0)];
+    input.read_exact(&mut body).map_err(|e| e.to_string())?;
+    serde_json::from_slice(&body).map(|v| Some(v)).map_err(|e| e.to_string())
+}
+
+pub fn encode_message(msg: &Value) -> Vec<u8> {
+    let body = msg.to_string();
+    let mut result = format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes().to_vec();
+    result.append(&mut body.as_bytes().to_vec());
+    result
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum StepKind {
+    Into,
+    Over,
+    Out
+}
+
+struct DapSession {
+    debugger: CldbDebugger,
+    stop_on_entry: bool,
+    // Every file named by a source location in the program.
+    known_files: Vec<String>,
+    source_breakpoints: HashMap<String, Vec<usize>>,
+    function_breakpoints: Vec<usize>
+}
+
+pub struct DapServer {
+    allocator: Allocator,
+    seq: i64,
+    session: Option<DapSession>,
+    finished: bool
+}
+
+fn collect_files(files: &mut Vec<String>, sexp: &SExp) {
+    let file = sexp.loc().file.to_string();
+    if !files.contains(&file) {
+        files.push(file);
+    }
+
+    match sexp {
+        SExp::Cons(_, a, b) => {
+            collect_files(files, a.borrow());
+            collect_files(files, b.borrow());
+        },
+        _ => { }
+    }
+}
+
+fn same_file(a: &String, b: &String) -> bool {
+    if a == b {
+        return true;
+    }
+
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(ca), Ok(cb)) => ca == cb,
+        _ => false
+    }
+}
+
+fn source_json(file: &String) -> Value {
+    let name = Path::new(file).file_name().map(|n| n.to_string_lossy().to_string()).
+        unwrap_or_else(|| file.clone());
+    match fs::canonicalize(file) {
+        Ok(p) => json!({ "name": name, "path": p.to_string_lossy() }),
+        _ => json!({ "name": name })
+    }
+}
+
+fn arrived_line(step: &RunStep) -> Option<(String, usize)> {
+    match step {
+        RunStep::Step(_, _, _) => step_location(step).map(|l| (l.file.to_string(), l.line)),
+        _ => None
+    }
+}
+
+fn string_arg(args: &Value, name: &str) -> Option<String> {
+    args.get(name).and_then(|v| v.as_str()).map(|s| s.to_string())
+}
+
+fn load_program(
+    allocator: &mut Allocator,
+    args: &Value
+) -> Result<(Rc<SExp>, Rc<SExp>, FunctionMap, Vec<String>), String> {
+    let program_file = string_arg(args, "program").ok_or_else(|| {
+        "launch needs a program".to_string()
+    })?;
+    let content = fs::read_to_string(&program_file).map_err(|e| {
+        format!("can't read {}: {}", program_file, e)
+    })?;
+    let program_args = string_arg(args, "args");
+    let program_loc = Srcloc::start(&program_file);
+    let args_loc = Srcloc::start(&"*args*".to_string());
+
+    let is_hex = args.get("hex").and_then(|h| h.as_bool()).
+        unwrap_or_else(|| program_file.ends_with(".hex"));
+
+    if is_hex {
+        // Compiled programs get their locations from a symbol table.
+        let symbol_table: HashMap<String, String> = match string_arg(args, "symbols") {
+            Some(f) => {
+                let symbols = fs::read_to_string(&f).map_err(|e| {
+                    format!("can't read {}: {}", f, e)
+                })?;
+                serde_json::from_str(&symbols).map_err(|e| {
+                    format!("bad symbol table {}: {}", f, e)
+                })?
+            },
+            None => HashMap::new()
+        };
+
+        let program = hex_to_modern_sexp(
+            allocator,
+            &symbol_table,
+            program_loc,
+            &content.trim().to_string()
+        ).map_err(|e| e.to_string())?;
+        let program_args = hex_to_modern_sexp(
+            allocator,
+            &HashMap::new(),
+            args_loc,
+            &program_args.unwrap_or_else(|| "80".to_string())
+        ).map_err(|e| e.to_string())?;
+        let program_lines = content.lines().map(|x| x.to_string()).collect();
+        return Ok((program, program_args, FunctionMap::empty(), program_lines));
+    }
+
+    let search_paths: Vec<String> = args.get("includePaths").and_then(|p| p.as_array()).
+        map(|p| p.iter().filter_map(|x| x.as_str().map(|s| s.to_string())).collect()).
+        unwrap_or_else(|| Vec::new());
+    let runner = Rc::new(DefaultProgramRunner::new());
+    let opts = Rc::new(DefaultCompilerOpts::new(&program_file)).set_search_paths(&search_paths);
+    let show_err = |e: CompileErr| format!("{}: {}", e.0.to_string(), e.1);
+
+    let program = compile_file(allocator, runner, opts.clone(), &content).map_err(show_err)?;
+    let functions = FunctionMap::from_source(opts, &content).map_err(show_err)?;
+    let program_args = program_args.unwrap_or_else(|| "()".to_string());
+    let parsed_args = parse_sexp(args_loc.clone(), &program_args).map_err(|e| {
+        format!("{}: {}", e.0.to_string(), e.1)
+    })?;
+    let program_args = parsed_args.first().map(|a| a.clone()).
+        unwrap_or_else(|| Rc::new(SExp::Nil(args_loc)));
+    let program_lines = content.lines().map(|x| x.to_string()).collect();
+    Ok((Rc::new(program), program_args, functions, program_lines))
+}
+
+impl DapServer {
+    pub fn new() -> Self {
+        DapServer {
+            allocator: Allocator::new(),
+            seq: 0,
+            session: None,
+            finished: false
+        }
+    }
+
+    // True once the client has disconnected.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn next_seq(&mut self) -> i64 {
+        self.seq += 1;
+        self.seq
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> Value {
+        json!({
+            "seq": self.next_seq(),
+            "type": "event",
+            "event": event,
+            "body": body
+        })
+    }
+
+    fn response(&mut self, request: &Value, body: Value) -> Value {
+        json!({
+            "seq": self.next_seq(),
+            "type": "response",
+            "request_seq": request.get("seq").cloned().unwrap_or_else(|| json!(0)),
+            "command": request.get("command").cloned().unwrap_or_else(|| json!("")),
+            "success": true,
+            "body": body
+        })
+    }
+
+    fn error_response(&mut self, request: &Value, message: String) -> Value {
+        json!({
+            "seq": self.next_seq(),
+            "type": "response",
+            "request_seq": request.get("seq").cloned().unwrap_or_else(|| json!(0)),
+            "command": request.get("command").cloned().unwrap_or_else(|| json!("")),
+            "success": false,
+            "message": message
+        })
+    }
+
+    // Handle one request, returning the messages to send in reply.
+    pub fn handle(&mut self, request: &Value) -> Vec<Value> {
+        let command = request.get("command").and_then(|c| c.as_str()).unwrap_or_else(|| "");
+        let args = request.get("arguments").cloned().unwrap_or_else(|| json!({}));
+
+        if command != "initialize" && command != "launch" && command != "disconnect" &&
+            command != "terminate" && self.session.is_none() {
+            return vec!(self.error_response(request, "no program has been launched".to_string()));
+        }
+
+        match command {
+            "initialize" => {
+                let capabilities = json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsFunctionBreakpoints": true,
+                    "supportsEvaluateForHovers": true,
+                    "supportsTerminateRequest": true
+                });
+                vec!(self.response(request, capabilities))
+            },
+            "launch" => self.launch(request, &args),
+            "setBreakpoints" => self.set_breakpoints(request, &args),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(request, &args),
+            "setExceptionBreakpoints" => {
+                vec!(self.response(request, json!({ "breakpoints": [] })))
+            },
+            "configurationDone" => {
+                let mut result = vec!(self.response(request, json!({})));
+                let stop_on_entry = self.session.as_ref().map(|s| s.stop_on_entry).unwrap_or_else(|| false);
+                if stop_on_entry {
+                    let stopped = self.event("stopped", json!({
+                        "reason": "entry",
+                        "threadId": 1,
+                        "allThreadsStopped": true
+                    }));
+                    result.push(stopped);
+                } else {
+                    result.append(&mut self.resume(None));
+                }
+                result
+            },
+            "threads" => {
+                vec!(self.response(request, json!({ "threads": [{ "id": 1, "name": "main" }] })))
+            },
+            "stackTrace" => self.stack_trace(request),
+            "scopes" => {
+                let frame_id = args.get("frameId").and_then(|f| f.as_i64()).unwrap_or_else(|| 0);
+                vec!(self.response(request, json!({
+                    "scopes": [{
+                        "name": "Arguments",
+                        "variablesReference": frame_id,
+                        "expensive": false
+                    }]
+                })))
+            },
+            "variables" => self.variables(request, &args),
+            "evaluate" => self.evaluate(request, &args),
+            "continue" => {
+                let mut result = vec!(self.response(request, json!({ "allThreadsContinued": true })));
+                result.append(&mut self.resume(None));
+                result
+            },
+            "next" | "stepIn" | "stepOut" => {
+                let kind = match command {
+                    "next" => StepKind::Over,
+                    "stepIn" => StepKind::Into,
+                    _ => StepKind::Out
+                };
+                let mut result = vec!(self.response(request, json!({})));
+                result.append(&mut self.resume(Some(kind)));
+                result
+            },
+            "pause" => {
+                // Execution only proceeds in response to requests, so the
+                // program is already paused.
+                vec!(self.response(request, json!({})))
+            },
+            "disconnect" | "terminate" => {
+                self.finished = true;
+                self.session = None;
+                vec!(self.response(request, json!({})))
+            },
+            _ => {
+                vec!(self.error_response(request, format!("unsupported command {}", command)))
+            }
+        }
+    }
+
+    fn launch(&mut self, request: &Value, args: &Value) -> Vec<Value> {
+        let (program, program_args, functions, program_lines) =
+            match load_program(&mut self.allocator, args) {
+                Ok(loaded) => loaded,
+                Err(e) => { return vec!(self.error_response(request, e)); }
+            };
+
+        let mut known_files = Vec::new();
+        collect_files(&mut known_files, program.borrow());
+
+        let runner = Rc::new(DefaultProgramRunner::new());
+        let step = start_step(program, program_args);
+        let cldbenv = CldbRunEnv::new(string_arg(args, "program"), program_lines);
+        let run = CldbRun::new(runner, prims::prim_map(), Box::new(cldbenv), step);
+
+        self.session = Some(DapSession {
+            debugger: CldbDebugger::new(run, functions),
+            stop_on_entry: args.get("stopOnEntry").and_then(|s| s.as_bool()).unwrap_or_else(|| false),
+            known_files: known_files,
+            source_breakpoints: HashMap::new(),
+            function_breakpoints: Vec::new()
+        });
+
+        let response = self.response(request, json!({}));
+        let initialized = self.event("initialized", json!({}));
+        vec!(response, initialized)
+    }
+
+    fn set_breakpoints(&mut self, request: &Value, args: &Value) -> Vec<Value> {
+        let path = args.get("source").and_then(|s| s.get("path")).and_then(|p| p.as_str()).
+            map(|p| p.to_string()).unwrap_or_else(|| "".to_string());
+        let lines: Vec<usize> = args.get("breakpoints").and_then(|b| b.as_array()).
+            map(|b| b.iter().filter_map(|bp| bp.get("line").and_then(|l| l.as_u64())).
+                 map(|l| l as usize).collect()).
+            unwrap_or_else(|| Vec::new());
+
+        let session = self.session.as_mut().unwrap();
+
+        // Breakpoints match the file name the compiler saw.
+        let file = session.known_files.iter().find(|f| same_file(f, &path)).
+            cloned().unwrap_or_else(|| path.clone());
+        let verified = session.known_files.contains(&file);
+
+        for id in session.source_breakpoints.remove(&path).unwrap_or_else(|| Vec::new()) {
+            session.debugger.remove_breakpoint(id);
+        }
+
+        let mut ids = Vec::new();
+        let mut breakpoints = Vec::new();
+        for line in lines.iter() {
+            let mut loc = Srcloc::start(&file);
+            loc.line = *line;
+            let id = session.debugger.add_breakpoint(Breakpoint::Location(loc));
+            ids.push(id);
+            breakpoints.push(json!({ "id": id, "verified": verified, "line": line }));
+        }
+        session.source_breakpoints.insert(path, ids);
+
+        vec!(self.response(request, json!({ "breakpoints": breakpoints })))
+    }
+
+    fn set_function_breakpoints(&mut self, request: &Value, args: &Value) -> Vec<Value> {
+        let names: Vec<String> = args.get("breakpoints").and_then(|b| b.as_array()).
+            map(|b| b.iter().filter_map(|bp| string_arg(bp, "name")).collect()).
+            unwrap_or_else(|| Vec::new());
+
+        let session = self.session.as_mut().unwrap();
+        for id in session.function_breakpoints.drain(..) {
+            session.debugger.remove_breakpoint(id);
+        }
+
+        let mut breakpoints = Vec::new();
+        for name in names.iter() {
+            if session.debugger.functions().has_function(name) {
+                let id = session.debugger.add_breakpoint(Breakpoint::FunctionEntry(name.clone()));
+                session.function_breakpoints.push(id);
+                breakpoints.push(json!({ "id": id, "verified": true }));
+            } else {
+                breakpoints.push(json!({
+                    "verified": false,
+                    "message": format!("no function named {}", name)
+                }));
+            }
+        }
+
+        vec!(self.response(request, json!({ "breakpoints": breakpoints })))
+    }
+
+    // Frames are numbered from 1, innermost first.
+    fn stack_trace(&mut self, request: &Value) -> Vec<Value> {
+        let session = self.session.as_ref().unwrap();
+        let frames: Vec<Value> = session.debugger.call_stack().iter().enumerate().map(|(i, f)| {
+            json!({
+                "id": i + 1,
+                "name": f.name,
+                "source": source_json(&f.loc.file),
+                "line": f.loc.line,
+                "column": f.loc.col
+            })
+        }).collect();
+        let total = frames.len();
+        vec!(self.response(request, json!({ "stackFrames": frames, "totalFrames": total })))
+    }
+
+    fn variables(&mut self, request: &Value, args: &Value) -> Vec<Value> {
+        let reference = args.get("variablesReference").and_then(|v| v.as_u64()).unwrap_or_else(|| 0) as usize;
+        let session = self.session.as_ref().unwrap();
+        let frames = session.debugger.call_stack();
+
+        let mut variables = Vec::new();
+        if reference > 0 && reference <= frames.len() {
+            for (name, value) in session.debugger.frame_arguments(&mut self.allocator, &frames[reference - 1]) {
+                let shown = match value {
+                    Ok(v) => v.to_string(),
+                    Err(e) => format!("error: {}", e.to_string())
+                };
+                variables.push(json!({ "name": name, "value": shown, "variablesReference": 0 }));
+            }
+        }
+
+        vec!(self.response(request, json!({ "variables": variables })))
+    }
+
+    fn evaluate(&mut self, request: &Value, args: &Value) -> Vec<Value> {
+        let text = string_arg(args, "expression").unwrap_or_else(|| "".to_string());
+        let frame_id = args.get("frameId").and_then(|f| f.as_u64()).unwrap_or_else(|| 1) as usize;
+
+        let parsed = match parse_sexp(Srcloc::start(&"*evaluate*".to_string()), &text) {
+            Ok(p) => p,
+            Err(e) => { return vec!(self.error_response(request, e.1)); }
+        };
+        if parsed.len() != 1 {
+            return vec!(self.error_response(request, format!("expected one expression in {}", text)));
+        }
+
+        let session = self.session.as_ref().unwrap();
+        let frames = session.debugger.call_stack();
+        if frame_id == 0 || frame_id > frames.len() {
+            return vec!(self.error_response(request, "the program has finished".to_string()));
+        }
+
+        let frame = &frames[frame_id - 1];
+        let result = session.debugger.evaluate_in(
+            &mut self.allocator,
+            &frame.loc,
+            frame.context.clone(),
+            parsed[0].clone()
+        );
+        match result {
+            Ok(v) => vec!(self.response(request, json!({ "result": v.to_string(), "variablesReference": 0 }))),
+            Err(e) => vec!(self.error_response(request, e.to_string()))
+        }
+    }
+
+    /* Run until a breakpoint, the end of the program, or, when stepping,
+     * until the step is complete.  Returns the events describing where
+     * execution stopped.
+     */
+    fn resume(&mut self, stepping: Option<StepKind>) -> Vec<Value> {
+        let mut rows = Vec::new();
+        let mut stop = None;
+
+        {
+            let session = self.session.as_mut().unwrap();
+            let debugger = &mut session.debugger;
+            // Only stepping needs to know where it started.
+            let (start_depth, start_line) = match stepping {
+                Some(_) => (
+                    debugger.call_depth(),
+                    step_location(&debugger.current_step()).map(|l| (l.file.to_string(), l.line))
+                ),
+                None => (0, None)
+            };
+
+            while !debugger.is_ended() {
+                let (row, hit) = debugger.step(&mut self.allocator);
+                match row {
+                    Some(r) => { rows.push(r); },
+                    _ => { }
+                }
+
+                if hit.is_some() {
+                    stop = Some(("breakpoint", hit));
+                    break;
+                }
+
+                let kind = match stepping {
+                    Some(k) => k,
+                    _ => { continue; }
+                };
+
+                // Any step returning to a caller finishes stepping, while
+                // arriving at a new line only counts for an expression.
+                let current = debugger.current_step();
+                let depth = debugger.call_depth();
+                let new_line = arrived_line(&current).map(|l| Some(l) != start_line).
+                    unwrap_or_else(|| false);
+                let done = depth < start_depth || match kind {
+                    StepKind::Into => new_line,
+                    StepKind::Over => new_line && depth == start_depth,
+                    StepKind::Out => false
+                };
+                if done {
+                    stop = Some(("step", None));
+                    break;
+                }
+            }
+        }
+
+        let mut result = Vec::new();
+        let ended = self.session.as_ref().map(|s| s.debugger.is_ended()).unwrap_or_else(|| true);
+        if ended {
+            let mut exit_code = 0;
+            for r in rows.iter() {
+                let (category, output) = match (r.get("Final"), r.get("Failure"), r.get("Throw")) {
+                    (Some(f), _, _) => ("stdout", format!("Final: {}\n", f)),
+                    (_, Some(f), _) => ("stderr", format!("Failure: {}\n", f)),
+                    (_, _, Some(t)) => ("stderr", format!("Throw: {}\n", t)),
+                    _ => { continue; }
+                };
+                if category == "stderr" {
+                    exit_code = 1;
+                }
+                let output_event = self.event("output", json!({ "category": category, "output": output }));
+                result.push(output_event);
+            }
+
+            let exited = self.event("exited", json!({ "exitCode": exit_code }));
+            let terminated = self.event("terminated", json!({}));
+            result.push(exited);
+            result.push(terminated);
+            return result;
+        }
+
+        match stop {
+            Some((reason, hit)) => {
+                let mut body = json!({
+                    "reason": reason,
+                    "threadId": 1,
+                    "allThreadsStopped": true
+                });
+                match hit {
+                    Some(id) => { body["hitBreakpointIds"] = json!([id]); },
+                    _ => { }
+                }
+                let stopped = self.event("stopped", body);
+                result.push(stopped);
+            },
+            _ => { }
+        }
+
+        result
+    }
+}
@@ -0,0 +1,271 @@
+use crate::compiler::comptypes::CompileErr;
+use crate::compiler::sexp::{
+    CstElement,
+    CstNode,
+    Trivia,
+    parse_sexp_lossless
+};
+use crate::compiler::srcloc::Srcloc;
+
+/* A formatter for chialisp and clvm source.
+ *
+ * Source is read with parse_sexp_lossless, so a file the compiler can't read
+ * is reported with the compiler's error, and the spelling of every token is
+ * kept along with comments and blank lines.  It's then laid out again:
+ *
+ * - Lists that fit in the line width are printed on one line.
+ * - mod, defun, defun-inline, defmacro, defconstant, let and lambda keep
+ *   their name and arguments on the first line and indent the body by 2,
+ *   and multiline mod and helper forms close on a line of their own.
+ * - Other calls, including if, align their arguments under the first.
+ * - Comments stay on their own line or at the end of the line they ended,
+ *   and runs of blank lines are kept as one.
+ */
+
+const INDENT: usize = 2;
+pub const DEFAULT_WIDTH: usize = 80;
+
+#[derive(Clone, Debug)]
+enum FormatKind {
+    Token(String),
+    List(Vec<FormatItem>),
+    Dot,
+    Comment(String)
+}
+
+#[derive(Clone, Debug)]
+struct FormatItem {
+    kind: FormatKind,
+    blank_before: bool,
+    comment_after: Option<String>
+}
+
+// Turns CST trivia into comment items, attaching a comment to the item
+// before it when it's on the same line.
+fn add_trivia(items: &mut Vec<FormatItem>, trivia: &Vec<Trivia>) -> bool {
+    let mut newlines = 0;
+    for t in trivia.iter() {
+        match t {
+            Trivia::Whitespace(_,_) => { newlines += t.newlines(); },
+            Trivia::Comment(_,c) => {
+                let text = String::from_utf8_lossy(c).trim_end().to_string();
+                match items.last_mut() {
+                    Some(last) => {
+                        if newlines == 0 && last.comment_after.is_none() {
+                            last.comment_after = Some(text);
+                            continue;
+                        }
+                    },
+                    _ => { }
+                }
+
+                let blank_before = items.len() > 0 && newlines > 1;
+                items.push(FormatItem { kind: FormatKind::Comment(text), blank_before: blank_before, comment_after: None });
+                newlines = 0;
+            }
+        }
+    }
+
+    items.len() > 0 && newlines > 1
+}
+
+fn format_items(elements: &Vec<CstElement>, closing: &Vec<Trivia>) -> Vec<FormatItem> {
+    let mut items = Vec::new();
+    for e in elements.iter() {
+        let blank_before = add_trivia(&mut items, &e.trivia);
+        let kind = match &e.node {
+            CstNode::Atom(_,t) => FormatKind::Token(String::from_utf8_lossy(t).to_string()),
+            CstNode::QuotedString(_,_,t) => FormatKind::Token(String::from_utf8_lossy(t).to_string()),
+            CstNode::Dot(_) => FormatKind::Dot,
+            CstNode::List(_,elts,list_closing) => FormatKind::List(format_items(elts, list_closing))
+        };
+        items.push(FormatItem { kind: kind, blank_before: blank_before, comment_after: None });
+    }
+    add_trivia(&mut items, closing);
+    items
+}
+
+// How many arguments stay on the first line of a form with a body.
+fn header_count(head: &str) -> Option<usize> {
+    match head {
+        "mod" | "defconstant" | "let" | "let*" | "lambda" => Some(1),
+        "defun" | "defun-inline" | "defmacro" => Some(2),
+        _ => None
+    }
+}
+
+fn closes_on_own_line(head: &str) -> bool {
+    match head {
+        "mod" | "defun" | "defun-inline" | "defmacro" | "defconstant" => true,
+        _ => false
+    }
+}
+
+fn current_col(out: &String) -> usize {
+    let line_start = out.rfind('\n').map(|i| i + 1).unwrap_or_else(|| 0);
+    out[line_start..].chars().count()
+}
+
+fn newline(out: &mut String, col: usize) {
+    out.push('\n');
+    out.push_str(&" ".repeat(col));
+}
+
+struct Layout {
+    width: usize
+}
+
+impl Layout {
+    // The item on one line, if it has no comments that force line breaks.
+    fn flat(&self, item: &FormatItem) -> Option<String> {
+        if item.comment_after.is_some() {
+            return None;
+        }
+
+        match &item.kind {
+            FormatKind::Token(t) => Some(t.clone()),
+            FormatKind::Dot => Some(".".to_string()),
+            FormatKind::Comment(_) => None,
+            FormatKind::List(items) => {
+                let mut parts = Vec::new();
+                for i in items.iter() {
+                    parts.push(self.flat(i)?);
+                }
+                Some(format!("({})", parts.join(" ")))
+            }
+        }
+    }
+
+    fn item(&self, item: &FormatItem, toplevel: bool, out: &mut String) {
+        let col = current_col(out);
+        match &item.kind {
+            FormatKind::Token(t) => { out.push_str(t); },
+            FormatKind::Dot => { out.push('.'); },
+            FormatKind::Comment(c) => { out.push_str(c); },
+            FormatKind::List(items) => {
+                let flat = self.flat(&FormatItem {
+                    kind: item.kind.clone(),
+                    blank_before: false,
+                    comment_after: None
+                });
+                match flat {
+                    Some(f) if col + f.chars().count() <= self.width => { out.push_str(&f); },
+                    _ => { self.list(items, col, toplevel, out); }
+                }
+            }
+        }
+
+        match &item.comment_after {
+            Some(c) => {
+                out.push(' ');
+                out.push_str(c);
+            },
+            _ => { }
+        }
+    }
+
+    fn list(&self, items: &Vec<FormatItem>, col: usize, toplevel: bool, out: &mut String) {
+        out.push('(');
+        if items.len() == 0 {
+            out.push(')');
+            return;
+        }
+
+        let head = match &items[0].kind {
+            FormatKind::Token(t) => Some(t.clone()),
+            _ => None
+        };
+
+        // A file of forms, as in an include file, gets each on its own line.
+        let list_of_forms = toplevel && match &items[0].kind {
+            FormatKind::List(_) | FormatKind::Comment(_) => true,
+            _ => false
+        };
+
+        let mut next = 0;
+        let mut body_col = col + INDENT;
+        let mut own_line_close = list_of_forms;
+
+        if !list_of_forms {
+            self.item(&items[0], false, out);
+            next = 1;
+
+            let on_first_line = match head.as_ref() {
+                Some(h) => {
+                    own_line_close = closes_on_own_line(h);
+                    header_count(h).unwrap_or_else(|| 1)
+                },
+                None => {
+                    body_col = col + 1;
+                    0
+                }
+            };
+
+            let aligned = head.as_ref().map(|h| header_count(h).is_none()).unwrap_or_else(|| false);
+            while next <= on_first_line && next < items.len() {
+                let fits_on_line = match &items[next].kind {
+                    FormatKind::Comment(_) => false,
+                    _ => items[next - 1].comment_after.is_none() && !items[next].blank_before
+                };
+                if !fits_on_line {
+                    break;
+                }
+
+                out.push(' ');
+                if aligned {
+                    body_col = current_col(out);
+                }
+                self.item(&items[next], false, out);
+                next += 1;
+            }
+        }
+
+        for i in items.iter().skip(next) {
+            if i.blank_before {
+                out.push('\n');
+            }
+            newline(out, body_col);
+            self.item(i, false, out);
+        }
+
+        let ends_in_comment = match items.last() {
+            Some(last) => {
+                match &last.kind {
+                    FormatKind::Comment(_) => true,
+                    _ => last.comment_after.is_some()
+                }
+            },
+            _ => false
+        };
+
+        if own_line_close || ends_in_comment {
+            newline(out, col);
+        }
+        out.push(')');
+    }
+}
+
+/* Format source text, with lines no wider than width where possible.  The
+ * filename is used for error locations.
+ */
+pub fn format_source(filename: &String, text: &String, width: usize) -> Result<String, CompileErr> {
+    let cst = parse_sexp_lossless(Srcloc::start(filename), text).map_err(|e| CompileErr(e.0, e.1))?;
+    let items = format_items(&cst.forms, &cst.trailing);
+    let layout = Layout { width: width };
+
+    let mut out = String::new();
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+            if item.blank_before {
+                out.push('\n');
+            }
+        }
+        layout.item(item, true, &mut out);
+    }
+
+    if out.len() > 0 {
+        out.push('\n');
+    }
+    Ok(out)
+}
@@ -11,8 +11,11 @@ use crate::compiler::comptypes::{
     CompileErr,
     CompileForm,
     CompilerOpts,
+    Diagnostic,
+    FunctionType,
     HelperForm,
     ModAccum,
+    TypeAnnotation,
     list_to_cons
 };
 use crate::compiler::preprocessor::preprocess;
@@ -134,7 +137,7 @@ fn calculate_live_helpers(
     }
 }
 
-fn qq_to_expression(body: Rc<SExp>) -> Result<BodyForm, CompileErr> {
+fn qq_to_expression(body: Rc<SExp>, errors: &mut Vec<CompileErr>) -> Result<BodyForm, CompileErr> {
     let body_copy: &SExp = body.borrow();
 
     match body.borrow() {
@@ -169,14 +172,14 @@ fn qq_to_expression(body: Rc<SExp>) -> Result<BodyForm, CompileErr> {
                                 ));
                             }
 
-                            return compile_bodyform(Rc::new(list[0].clone()));
+                            return compile_bodyform_(Rc::new(list[0].clone()), errors);
                         }
                     },
                     _ => { }
                 }
             }
 
-            return qq_to_expression_list(body.clone());
+            return qq_to_expression_list(body.clone(), errors);
         },
         _ => {
             return Ok(BodyForm::Quoted(body_copy.clone()));
@@ -184,11 +187,11 @@ fn qq_to_expression(body: Rc<SExp>) -> Result<BodyForm, CompileErr> {
     }
 }
 
-fn qq_to_expression_list(body: Rc<SExp>) -> Result<BodyForm, CompileErr> {
+fn qq_to_expression_list(body: Rc<SExp>, errors: &mut Vec<CompileErr>) -> Result<BodyForm, CompileErr> {
     match body.borrow() {
         SExp::Cons(l,f,r) => { m! {
-            f_qq <- qq_to_expression(f.clone());
-            r_qq <- qq_to_expression_list(r.clone());
+            f_qq <- qq_to_expression(f.clone(), errors);
+            r_qq <- qq_to_expression_list(r.clone(), errors);
             Ok(BodyForm::Call(l.clone(), vec!(
                 Rc::new(BodyForm::Value(
                     SExp::Atom(l.clone(), "c".as_bytes().to_vec())
@@ -207,16 +210,26 @@ fn qq_to_expression_list(body: Rc<SExp>) -> Result<BodyForm, CompileErr> {
     }
 }
 
-fn args_to_expression_list(body: Rc<SExp>) -> Result<Vec<Rc<BodyForm>>, CompileErr> {
+// Stands in for a form that failed to compile so the forms after it can
+// still be checked.
+fn recovered(errors: &mut Vec<CompileErr>, loc: Srcloc, r: Result<BodyForm, CompileErr>) -> BodyForm {
+    r.unwrap_or_else(|e| {
+        errors.push(e);
+        BodyForm::Quoted(SExp::Nil(loc))
+    })
+}
+
+fn args_to_expression_list(body: Rc<SExp>, errors: &mut Vec<CompileErr>) -> Result<Vec<Rc<BodyForm>>, CompileErr> {
     if body.nilp() {
         Ok(vec!())
     } else {
         match body.borrow() {
             SExp::Cons(_l, first, rest) => {
                 let mut result_list = Vec::new();
-                let f_compiled = compile_bodyform(first.clone())?;
+                let f_result = compile_bodyform_(first.clone(), errors);
+                let f_compiled = recovered(errors, first.loc(), f_result);
                 result_list.push(Rc::new(f_compiled));
-                let mut args = args_to_expression_list(rest.clone())?;
+                let mut args = args_to_expression_list(rest.clone(), errors)?;
                 result_list.append(&mut args);
                 Ok(result_list)
             },
@@ -225,7 +238,7 @@ fn args_to_expression_list(body: Rc<SExp>) -> Result<Vec<Rc<BodyForm>>, CompileE
     }
 }
 
-fn make_let_bindings(body: Rc<SExp>) -> Result<Vec<Rc<Binding>>, CompileErr> {
+fn make_let_bindings(body: Rc<SExp>, errors: &mut Vec<CompileErr>) -> Result<Vec<Rc<Binding>>, CompileErr> {
     let err = Err(CompileErr(
         body.loc(), "Bad binding tail ".to_string() + &body.to_string()
     ));
@@ -235,9 +248,10 @@ fn make_let_bindings(body: Rc<SExp>) -> Result<Vec<Rc<Binding>>, CompileErr> {
             head.proper_list().map(|x| {
                 match &x[..] {
                     [SExp::Atom(l, name), expr] => {
-                        let compiled_body = compile_bodyform(Rc::new(expr.clone()))?;
+                        let body_result = compile_bodyform_(Rc::new(expr.clone()), errors);
+                        let compiled_body = recovered(errors, expr.loc(), body_result);
                         let mut result = Vec::new();
-                        let mut rest_bindings = make_let_bindings(tl.clone())?;
+                        let mut rest_bindings = make_let_bindings(tl.clone(), errors)?;
                         result.push(Rc::new(Binding {
                             loc: l.clone(),
                             name: name.to_vec(),
@@ -255,11 +269,27 @@ fn make_let_bindings(body: Rc<SExp>) -> Result<Vec<Rc<Binding>>, CompileErr> {
 }
 
 pub fn compile_bodyform(body: Rc<SExp>) -> Result<BodyForm, CompileErr> {
+    let mut errors = Vec::new();
+    let result = compile_bodyform_(body, &mut errors);
+    first_error(errors, result)
+}
+
+// The first error found, recovered from or not.
+fn first_error<T>(errors: Vec<CompileErr>, result: Result<T, CompileErr>) -> Result<T, CompileErr> {
+    match errors.first() {
+        Some(e) => Err(e.clone()),
+        None => result
+    }
+}
+
+// Compiles body, recording errors in arguments and let bindings in errors
+// and carrying on past them.
+fn compile_bodyform_(body: Rc<SExp>, errors: &mut Vec<CompileErr>) -> Result<BodyForm, CompileErr> {
     match body.borrow() {
         SExp::Cons(l, op, tail) => {
-            let application = || {
-                args_to_expression_list(tail.clone()).and_then(|args| {
-                    compile_bodyform(op.clone()).map(|func| {
+            let application = |errors: &mut Vec<CompileErr>| {
+                args_to_expression_list(tail.clone(), errors).and_then(|args| {
+                    compile_bodyform_(op.clone(), errors).map(|func| {
                         let mut result_call = vec!(Rc::new(func));
                         let mut args_clone = args.to_vec();
                         result_call.append(&mut args_clone);
@@ -294,8 +324,8 @@ pub fn compile_bodyform(body: Rc<SExp>) -> Result<BodyForm, CompileErr> {
                                 let bindings = v[0].clone();
                                 let body = v[1].clone();
 
-                                let let_bindings = make_let_bindings(Rc::new(bindings.clone()))?;
-                                let compiled_body = compile_bodyform(Rc::new(body.clone()))?;
+                                let let_bindings = make_let_bindings(Rc::new(bindings.clone()), errors)?;
+                                let compiled_body = compile_bodyform_(Rc::new(body.clone()), errors)?;
                                 return Ok(BodyForm::Let(l.clone(), let_bindings, Rc::new(compiled_body)));
                             } else if *atom_name == "quote".as_bytes().to_vec() {
                                 if v.len() != 1 {
@@ -312,9 +342,9 @@ pub fn compile_bodyform(body: Rc<SExp>) -> Result<BodyForm, CompileErr> {
 
                                 let quote_body = v[0].clone();
 
-                                return qq_to_expression(Rc::new(quote_body.clone()));
+                                return qq_to_expression(Rc::new(quote_body.clone()), errors);
                             } else {
-                                return application();
+                                return application(errors);
                             }
                         },
                         None => {
@@ -323,11 +353,11 @@ pub fn compile_bodyform(body: Rc<SExp>) -> Result<BodyForm, CompileErr> {
                     }
                 },
                 SExp::Integer(il,i) => {
-                    return compile_bodyform(Rc::new(SExp::Cons(
+                    return compile_bodyform_(Rc::new(SExp::Cons(
                         il.clone(),
                         Rc::new(SExp::Atom(il.clone(), u8_from_number(i.clone()))),
                         tail.clone()
-                    )));
+                    )), errors);
                 },
                 SExp::QuotedString(_,_,_) => {
                     let body_copy: &SExp = body.borrow();
@@ -349,8 +379,13 @@ pub fn compile_bodyform(body: Rc<SExp>) -> Result<BodyForm, CompileErr> {
     }
 }
 
-fn compile_defconstant(l: Srcloc, name: Vec<u8>, body: Rc<SExp>) -> Result<HelperForm, CompileErr> {
-    compile_bodyform(body).map(|bf| {
+fn compile_defconstant(
+    l: Srcloc,
+    name: Vec<u8>,
+    body: Rc<SExp>,
+    errors: &mut Vec<CompileErr>
+) -> Result<HelperForm, CompileErr> {
+    compile_bodyform_(body, errors).map(|bf| {
         HelperForm::Defconstant(l, name.to_vec(), Rc::new(bf))
     })
 }
@@ -360,18 +395,160 @@ fn compile_defun(
     inline: bool,
     name: Vec<u8>,
     args: Rc<SExp>,
-    body: Rc<SExp>
+    body: Rc<SExp>,
+    errors: &mut Vec<CompileErr>
 ) -> Result<HelperForm, CompileErr> {
     let mut take_form = body.clone();
     match body.borrow() {
         SExp::Cons(_,f,r) => { take_form = f.clone(); },
         _ => { }
     }
-    compile_bodyform(take_form).map(|bf| {
+    compile_bodyform_(take_form, errors).map(|bf| {
         HelperForm::Defun(l, name, inline, args.clone(), Rc::new(bf))
     })
 }
 
+// The helper in an (override helper) form.
+fn override_target(form: Rc<SExp>) -> Option<Rc<SExp>> {
+    form.proper_list().and_then(|x| {
+        match &x[..] {
+            [SExp::Atom(_,kw), helper] => {
+                if *kw == "override".as_bytes().to_vec() {
+                    Some(Rc::new(helper.clone()))
+                } else {
+                    None
+                }
+            },
+            _ => None
+        }
+    })
+}
+
+fn parse_type_annotation(t: &SExp) -> Result<TypeAnnotation, CompileErr> {
+    let name =
+        match t {
+            SExp::Atom(_,name) => decode_string(name),
+            _ => "".to_string()
+        };
+
+    match name.as_str() {
+        "Any" => Ok(TypeAnnotation::Any),
+        "Atom" => Ok(TypeAnnotation::Atom),
+        "Int" => Ok(TypeAnnotation::Int),
+        "Bytes32" => Ok(TypeAnnotation::Bytes32),
+        "G1" => Ok(TypeAnnotation::G1),
+        "List" => Ok(TypeAnnotation::List),
+        "Pair" => Ok(TypeAnnotation::Pair),
+        _ => Err(CompileErr(t.loc(), format!("unknown type {}", t.to_string())))
+    }
+}
+
+fn is_colon(s: &SExp) -> bool {
+    match s {
+        SExp::Atom(_,a) => *a == ":".as_bytes().to_vec(),
+        _ => false
+    }
+}
+
+// Arguments written (NAME : Type) lose their annotation, leaving NAME.
+// stripped is set if any did.
+fn strip_arg_annotations(
+    args: Rc<SExp>,
+    types: &mut Vec<(Vec<u8>, TypeAnnotation)>,
+    stripped: &mut bool
+) -> Result<Rc<SExp>, CompileErr> {
+    match args.borrow() {
+        SExp::Cons(l,f,r) => {
+            let annotated =
+                f.proper_list().and_then(|lst| {
+                    match (lst.len(), lst.get(0), lst.get(1), lst.get(2)) {
+                        (3, Some(SExp::Atom(nl,name)), Some(colon), Some(t)) if is_colon(colon) => {
+                            Some((nl.clone(), name.clone(), t.clone()))
+                        },
+                        _ => None
+                    }
+                });
+
+            let plain_arg =
+                match annotated {
+                    Some((nl,name,t)) => {
+                        types.push((name.clone(), parse_type_annotation(&t)?));
+                        *stripped = true;
+                        Rc::new(SExp::Atom(nl, name))
+                    },
+                    None => {
+                        let name =
+                            match f.borrow() {
+                                SExp::Atom(_,name) => name.clone(),
+                                _ => Vec::new()
+                            };
+                        types.push((name, TypeAnnotation::Any));
+                        f.clone()
+                    }
+                };
+
+            let rest = strip_arg_annotations(r.clone(), types, stripped)?;
+            Ok(Rc::new(SExp::Cons(l.clone(), plain_arg, rest)))
+        },
+        _ => Ok(args)
+    }
+}
+
+// Take any type annotations off a defun form, giving the form as it would
+// have been written without them and the function type they describe.
+// Forms without annotations are left alone.
+fn strip_type_annotations(
+    form: Rc<SExp>
+) -> Result<(Rc<SExp>, Option<FunctionType>), CompileErr> {
+    let unchanged = Ok((form.clone(), None));
+    let (op, name, args, body) =
+        match form.proper_list() {
+            Some(lst) => {
+                if lst.len() < 3 {
+                    return unchanged;
+                }
+                (lst[0].clone(), lst[1].clone(), lst[2].clone(), lst[3..].to_vec())
+            },
+            None => { return unchanged; }
+        };
+
+    match &op {
+        SExp::Atom(_,op_name) => {
+            if *op_name != "defun".as_bytes().to_vec() &&
+                *op_name != "defun-inline".as_bytes().to_vec() {
+                return unchanged;
+            }
+        },
+        _ => { return unchanged; }
+    }
+
+    let mut arg_types = Vec::new();
+    let mut annotated = false;
+    let plain_args = strip_arg_annotations(Rc::new(args), &mut arg_types, &mut annotated)?;
+
+    // Even an annotation of Any has to come off, or it would be read as a
+    // destructuring pattern or as the body.
+    let (result, plain_body) =
+        if body.len() >= 2 && is_colon(&body[0]) {
+            annotated = true;
+            (parse_type_annotation(&body[1])?, body[2..].to_vec())
+        } else {
+            (TypeAnnotation::Any, body)
+        };
+
+    if !annotated {
+        return unchanged;
+    }
+
+    let l = form.loc();
+    let mut plain_form = vec!(Rc::new(op), Rc::new(name), plain_args);
+    plain_form.append(&mut plain_body.into_iter().map(Rc::new).collect());
+    Ok((
+        Rc::new(list_to_cons(l.clone(), &plain_form)),
+        Some(FunctionType { loc: l, args: arg_types, result: result })
+    ))
+}
+
 fn compile_defmacro(
     opts: Rc<dyn CompilerOpts>,
     l: Srcloc,
@@ -431,7 +608,8 @@ fn match_op_name_4(
 
 fn compile_helperform(
     opts: Rc<dyn CompilerOpts>,
-    body: Rc<SExp>
+    body: Rc<SExp>,
+    errors: &mut Vec<CompileErr>
 ) -> Result<Option<HelperForm>, CompileErr> {
     let l = body.loc();
     let plist = body.proper_list();
@@ -439,13 +617,13 @@ fn compile_helperform(
     match plist.and_then(|pl| match_op_name_4(body.clone(), &pl)) {
         Some((op_name,name,args,body)) => {
             if *op_name == "defconstant".as_bytes().to_vec() {
-                return compile_defconstant(l, name.to_vec(), args.clone()).map(|x| Some(x));
+                return compile_defconstant(l, name.to_vec(), args.clone(), errors).map(|x| Some(x));
             } else if *op_name == "defmacro".as_bytes().to_vec() {
                 return compile_defmacro(opts, l, name.to_vec(), args.clone(), body.clone()).map(|x| Some(x));
             } else if *op_name == "defun".as_bytes().to_vec() {
-                return compile_defun(l, false, name.to_vec(), args.clone(), body.clone()).map(|x| Some(x));
+                return compile_defun(l, false, name.to_vec(), args.clone(), body.clone(), errors).map(|x| Some(x));
             } else if *op_name == "defun-inline".as_bytes().to_vec() {
-                return compile_defun(l, true, name.to_vec(), args.clone(), body.clone()).map(|x| Some(x));
+                return compile_defun(l, true, name.to_vec(), args.clone(), body.clone(), errors).map(|x| Some(x));
             }
         },
         _ => { }
@@ -458,7 +636,8 @@ fn compile_mod_(
     mc: &ModAccum,
     opts: Rc<dyn CompilerOpts>,
     args: Rc<SExp>,
-    content: Rc<SExp>
+    content: Rc<SExp>,
+    errors: &mut Vec<CompileErr>
 ) -> Result<ModAccum, CompileErr> {
     match content.borrow() {
         SExp::Nil(l) => {
@@ -472,40 +651,86 @@ fn compile_mod_(
                             return Err(CompileErr(l.clone(),"too many expressions".to_string()));
                         },
                         _ => {
+                            for h in mc.helpers.iter() {
+                                if mc.overrides.get(&h.name()) == Some(&false) {
+                                    errors.push(CompileErr(
+                                        h.loc(),
+                                        format!(
+                                            "override of {} doesn't replace any other definition",
+                                            decode_string(&h.name())
+                                        )
+                                    ));
+                                }
+                            }
+
+                            let exp_result = compile_bodyform_(body.clone(), errors);
                             return Ok(mc.set_final(&CompileForm {
                                 loc: mc.loc.clone(),
                                 args: args.clone(),
                                 helpers: mc.helpers.clone(),
-                                exp: Rc::new(compile_bodyform(body.clone())?)
+                                exp: Rc::new(recovered(errors, body.loc(), exp_result)),
+                                types: mc.types.clone()
                             }));
                         }
                     }
                 },
                 _ => {
-                    let helper = compile_helperform(opts.clone(), body.clone())?;
-                    match helper {
-                        None => {
+                    // A form that isn't a working helper is reported and
+                    // skipped so the rest of the mod is still checked.
+                    let (is_override, helper_body) =
+                        match override_target(body.clone()) {
+                            Some(target) => (true, target),
+                            None => (false, body.clone())
+                        };
+                    let (plain_body, signature) =
+                        match strip_type_annotations(helper_body) {
+                            Ok(stripped) => stripped,
+                            Err(e) => {
+                                errors.push(e);
+                                return compile_mod_(mc, opts, args.clone(), tail.clone(), errors);
+                            }
+                        };
+                    let helper =
+                        match compile_helperform(opts.clone(), plain_body, errors) {
+                            Ok(Some(form)) => Some(form),
+                            Ok(None) => {
+                                errors.push(CompileErr(
+                                    l.clone(),
+                                    "only the last form can be an exprssion in mod".to_string()
+                                ));
+                                None
+                            },
+                            Err(e) => {
+                                errors.push(e);
+                                None
+                            }
+                        };
+
+                    match (helper, &mc.exp_form) {
+                        (Some(_), Some(_)) => {
                             return Err(CompileErr(
-                                l.clone(),
-                                "only the last form can be an exprssion in mod".to_string()
+                                l.clone(), "too many expressions".to_string()
                             ));
                         },
-                        Some(form) => {
-                            match mc.exp_form {
-                                None => {
-                                    return compile_mod_(
-                                        &mc.add_helper(form),
-                                        opts,
-                                        args.clone(),
-                                        tail.clone()
-                                    );
-                                },
-                                Some(_) => {
-                                    return Err(CompileErr(
-                                        l.clone(), "too many expressions".to_string()
-                                    ));
-                                }
-                            }
+                        (Some(form), None) => {
+                            let with_helper =
+                                match mc.merge_helper(form, signature, is_override) {
+                                    Ok(merged) => merged,
+                                    Err(e) => {
+                                        errors.push(e);
+                                        mc.clone()
+                                    }
+                                };
+                            return compile_mod_(
+                                &with_helper,
+                                opts,
+                                args.clone(),
+                                tail.clone(),
+                                errors
+                            );
+                        },
+                        (None, _) => {
+                            return compile_mod_(mc, opts, args.clone(), tail.clone(), errors);
                         }
                     }
                 }
@@ -522,73 +747,225 @@ fn compile_mod_(
 
 fn frontend_start(
     opts: Rc<dyn CompilerOpts>,
-    pre_forms: Vec<Rc<SExp>>
+    pre_forms: Vec<Rc<SExp>>,
+    errors: &mut Vec<CompileErr>
 ) -> Result<ModAccum, CompileErr> {
     if pre_forms.len() == 0 {
         return Err(CompileErr(
             Srcloc::start(&opts.filename()),
             "empty source file not allowed".to_string()
         ));
-    } else {
-        let finish = || {
-            let loc = pre_forms[0].loc();
-            frontend_start(opts.clone(), vec!(Rc::new(
-                SExp::Cons(
-                    loc.clone(),
-                    Rc::new(SExp::Atom(loc.clone(),"mod".as_bytes().to_vec())),
-                    Rc::new(SExp::Cons(
-                        loc.clone(),
-                        Rc::new(SExp::Nil(loc.clone())),
-                        Rc::new(list_to_cons(loc.clone(), &pre_forms))
-                    ))
-                )
-            )))
-        };
-        let l = pre_forms[0].loc();
-        return pre_forms[0].proper_list().map(|x| {
-            if x.len() < 3 {
-                return finish();
+    }
+
+    let l = pre_forms[0].loc();
+    match pre_forms[0].proper_list() {
+        Some(x) => {
+            if x.len() >= 3 {
+                match &x[0] {
+                    SExp::Atom(_,mod_atom) => {
+                        if pre_forms.len() > 1 {
+                            return Err(CompileErr(
+                                pre_forms[0].loc(), "one toplevel mod form allowed".to_string()
+                            ));
+                        }
+
+                        if *mod_atom == "mod".as_bytes().to_vec() {
+                            let args = Rc::new(x[1].clone());
+                            let body_vec = x.iter().skip(2).
+                                map(|s| Rc::new(s.clone())).collect();
+                            let body = Rc::new(enlist(
+                                pre_forms[0].loc(),
+                                body_vec
+                            ));
+
+                            let ls = preprocess(opts.clone(), body.clone())?;
+                            return compile_mod_(
+                                &ModAccum::new(l.clone()),
+                                opts.clone(),
+                                args.clone(),
+                                Rc::new(list_to_cons(l, &ls)),
+                                errors
+                            );
+                        }
+                    },
+                    _ => { }
+                }
             }
+        },
+        _ => { }
+    }
 
-            match &x[0] {
-                SExp::Atom(_,mod_atom) => {
-                    if pre_forms.len() > 1 {
-                        return Err(CompileErr(
-                            pre_forms[0].loc(), "one toplevel mod form allowed".to_string()
-                        ));
-                    }
+    frontend_start(opts.clone(), vec!(Rc::new(
+        SExp::Cons(
+            l.clone(),
+            Rc::new(SExp::Atom(l.clone(),"mod".as_bytes().to_vec())),
+            Rc::new(SExp::Cons(
+                l.clone(),
+                Rc::new(SExp::Nil(l.clone())),
+                Rc::new(list_to_cons(l.clone(), &pre_forms))
+            ))
+        )
+    )), errors)
+}
 
-                    if *mod_atom == "mod".as_bytes().to_vec() {
-                        let args = Rc::new(x[1].clone());
-                        let body_vec = x.iter().skip(2).
-                            map(|s| Rc::new(s.clone())).collect();
-                        let body = Rc::new(enlist(
-                            pre_forms[0].loc(),
-                            body_vec
-                        ));
-
-                        let ls = preprocess(opts.clone(), body.clone())?;
-                        return compile_mod_(
-                            &ModAccum::new(l.clone()),
-                            opts.clone(),
-                            args.clone(),
-                            Rc::new(list_to_cons(l, &ls))
-                        );
-                    }
-                },
-                _ => { }
+// Names bound by an argument list, with where each is bound.
+fn arg_names(args: Rc<SExp>) -> Vec<(Srcloc, Vec<u8>)> {
+    match args.borrow() {
+        SExp::Atom(l,name) => vec!((l.clone(), name.clone())),
+        SExp::Cons(_,f,r) => {
+            let mut names = arg_names(f.clone());
+            names.append(&mut arg_names(r.clone()));
+            names
+        },
+        _ => vec!()
+    }
+}
+
+// Names starting with _ are expected to go unused.
+fn may_be_unused(name: &Vec<u8>) -> bool {
+    name.len() > 0 && name[0] == '_' as u8
+}
+
+fn warn_unused_args(
+    warnings: &mut Vec<Diagnostic>,
+    owner: &String,
+    args: Rc<SExp>,
+    body: &BodyForm
+) {
+    let used: HashSet<Vec<u8>> = collect_used_names_bodyform(body).into_iter().collect();
+    for (l, name) in arg_names(args) {
+        if !used.contains(&name) && !may_be_unused(&name) {
+            warnings.push(Diagnostic::warning(
+                l, format!("unused argument {} of {}", decode_string(&name), owner)
+            ));
+        }
+    }
+}
+
+fn warn_shadowing_lets(
+    warnings: &mut Vec<Diagnostic>,
+    args: &HashSet<Vec<u8>>,
+    body: &BodyForm
+) {
+    match body {
+        BodyForm::Let(_,bindings,expr) => {
+            for b in bindings.iter() {
+                if args.contains(&b.name) {
+                    warnings.push(Diagnostic::warning(
+                        b.loc.clone(),
+                        format!("let binding {} shadows an argument", decode_string(&b.name))
+                    ));
+                }
+                warn_shadowing_lets(warnings, args, b.body.borrow());
+            }
+            warn_shadowing_lets(warnings, args, expr.borrow());
+        },
+        BodyForm::Call(_,vs) => {
+            for v in vs.iter() {
+                warn_shadowing_lets(warnings, args, v.borrow());
             }
+        },
+        _ => { }
+    }
+}
+
+fn check_function_body(
+    warnings: &mut Vec<Diagnostic>,
+    owner: &String,
+    args: Rc<SExp>,
+    body: &BodyForm
+) {
+    warn_unused_args(warnings, owner, args.clone(), body);
+    let arg_set: HashSet<Vec<u8>> = arg_names(args).into_iter().map(|(_,n)| n).collect();
+    warn_shadowing_lets(warnings, &arg_set, body);
+}
+
+/* Warn about helpers nothing uses, unused mod and defun arguments and let
+ * bindings that shadow arguments.  Only forms in the file being compiled
+ * are checked, not ones from includes.
+ */
+fn compile_warnings(opts: Rc<dyn CompilerOpts>, form: &CompileForm) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+    let in_file = |l: &Srcloc| *l.file == opts.filename();
 
-            return finish();
-        }).unwrap_or_else(finish);
+    let expr_names: HashSet<Vec<u8>> =
+        collect_used_names_bodyform(form.exp.borrow()).into_iter().collect();
+    let mut helper_map = HashMap::new();
+    for h in form.helpers.iter() {
+        helper_map.insert(h.name(), h.clone());
     }
+    let live = calculate_live_helpers(opts.clone(), &HashSet::new(), &expr_names, &helper_map);
+
+    if in_file(&form.args.loc()) {
+        check_function_body(&mut warnings, &"mod".to_string(), form.args.clone(), form.exp.borrow());
+    }
+
+    for h in form.helpers.iter() {
+        if !in_file(&h.loc()) {
+            continue;
+        }
+
+        let kind = match h {
+            HelperForm::Defconstant(_,_,_) => "defconstant",
+            HelperForm::Defmacro(_,_,_,_) => "defmacro",
+            HelperForm::Defun(_,_,true,_,_) => "defun-inline",
+            HelperForm::Defun(_,_,false,_,_) => "defun"
+        };
+        let name = decode_string(&h.name());
+
+        if !live.contains(&h.name()) {
+            warnings.push(Diagnostic::warning(h.loc(), format!("unused {} {}", kind, name)));
+        }
+
+        match h {
+            HelperForm::Defun(_,_,_,args,body) => {
+                let owner = format!("{} {}", kind, name);
+                check_function_body(&mut warnings, &owner, args.clone(), body.borrow());
+            },
+            _ => { }
+        }
+    }
+
+    warnings
 }
 
 pub fn frontend(
     opts: Rc<dyn CompilerOpts>,
     pre_forms: Vec<Rc<SExp>>
 ) -> Result<CompileForm, CompileErr> {
-    let started = frontend_start(opts.clone(), pre_forms)?;
+    let mut errors = Vec::new();
+    let result = frontend_(opts, pre_forms, &mut errors, &mut Vec::new());
+    first_error(errors, result)
+}
+
+// As frontend, but carries on past helpers and expressions that don't
+// compile and returns everything it found, including warnings.  The form is
+// only given when there were no errors.
+pub fn frontend_diagnostics(
+    opts: Rc<dyn CompilerOpts>,
+    pre_forms: Vec<Rc<SExp>>
+) -> (Option<CompileForm>, Vec<Diagnostic>) {
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    match frontend_(opts, pre_forms, &mut errors, &mut warnings) {
+        Ok(form) => {
+            if errors.len() == 0 {
+                return (Some(form), warnings);
+            }
+        },
+        Err(e) => { errors.push(e); }
+    }
+
+    (None, errors.into_iter().map(Diagnostic::error).collect())
+}
+
+fn frontend_(
+    opts: Rc<dyn CompilerOpts>,
+    pre_forms: Vec<Rc<SExp>>,
+    errors: &mut Vec<CompileErr>,
+    warnings: &mut Vec<Diagnostic>
+) -> Result<CompileForm, CompileErr> {
+    let started = frontend_start(opts.clone(), pre_forms, errors)?;
 
     let compiled: Result<CompileForm, CompileErr> =
         match started.exp_form {
@@ -599,7 +976,12 @@ pub fn frontend(
             }
         };
 
-    let our_mod = rename_children_compileform(&compiled?);
+    let compiled = compiled?;
+    if errors.len() == 0 {
+        warnings.append(&mut compile_warnings(opts.clone(), &compiled));
+    }
+
+    let our_mod = rename_children_compileform(&compiled);
 
     let expr_names: HashSet<Vec<u8>> =
         collect_used_names_bodyform(our_mod.exp.borrow()).iter().map(|x| x.to_vec()).collect();
@@ -628,6 +1010,7 @@ pub fn frontend(
         loc: our_mod.loc.clone(),
         args: our_mod.args.clone(),
         helpers: live_helpers,
-        exp: our_mod.exp.clone()
+        exp: our_mod.exp.clone(),
+        types: our_mod.types.clone()
     })
 }
@@ -15,3 +15,17 @@ pub fn gensym(name: Vec<u8>) -> Vec<u8> {
     result_vec.append(&mut number_value.as_bytes().to_vec());
     return result_vec;
 }
+
+// Recover the name a symbol had before gensym renamed it.
+pub fn ungensym(name: &Vec<u8>) -> Vec<u8> {
+    match name.windows(3).rposition(|w| w == "_$_".as_bytes()) {
+        Some(idx) => {
+            let suffix = &name[idx+3..];
+            if suffix.len() > 0 && suffix.iter().all(|c| c.is_ascii_digit()) {
+                return name[..idx].to_vec();
+            }
+            name.clone()
+        },
+        None => name.clone()
+    }
+}
@@ -0,0 +1,491 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use serde_json::{json, Value};
+
+use crate::compiler::compiler::DefaultCompilerOpts;
+use crate::compiler::comptypes::{CompileErr, CompilerOpts, HelperForm, Severity};
+use crate::compiler::frontend::frontend_diagnostics;
+use crate::compiler::gensym::ungensym;
+use crate::compiler::preprocessor::process_include;
+use crate::compiler::sexp::{decode_string, parse_sexp, parse_sexp_recover, SExp};
+use crate::compiler::srcloc::Srcloc;
+
+/* A language server for modern chialisp.  Documents are checked with the
+ * frontend as they change, and helpers found there answer go-to-definition
+ * and hover requests.  LspServer::handle takes one JSON-RPC message and
+ * returns the messages to send back, so the server can be driven from stdio
+ * or from a scripted client.
+ *
+ * Documents are synchronized in full, and positions count characters from 0
+ * as the protocol does.
+ */
+pub struct LspServer {
+    documents: HashMap<String, String>,
+    // Helpers from the last version of each document that compiled.
+    helpers: HashMap<String, Vec<HelperForm>>,
+    include_paths: Vec<String>,
+    finished: bool
+}
+
+struct IncludeForm {
+    name: String,
+    name_loc: Srcloc
+}
+
+pub fn uri_to_path(uri: &str) -> String {
+    let path = uri.strip_prefix("file://").unwrap_or_else(|| uri);
+    let bytes = path.as_bytes();
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            match std::str::from_utf8(&bytes[i+1..i+3]).ok().and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                Some(b) => {
+                    result.push(b);
+                    i += 3;
+                    continue;
+                },
+                _ => { }
+            }
+        }
+        result.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&result).to_string()
+}
+
+pub fn path_to_uri(path: &str) -> String {
+    let mut result = "file://".to_string();
+    for c in path.chars() {
+        match c {
+            ' ' => { result.push_str("%20"); },
+            '%' => { result.push_str("%25"); },
+            '#' => { result.push_str("%23"); },
+            '?' => { result.push_str("%3F"); },
+            _ => { result.push(c); }
+        }
+    }
+    result
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && c != '(' && c != ')' && c != '"' && c != '\''
+}
+
+// The end of the token starting at (line, col), both counted from 0.
+fn token_end(text: &String, line: usize, col: usize) -> usize {
+    let chars: Vec<char> = text.lines().nth(line).map(|l| l.chars().collect()).
+        unwrap_or_else(|| Vec::new());
+    let mut end = col;
+    while end < chars.len() && is_word_char(chars[end]) {
+        end += 1;
+    }
+    if end == col {
+        end + 1
+    } else {
+        end
+    }
+}
+
+fn position(line: usize, character: usize) -> Value {
+    json!({ "line": line, "character": character })
+}
+
+fn range_of(text: &String, loc: &Srcloc) -> Value {
+    let line = if loc.line > 0 { loc.line - 1 } else { 0 };
+    let col = if loc.col > 0 { loc.col - 1 } else { 0 };
+    let end = match loc.until {
+        Some((l, c)) if l > loc.line || (l == loc.line && c > loc.col) => position(l - 1, c - 1),
+        _ => position(line, token_end(text, line, col))
+    };
+    json!({ "start": position(line, col), "end": end })
+}
+
+fn in_loc(text: &String, loc: &Srcloc, line: usize, character: usize) -> bool {
+    if loc.line != line + 1 {
+        return false;
+    }
+
+    let start = if loc.col > 0 { loc.col - 1 } else { 0 };
+    character >= start && character < token_end(text, line, start)
+}
+
+fn word_at(text: &String, line: usize, character: usize) -> Option<String> {
+    let chars: Vec<char> = text.lines().nth(line)?.chars().collect();
+    if character > chars.len() {
+        return None;
+    }
+
+    let mut start = character;
+    while start > 0 && is_word_char(chars[start - 1]) {
+        start -= 1;
+    }
+    let mut end = character;
+    while end < chars.len() && is_word_char(chars[end]) {
+        end += 1;
+    }
+
+    if start == end {
+        None
+    } else {
+        Some(chars[start..end].iter().collect())
+    }
+}
+
+// The include forms at the toplevel of a program.
+fn include_forms(pre_forms: &Vec<Rc<SExp>>) -> Vec<IncludeForm> {
+    let mut body: Vec<SExp> = pre_forms.iter().map(|f| {
+        let form: &SExp = f.borrow();
+        form.clone()
+    }).collect();
+    if pre_forms.len() == 1 {
+        match pre_forms[0].proper_list() {
+            Some(l) => {
+                match l.first() {
+                    Some(SExp::Atom(_, m)) => {
+                        if *m == "mod".as_bytes().to_vec() {
+                            body = l.into_iter().skip(2).collect();
+                        }
+                    },
+                    _ => { }
+                }
+            },
+            _ => { }
+        }
+    }
+
+    body.iter().filter_map(|form| {
+        match form.proper_list().as_ref().map(|l| &l[..]) {
+            Some([SExp::Atom(_, inc), SExp::Atom(l, name)]) |
+            Some([SExp::Atom(_, inc), SExp::QuotedString(l, _, name)]) => {
+                if *inc == "include".as_bytes().to_vec() {
+                    Some(IncludeForm { name: decode_string(name), name_loc: l.clone() })
+                } else {
+                    None
+                }
+            },
+            _ => None
+        }
+    }).collect()
+}
+
+fn strip_gensyms(args: &SExp) -> SExp {
+    match args {
+        SExp::Atom(l, name) => SExp::Atom(l.clone(), ungensym(name)),
+        SExp::Cons(l, a, b) => {
+            SExp::Cons(l.clone(), Rc::new(strip_gensyms(a.borrow())), Rc::new(strip_gensyms(b.borrow())))
+        },
+        _ => args.clone()
+    }
+}
+
+fn describe_helper(h: &HelperForm) -> String {
+    match h {
+        HelperForm::Defconstant(_, name, _) => {
+            format!("(defconstant {})", decode_string(name))
+        },
+        HelperForm::Defmacro(_, name, args, _) => {
+            format!("(defmacro {} {})", decode_string(name), strip_gensyms(args.borrow()).to_string())
+        },
+        HelperForm::Defun(_, name, inline, args, _) => {
+            format!(
+                "({} {} {})",
+                if *inline { "defun-inline" } else { "defun" },
+                decode_string(name),
+                strip_gensyms(args.borrow()).to_string()
+            )
+        }
+    }
+}
+
+fn diagnostic(text: &String, loc: &Srcloc, severity: &Severity, message: String) -> Value {
+    let lsp_severity = match severity {
+        Severity::Error => 1,
+        Severity::Warning => 2
+    };
+    json!({
+        "range": range_of(text, loc),
+        "severity": lsp_severity,
+        "source": "chialisp",
+        "message": message
+    })
+}
+
+impl LspServer {
+    pub fn new() -> Self {
+        LspServer {
+            documents: HashMap::new(),
+            helpers: HashMap::new(),
+            include_paths: Vec::new(),
+            finished: false
+        }
+    }
+
+    // True once the client has sent exit.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // Includes are looked up next to the document, then in the workspace.
+    fn opts_for(&self, path: &String) -> Rc<dyn CompilerOpts> {
+        let mut search_paths = Vec::new();
+        match Path::new(path).parent() {
+            Some(p) => {
+                let dir = p.to_string_lossy().to_string();
+                search_paths.push(if dir.len() == 0 { ".".to_string() } else { dir });
+            },
+            _ => { }
+        }
+        search_paths.append(&mut self.include_paths.clone());
+        Rc::new(DefaultCompilerOpts::new(path)).set_search_paths(&search_paths)
+    }
+
+    fn parse_document(&self, uri: &String) -> Result<Vec<Rc<SExp>>, CompileErr> {
+        let path = uri_to_path(uri);
+        let text = self.documents.get(uri).cloned().unwrap_or_else(|| "".to_string());
+        parse_sexp(Srcloc::start(&path), &text).map_err(|e| CompileErr(e.0, e.1))
+    }
+
+    // The text of a file, preferring an open document's contents.
+    fn file_text(&self, path: &String) -> String {
+        self.documents.get(&path_to_uri(path)).cloned().unwrap_or_else(|| {
+            fs::read_to_string(path).unwrap_or_else(|_| "".to_string())
+        })
+    }
+
+    fn check_document(&mut self, uri: &String) -> Vec<Value> {
+        let path = uri_to_path(uri);
+        let text = self.documents.get(uri).cloned().unwrap_or_else(|| "".to_string());
+        let opts = self.opts_for(&path);
+
+        let (pre_forms, parse_errors) = parse_sexp_recover(Srcloc::start(&path), &text);
+        let mut diagnostics: Vec<Value> = parse_errors.into_iter().map(|(l, m)| {
+            diagnostic(&text, &l, &Severity::Error, m)
+        }).collect();
+        if pre_forms.len() == 0 && diagnostics.len() > 0 {
+            return diagnostics;
+        }
+
+        let includes = include_forms(&pre_forms);
+        let mut include_failed = false;
+        for inc in includes.iter() {
+            match process_include(opts.clone(), &inc.name) {
+                Err(CompileErr(l, m)) => {
+                    let message = if *l.file == path || *l.file == inc.name {
+                        m
+                    } else {
+                        format!("{}: {}", l.to_string(), m)
+                    };
+                    diagnostics.push(diagnostic(&text, &inc.name_loc, &Severity::Error, message));
+                    include_failed = true;
+                },
+                _ => { }
+            }
+        }
+        if include_failed {
+            return diagnostics;
+        }
+
+        let (form, found) = frontend_diagnostics(opts, pre_forms);
+        match form {
+            Some(form) => {
+                self.helpers.insert(uri.clone(), form.helpers.clone());
+            },
+            _ => { }
+        }
+
+        for d in found.iter() {
+            // Problems in included files are shown at the include.
+            if *d.loc.file == path {
+                diagnostics.push(diagnostic(&text, &d.loc, &d.severity, d.message.clone()));
+            } else {
+                let at = includes.iter().find(|i| i.name == *d.loc.file).
+                    map(|i| i.name_loc.clone()).
+                    unwrap_or_else(|| Srcloc::start(&path));
+                let message = format!("{}: {}", d.loc.to_string(), d.message);
+                diagnostics.push(diagnostic(&text, &at, &d.severity, message));
+            }
+        }
+
+        diagnostics
+    }
+
+    fn publish(&self, uri: &String, diagnostics: Vec<Value>) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics }
+        })
+    }
+
+    // The file an include names, if it can be found on disk.
+    fn resolve_include(&self, path: &String, name: &String) -> Option<String> {
+        let opts = self.opts_for(path);
+        opts.read_new_file(path.clone(), name.clone()).ok().
+            map(|(found, _)| found).
+            filter(|found| Path::new(found).is_file())
+    }
+
+    fn include_at(&self, uri: &String, line: usize, character: usize) -> Option<String> {
+        let text = self.documents.get(uri)?;
+        let pre_forms = self.parse_document(uri).ok()?;
+        include_forms(&pre_forms).into_iter().
+            find(|i| in_loc(text, &i.name_loc, line, character)).
+            map(|i| i.name)
+    }
+
+    fn helper_named(&self, uri: &String, name: &String) -> Option<HelperForm> {
+        self.helpers.get(uri)?.iter().find(|h| decode_string(&h.name()) == *name).cloned()
+    }
+
+    fn definition(&self, uri: &String, line: usize, character: usize) -> Value {
+        let path = uri_to_path(uri);
+
+        match self.include_at(uri, line, character) {
+            Some(name) => {
+                return self.resolve_include(&path, &name).map(|found| {
+                    json!({
+                        "uri": path_to_uri(&found),
+                        "range": { "start": position(0, 0), "end": position(0, 0) }
+                    })
+                }).unwrap_or_else(|| Value::Null);
+            },
+            _ => { }
+        }
+
+        let text = self.documents.get(uri).cloned().unwrap_or_else(|| "".to_string());
+        let helper = match word_at(&text, line, character).and_then(|w| self.helper_named(uri, &w)) {
+            Some(h) => h,
+            None => { return Value::Null; }
+        };
+
+        let loc = helper.loc();
+        let file = if *loc.file == path {
+            Some(path.clone())
+        } else {
+            self.resolve_include(&path, &loc.file.to_string())
+        };
+
+        match file {
+            Some(f) => {
+                json!({ "uri": path_to_uri(&f), "range": range_of(&self.file_text(&f), &loc) })
+            },
+            None => Value::Null
+        }
+    }
+
+    fn hover(&self, uri: &String, line: usize, character: usize) -> Value {
+        let path = uri_to_path(uri);
+
+        let contents = match self.include_at(uri, line, character) {
+            Some(name) => {
+                match self.resolve_include(&path, &name) {
+                    Some(found) => format!("include {}", found),
+                    None => { return Value::Null; }
+                }
+            },
+            None => {
+                let text = self.documents.get(uri).cloned().unwrap_or_else(|| "".to_string());
+                match word_at(&text, line, character).and_then(|w| self.helper_named(uri, &w)) {
+                    Some(h) => format!("```chialisp\n{}\n```", describe_helper(&h)),
+                    None => { return Value::Null; }
+                }
+            }
+        };
+
+        json!({ "contents": { "kind": "markdown", "value": contents } })
+    }
+
+    fn respond(&self, id: &Value, result: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "result": result })
+    }
+
+    // Handle one message, returning the messages to send in reply.
+    pub fn handle(&mut self, msg: &Value) -> Vec<Value> {
+        let method = msg.get("method").and_then(|m| m.as_str()).unwrap_or_else(|| "").to_string();
+        let params = msg.get("params").cloned().unwrap_or_else(|| json!({}));
+        let uri = params.get("textDocument").and_then(|d| d.get("uri")).and_then(|u| u.as_str()).
+            map(|u| u.to_string()).unwrap_or_else(|| "".to_string());
+        let line = params.get("position").and_then(|p| p.get("line")).and_then(|l| l.as_u64()).
+            unwrap_or_else(|| 0) as usize;
+        let character = params.get("position").and_then(|p| p.get("character")).and_then(|c| c.as_u64()).
+            unwrap_or_else(|| 0) as usize;
+
+        let id = match msg.get("id") {
+            Some(id) => id.clone(),
+            None => {
+                // Notifications get no response.
+                match method.as_str() {
+                    "textDocument/didOpen" => {
+                        let text = params["textDocument"]["text"].as_str().unwrap_or_else(|| "").to_string();
+                        self.documents.insert(uri.clone(), text);
+                    },
+                    "textDocument/didChange" => {
+                        let text = params["contentChanges"].as_array().and_then(|c| c.last()).
+                            and_then(|c| c["text"].as_str()).map(|t| t.to_string());
+                        match text {
+                            Some(t) => { self.documents.insert(uri.clone(), t); },
+                            _ => { }
+                        }
+                    },
+                    "textDocument/didSave" => { },
+                    "textDocument/didClose" => {
+                        self.documents.remove(&uri);
+                        self.helpers.remove(&uri);
+                        return vec!(self.publish(&uri, Vec::new()));
+                    },
+                    "exit" => {
+                        self.finished = true;
+                        return Vec::new();
+                    },
+                    _ => { return Vec::new(); }
+                }
+
+                let diagnostics = self.check_document(&uri);
+                return vec!(self.publish(&uri, diagnostics));
+            }
+        };
+
+        match method.as_str() {
+            "initialize" => {
+                let root = params.get("rootUri").and_then(|r| r.as_str()).map(|r| uri_to_path(r)).
+                    or_else(|| params.get("rootPath").and_then(|r| r.as_str()).map(|r| r.to_string()));
+                match root {
+                    Some(r) => { self.include_paths.push(r); },
+                    _ => { }
+                }
+                match params.get("initializationOptions").and_then(|o| o.get("includePaths")).and_then(|p| p.as_array()) {
+                    Some(paths) => {
+                        for p in paths.iter().filter_map(|p| p.as_str()) {
+                            self.include_paths.push(p.to_string());
+                        }
+                    },
+                    _ => { }
+                }
+
+                vec!(self.respond(&id, json!({
+                    "capabilities": {
+                        "textDocumentSync": 1,
+                        "definitionProvider": true,
+                        "hoverProvider": true
+                    },
+                    "serverInfo": { "name": "chialisp-lsp" }
+                })))
+            },
+            "textDocument/definition" => vec!(self.respond(&id, self.definition(&uri, line, character))),
+            "textDocument/hover" => vec!(self.respond(&id, self.hover(&uri, line, character))),
+            "shutdown" => vec!(self.respond(&id, Value::Null)),
+            _ => {
+                vec!(json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": -32601, "message": format!("unsupported method {}", method) }
+                }))
+            }
+        }
+    }
+}
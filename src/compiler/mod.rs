@@ -1,15 +1,24 @@
+pub mod cldb;
+pub mod cldb_repl;
+pub mod cltest;
 pub mod clvm;
 mod codegen;
 pub mod compiler;
 pub mod comptypes;
+pub mod coverage;
+pub mod dap;
 pub mod debug;
-mod gensym;
+pub mod format;
+pub mod gensym;
 mod inline;
+pub mod lsp;
 pub mod prims;
 mod optimize;
 pub mod runtypes;
 pub mod sexp;
+pub mod sourcemap;
 pub mod srcloc;
+mod typecheck;
 pub mod frontend;
 pub mod preprocessor;
 pub mod rename;
@@ -1,19 +1,38 @@
 use std::borrow::Borrow;
+use std::collections::{
+    HashMap,
+    HashSet
+};
 use std::rc::Rc;
 
 use clvm_rs::allocator::Allocator;
 
 use crate::classic::clvm_tools::stages::stage_0::TRunProgram;
 
-use crate::compiler::clvm::run;
-use crate::compiler::codegen::get_callable;
+use crate::compiler::clvm::{
+    RunStep,
+    run,
+    run_step,
+    start_step
+};
+use crate::compiler::codegen::{
+    finalize_env,
+    generate_expr_code,
+    get_callable
+};
 use crate::compiler::comptypes::{
     BodyForm,
     Callable,
+    CompileForm,
     CompilerOpts,
-    PrimaryCodegen
+    DefunCall,
+    HelperForm,
+    PrimaryCodegen,
+    list_to_cons
 };
+use crate::compiler::gensym::gensym;
 use crate::compiler::prims::primquote;
+use crate::compiler::runtypes::RunFailure;
 use crate::compiler::sexp::SExp;
 use crate::compiler::srcloc::Srcloc;
 use crate::util::u8_from_number;
@@ -27,6 +46,208 @@ fn is_at_form(head: Rc<BodyForm>) -> bool {
     }
 }
 
+// Folding a call runs user code, which might not terminate, so give up after
+// this many steps and leave the call in place.
+const FOLD_STEP_LIMIT: usize = 100000;
+
+fn run_folded(
+    allocator: &mut Allocator,
+    runner: Rc<dyn TRunProgram>,
+    opts: Rc<dyn CompilerOpts>,
+    sexp: Rc<SExp>,
+    env: Rc<SExp>
+) -> Result<Rc<SExp>, RunFailure> {
+    let mut step = start_step(sexp.clone(), env);
+
+    for _ in 0..FOLD_STEP_LIMIT {
+        step = run_step(allocator, runner.clone(), opts.prim_map(), &step)?;
+        match step {
+            RunStep::Done(_,x) => { return Ok(x); },
+            _ => { }
+        }
+    }
+
+    Err(RunFailure::RunErr(sexp.loc(), "too many steps to fold".to_string()))
+}
+
+fn arg_names(args: Rc<SExp>, names: &mut HashSet<Vec<u8>>) {
+    match args.borrow() {
+        SExp::Atom(_,a) => { names.insert(a.clone()); },
+        SExp::Cons(_,a,b) => {
+            arg_names(a.clone(), names);
+            arg_names(b.clone(), names);
+        },
+        _ => { }
+    }
+}
+
+// An inline body is spliced into its caller, so it can only be folded if
+// everything it refers to is one of its own arguments or a constant.  Any
+// other name or an @ form would read the caller's environment.
+fn inline_is_pure(
+    compiler: &PrimaryCodegen,
+    args: &HashSet<Vec<u8>>,
+    visited: &mut HashSet<Vec<u8>>,
+    body: Rc<BodyForm>
+) -> bool {
+    match body.borrow() {
+        BodyForm::Quoted(_) => true,
+        BodyForm::Let(_,_,_) => false,
+        BodyForm::Value(SExp::Atom(_,a)) => {
+            args.contains(a) || compiler.constants.contains_key(a)
+        },
+        BodyForm::Value(_) => true,
+        BodyForm::Call(_,forms) => {
+            if forms.len() == 0 {
+                return true;
+            } else if is_at_form(forms[0].clone()) {
+                return false;
+            }
+
+            match forms[0].borrow() {
+                BodyForm::Value(SExp::Atom(_,name)) => {
+                    match compiler.inlines.get(name) {
+                        Some(inline) => {
+                            if visited.contains(name) {
+                                return false;
+                            }
+
+                            let mut inner_args = HashSet::new();
+                            arg_names(inline.args.clone(), &mut inner_args);
+                            visited.insert(name.clone());
+                            if !inline_is_pure(compiler, &inner_args, visited, inline.body.clone()) {
+                                return false;
+                            }
+                        },
+                        _ => { }
+                    }
+                },
+                _ => { }
+            }
+
+            forms.iter().skip(1).all(|f| {
+                inline_is_pure(compiler, args, visited, f.clone())
+            })
+        }
+    }
+}
+
+/* Evaluate a call to a user function whose arguments are all constant.
+ *
+ * The call is compiled as it would be in place and run against the
+ * finished function table with no arguments.  A defun only sees the
+ * arguments it's passed, so this is the answer it gives at runtime.
+ * Functions that haven't been compiled yet, including the one being
+ * compiled when it recurses, raise instead of running, and so does any
+ * reference to the program's arguments, so the call is left as it is.
+ */
+fn fold_user_call(
+    allocator: &mut Allocator,
+    runner: Rc<dyn TRunProgram>,
+    opts: Rc<dyn CompilerOpts>,
+    compiler: &PrimaryCodegen,
+    code: Rc<BodyForm>
+) -> Option<SExp> {
+    let l = code.loc();
+    let mut table_compiler = compiler.clone();
+    for name in compiler.parentfns.iter() {
+        if !compiler.defuns.contains_key(name) {
+            let raise = SExp::Cons(
+                l.clone(),
+                Rc::new(SExp::atom_from_string(l.clone(), &"x".to_string())),
+                Rc::new(SExp::Nil(l.clone()))
+            );
+            table_compiler = table_compiler.add_defun(name, DefunCall {
+                required_env: Rc::new(SExp::Nil(l.clone())),
+                code: Rc::new(raise)
+            });
+        }
+    }
+
+    let table = finalize_env(allocator, runner.clone(), opts.clone(), &table_compiler).ok()?;
+    let compiled = generate_expr_code(allocator, runner.clone(), opts.clone(), compiler, code).ok()?;
+    let env = SExp::Cons(l.clone(), Rc::new(table), Rc::new(SExp::Nil(l.clone())));
+    run_folded(allocator, runner, opts, compiled.1, Rc::new(env)).ok().map(|x| {
+        let x_borrow: &SExp = x.borrow();
+        x_borrow.clone()
+    })
+}
+
+// The value of an optimized expression, if it's a constant.
+fn constant_value(body: &BodyForm) -> Option<SExp> {
+    match body {
+        BodyForm::Quoted(v) => Some(v.clone()),
+        BodyForm::Call(l,forms) => {
+            if forms.len() == 0 {
+                Some(SExp::Nil(l.clone()))
+            } else {
+                None
+            }
+        },
+        _ => None
+    }
+}
+
+// The if every mod starts with, rather than a macro of the same name.
+fn is_standard_if(name: &[u8], loc: &Srcloc) -> bool {
+    name == "if".as_bytes() && *loc.file == "*macros*"
+}
+
+// The branch an (i cond a b) with a constant condition takes.
+fn constant_i_branch(code: &BodyForm) -> Option<Rc<BodyForm>> {
+    match code {
+        BodyForm::Call(_,forms) => {
+            if forms.len() != 4 {
+                return None;
+            }
+
+            let value = constant_value(forms[1].borrow())?;
+            let (taken, dropped) =
+                if value.nilp() {
+                    (forms[3].clone(), forms[2].clone())
+                } else {
+                    (forms[2].clone(), forms[3].clone())
+                };
+
+            // i is eager, so the untaken argument is still evaluated, and
+            // anything but a constant could raise.
+            match dropped.borrow() {
+                BodyForm::Quoted(_) => Some(taken),
+                _ => None
+            }
+        },
+        _ => None
+    }
+}
+
+// Optimize each argument of a call, reporting whether they're all constant.
+fn optimize_call_args(
+    allocator: &mut Allocator,
+    opts: Rc<dyn CompilerOpts>,
+    runner: Rc<dyn TRunProgram>,
+    compiler: &PrimaryCodegen,
+    l: Srcloc,
+    forms: &Vec<Rc<BodyForm>>
+) -> (bool, BodyForm) {
+    let mut constant = true;
+    let optimized_args: Vec<(bool, Rc<BodyForm>)> = forms.iter().skip(1).map(|a| {
+        let optimized = optimize_expr(
+            allocator,
+            opts.clone(),
+            runner.clone(),
+            compiler,
+            a.clone()
+        );
+        constant = constant && optimized.as_ref().map(|x| x.0).unwrap_or_else(|| false);
+        optimized.map(|x| (x.0, x.1)).unwrap_or_else(|| (false, a.clone()))
+    }).collect();
+
+    let mut result_list = vec!(forms[0].clone());
+    let mut replaced_args = optimized_args.iter().map(|x| x.1.clone()).collect();
+    result_list.append(&mut replaced_args);
+    (constant, BodyForm::Call(l.clone(), result_list))
+}
+
 pub fn optimize_expr(
     allocator: &mut Allocator,
     opts: Rc<dyn CompilerOpts>,
@@ -47,32 +268,85 @@ pub fn optimize_expr(
             let mut examine_call = |al: Srcloc, an: &Vec<u8>| {
                 get_callable(opts.clone(), compiler, l.clone(), Rc::new(SExp::Atom(al.clone(), an.to_vec()))).
                     map(|calltype| match calltype {
-                        // A macro invocation emits a bodyform, which we
-                        // run back through the frontend and check.
-                        Callable::CallMacro(l,_) => None,
-                        // A function is constant if its body is a constant
-                        // expression or all its arguments are constant and
-                        // its body doesn't include an environment reference.
-                        Callable::CallDefun(l,target) => None,
-                        // A primcall is constant if its arguments are constant
-                        Callable::CallPrim(l,_) => {
-                            let mut constant = true;
-                            let optimized_args: Vec<(bool, Rc<BodyForm>)> = forms.iter().skip(1).map(|a| {
-                                let optimized = optimize_expr(
+                        // An if with a constant condition is the branch it
+                        // picks, and the other is never evaluated.
+                        Callable::CallMacro(_,code) => {
+                            if !is_standard_if(an, &code.loc()) || forms.len() != 4 {
+                                // A macro invocation emits a bodyform, which we
+                                // run back through the frontend and check.
+                                return None;
+                            }
+
+                            let condition = optimize_expr(
+                                allocator,
+                                opts.clone(),
+                                runner.clone(),
+                                compiler,
+                                forms[1].clone()
+                            )?;
+                            let value = constant_value(condition.1.borrow())?;
+                            let branch = if value.nilp() { forms[3].clone() } else { forms[2].clone() };
+                            optimize_expr(
+                                allocator,
+                                opts.clone(),
+                                runner.clone(),
+                                compiler,
+                                branch.clone()
+                            ).or_else(|| Some((false, branch)))
+                        },
+                        // A function is constant if all its arguments are
+                        // constant and its result depends only on them.
+                        // An inline function is the same, but its body is
+                        // expanded in the caller's environment, so it also
+                        // mustn't refer to anything else there.
+                        Callable::CallDefun(_,_) | Callable::CallInline(_,_) => {
+                            let (constant, code) = optimize_call_args(
+                                allocator,
+                                opts.clone(),
+                                runner.clone(),
+                                compiler,
+                                l.clone(),
+                                forms
+                            );
+
+                            let pure =
+                                match &calltype {
+                                    Callable::CallInline(_,inline) => {
+                                        let mut args = HashSet::new();
+                                        let mut visited = HashSet::new();
+                                        arg_names(inline.args.clone(), &mut args);
+                                        visited.insert(an.to_vec());
+                                        inline_is_pure(compiler, &args, &mut visited, inline.body.clone())
+                                    },
+                                    _ => true
+                                };
+
+                            if constant && pure {
+                                fold_user_call(
                                     allocator,
-                                    opts.clone(),
                                     runner.clone(),
+                                    opts.clone(),
                                     compiler,
-                                    a.clone()
-                                );
-                                constant = constant && optimized.as_ref().map(|x| x.0).unwrap_or_else(|| false);
-                                optimized.map(|x| (x.0, x.1)).unwrap_or_else(|| (false, a.clone()))
-                            }).collect();
-
-                            let mut result_list = vec!(forms[0].clone());
-                            let mut replaced_args = optimized_args.iter().map(|x| x.1.clone()).collect();
-                            result_list.append(&mut replaced_args);
-                            let code = BodyForm::Call(l.clone(), result_list);
+                                    Rc::new(code.clone())
+                                ).map(|x| {
+                                    Some((true, Rc::new(BodyForm::Quoted(x))))
+                                }).unwrap_or_else(|| {
+                                    Some((false, Rc::new(code)))
+                                })
+                            } else {
+                                Some((false, Rc::new(code)))
+                            }
+                        },
+                        // A primcall is constant if its arguments are constant
+                        Callable::CallPrim(l,_) => {
+                            let (constant, code) = optimize_call_args(
+                                allocator,
+                                opts.clone(),
+                                runner.clone(),
+                                compiler,
+                                l.clone(),
+                                forms
+                            );
 
                             if constant {
                                 run(
@@ -87,6 +361,13 @@ pub fn optimize_expr(
                                 }).unwrap_or_else(|_| {
                                     Some((false, Rc::new(code)))
                                 })
+                            } else if an.as_slice() == "i".as_bytes() {
+                                // Both branches of i are evaluated, so the
+                                // one not taken can only be dropped if
+                                // evaluating it can't raise.
+                                Some(constant_i_branch(&code).map(|branch| {
+                                    (false, branch)
+                                }).unwrap_or_else(|| (false, Rc::new(code))))
                             } else {
                                 Some((false, Rc::new(code)))
                             }
@@ -114,3 +395,306 @@ pub fn optimize_expr(
         _ => None
     }
 }
+
+/* Passes over a whole program that run before codegen turns let forms into
+ * inline functions.  They work from the program's own helpers and the primitives,
+ * since nothing has been compiled yet.
+ */
+
+#[derive(Clone, Debug, PartialEq)]
+enum HeadKind {
+    // Every argument is evaluated before the call.
+    Eager,
+    // The if macro evaluates its condition but only one branch.
+    If,
+    // A macro or something else whose arguments aren't plain expressions.
+    Opaque
+}
+
+struct BodyContext {
+    heads: HashMap<Vec<u8>, HeadKind>,
+    defuns: HashSet<Vec<u8>>
+}
+
+// Rough costs, in primitive calls, for deciding whether sharing values
+// pays.  Calling a defun costs about this many primitive calls, and so
+// does the extra defun call that passes shared values, plus one for each
+// value passed.
+const DEFUN_CALL_WEIGHT: usize = 5;
+const CSE_CALL_OVERHEAD: usize = 5;
+
+impl BodyContext {
+    fn new(opts: Rc<dyn CompilerOpts>, cmod: &CompileForm) -> Self {
+        let mut heads = HashMap::new();
+        let mut defuns = HashSet::new();
+        for (name,_) in opts.prim_map().iter() {
+            heads.insert(name.clone(), HeadKind::Eager);
+        }
+
+        for h in cmod.helpers.iter() {
+            match h {
+                HelperForm::Defun(_,name,inline,_,_) => {
+                    // An inline function's arguments are substituted into
+                    // its body, so they're evaluated only where it uses them.
+                    if *inline {
+                        heads.insert(name.clone(), HeadKind::Opaque);
+                    } else {
+                        heads.insert(name.clone(), HeadKind::Eager);
+                        defuns.insert(name.clone());
+                    }
+                },
+                HelperForm::Defmacro(loc,name,_,_) => {
+                    let kind = if is_standard_if(name, loc) { HeadKind::If } else { HeadKind::Opaque };
+                    heads.insert(name.clone(), kind);
+                },
+                HelperForm::Defconstant(_,name,_) => {
+                    heads.remove(name);
+                }
+            }
+        }
+
+        BodyContext { heads: heads, defuns: defuns }
+    }
+
+    // The approximate cost of evaluating an expression once.
+    fn weight(&self, body: &BodyForm) -> usize {
+        match body {
+            BodyForm::Call(_,forms) => {
+                let call =
+                    match forms.get(0).map(|f| f.borrow()) {
+                        Some(BodyForm::Value(SExp::Atom(_,name))) => {
+                            if self.defuns.contains(name) { DEFUN_CALL_WEIGHT } else { 1 }
+                        },
+                        _ => 1
+                    };
+                call + forms.iter().skip(1).map(|f| self.weight(f.borrow())).sum::<usize>()
+            },
+            _ => 0
+        }
+    }
+
+    fn head_kind(&self, forms: &Vec<Rc<BodyForm>>) -> HeadKind {
+        if forms.len() == 0 {
+            return HeadKind::Opaque;
+        }
+
+        match forms[0].borrow() {
+            BodyForm::Value(SExp::Atom(_,name)) => {
+                self.heads.get(name).cloned().unwrap_or_else(|| HeadKind::Opaque)
+            },
+            _ => HeadKind::Opaque
+        }
+    }
+
+    // The arguments of a call that are always evaluated.
+    fn eager_args(&self, forms: &Vec<Rc<BodyForm>>) -> Vec<usize> {
+        match self.head_kind(forms) {
+            HeadKind::Eager => (1..forms.len()).collect(),
+            HeadKind::If => if forms.len() > 1 { vec!(1) } else { Vec::new() },
+            HeadKind::Opaque => Vec::new()
+        }
+    }
+
+    // An expression that can be evaluated earlier than written without
+    // changing the result: calls to functions and primitives other than x,
+    // with no reference to the environment.
+    fn pure(&self, body: &BodyForm) -> bool {
+        match body {
+            BodyForm::Quoted(_) => true,
+            BodyForm::Value(SExp::Atom(_,a)) => a.as_slice() != "@".as_bytes(),
+            BodyForm::Value(_) => true,
+            BodyForm::Let(_,_,_) => false,
+            BodyForm::Call(_,forms) => {
+                match forms.get(0).map(|f| f.borrow()) {
+                    Some(BodyForm::Value(SExp::Atom(_,a))) => {
+                        if a.as_slice() == "x".as_bytes() {
+                            return false;
+                        }
+                    },
+                    _ => { }
+                }
+
+                self.head_kind(forms) == HeadKind::Eager &&
+                    forms.iter().skip(1).all(|f| self.pure(f.borrow()))
+            }
+        }
+    }
+}
+
+// Whether an expression refers to the environment as a whole with @, which
+// would mean a different environment once the expression is moved into
+// another defun.
+fn uses_whole_env(body: &BodyForm) -> bool {
+    match body {
+        BodyForm::Value(SExp::Atom(_,a)) => a.as_slice() == "@".as_bytes(),
+        BodyForm::Let(_,bindings,letbody) => {
+            bindings.iter().any(|b| uses_whole_env(b.body.borrow())) ||
+                uses_whole_env(letbody.borrow())
+        },
+        BodyForm::Call(_,forms) => forms.iter().any(|f| uses_whole_env(f.borrow())),
+        _ => false
+    }
+}
+
+fn count_subexpressions(
+    ctx: &BodyContext,
+    body: Rc<BodyForm>,
+    counts: &mut HashMap<String, (usize, Rc<BodyForm>)>
+) {
+    match body.borrow() {
+        BodyForm::Call(_,forms) => {
+            if ctx.pure(body.borrow()) {
+                let key = body.to_sexp().to_string();
+                let entry = counts.entry(key).or_insert_with(|| (0, body.clone()));
+                entry.0 += 1;
+            }
+
+            for i in ctx.eager_args(forms).iter() {
+                count_subexpressions(ctx, forms[*i].clone(), counts);
+            }
+        },
+        _ => { }
+    }
+}
+
+fn replace_subexpression(
+    ctx: &BodyContext,
+    body: Rc<BodyForm>,
+    key: &String,
+    name: &Vec<u8>
+) -> Rc<BodyForm> {
+    match body.borrow() {
+        BodyForm::Call(l,forms) => {
+            if body.to_sexp().to_string() == *key {
+                return Rc::new(BodyForm::Value(SExp::Atom(l.clone(), name.clone())));
+            }
+
+            let eager = ctx.eager_args(forms);
+            let new_forms = forms.iter().enumerate().map(|(i,f)| {
+                if eager.contains(&i) {
+                    replace_subexpression(ctx, f.clone(), key, name)
+                } else {
+                    f.clone()
+                }
+            }).collect();
+            Rc::new(BodyForm::Call(l.clone(), new_forms))
+        },
+        _ => body
+    }
+}
+
+/* Compute pure subexpressions that are evaluated more than once a single
+ * time each.  A let wouldn't do, since let bindings are expanded inline,
+ * so the body moves to a new defun taking the original arguments followed
+ * by the shared values:
+ *
+ *   (cse_$_N (r @) expr1 expr2 ...)
+ *
+ * Only places that are always evaluated are considered, so nothing is
+ * computed that wasn't before.  A body that uses @ is left alone, since the
+ * new defun's environment has the shared values after the arguments.
+ */
+fn eliminate_common_subexpressions(
+    ctx: &BodyContext,
+    args: Rc<SExp>,
+    body: Rc<BodyForm>
+) -> Option<(HelperForm, Rc<BodyForm>)> {
+    match body.borrow() {
+        BodyForm::Let(_,_,_) => { return None; },
+        _ => { }
+    }
+    if uses_whole_env(body.borrow()) {
+        return None;
+    }
+
+    let mut names = Vec::new();
+    let mut values = Vec::new();
+    let mut saving = 0;
+    let mut result = body.clone();
+    loop {
+        let mut counts = HashMap::new();
+        count_subexpressions(ctx, result.clone(), &mut counts);
+
+        // Each shared value costs about one call to pass along.
+        let mut candidates: Vec<(usize, String, Rc<BodyForm>)> = counts.into_iter().filter_map(|(k,(n,e))| {
+            let weight = ctx.weight(e.borrow());
+            if n > 1 && weight * (n - 1) > 1 {
+                Some((weight * (n - 1) - 1, k, e))
+            } else {
+                None
+            }
+        }).collect();
+        candidates.sort_by(|a,b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+        match candidates.get(0) {
+            Some((saved,key,expr)) => {
+                let name = gensym("cse".as_bytes().to_vec());
+                result = replace_subexpression(ctx, result, key, &name);
+                names.push(Rc::new(SExp::Atom(expr.loc(), name)));
+                values.push(expr.clone());
+                saving += saved;
+            },
+            None => { break; }
+        }
+    }
+
+    if saving < CSE_CALL_OVERHEAD {
+        return None;
+    }
+
+    let l = body.loc();
+    let defun_name = gensym("cse".as_bytes().to_vec());
+    let defun_args = SExp::Cons(l.clone(), args, Rc::new(list_to_cons(l.clone(), &names)));
+    let defun = HelperForm::Defun(l.clone(), defun_name.clone(), false, Rc::new(defun_args), result);
+
+    let mut call = vec!(
+        Rc::new(BodyForm::Value(SExp::Atom(l.clone(), defun_name))),
+        Rc::new(BodyForm::Call(l.clone(), vec!(
+            Rc::new(BodyForm::Value(SExp::atom_from_string(l.clone(), &"r".to_string()))),
+            Rc::new(BodyForm::Value(SExp::atom_from_string(l.clone(), &"@".to_string())))
+        )))
+    );
+    call.append(&mut values);
+    Some((defun, Rc::new(BodyForm::Call(l.clone(), call))))
+}
+
+fn optimize_body(
+    ctx: &BodyContext,
+    args: Rc<SExp>,
+    body: Rc<BodyForm>,
+    new_helpers: &mut Vec<HelperForm>
+) -> Rc<BodyForm> {
+    match eliminate_common_subexpressions(ctx, args, body.clone()) {
+        Some((helper, call)) => {
+            new_helpers.push(helper);
+            call
+        },
+        None => body
+    }
+}
+
+/* Compute repeated subexpressions in the main expression and the defuns of
+ * a program once.  Inline functions are
+ * left alone, since their bodies are expanded into the caller.
+ */
+pub fn optimize_body_forms(opts: Rc<dyn CompilerOpts>, cmod: &CompileForm) -> CompileForm {
+    let ctx = BodyContext::new(opts, cmod);
+    let mut new_helpers = Vec::new();
+    let mut helpers: Vec<HelperForm> = cmod.helpers.iter().map(|h| match h {
+        HelperForm::Defun(l,name,false,args,body) => {
+            let new_body = optimize_body(&ctx, args.clone(), body.clone(), &mut new_helpers);
+            HelperForm::Defun(l.clone(), name.clone(), false, args.clone(), new_body)
+        },
+        _ => h.clone()
+    }).collect();
+    let exp = optimize_body(&ctx, cmod.args.clone(), cmod.exp.clone(), &mut new_helpers);
+    helpers.append(&mut new_helpers);
+
+    CompileForm {
+        loc: cmod.loc.clone(),
+        args: cmod.args.clone(),
+        helpers: helpers,
+        exp: exp,
+        types: cmod.types.clone()
+    }
+}
@@ -1,4 +1,9 @@
 use std::borrow::Borrow;
+use std::collections::{
+    HashMap,
+    HashSet
+};
+use std::path::Path;
 use std::rc::Rc;
 
 use crate::classic::clvm::__type_compatibility__::{
@@ -11,6 +16,7 @@ use crate::compiler::comptypes::{
     CompilerOpts
 };
 use crate::compiler::sexp::{
+    decode_string,
     enlist,
     parse_sexp,
     SExp
@@ -41,11 +47,230 @@ pub fn process_include(
     })
 }
 
-/* Expand include inline in forms */
+// The name a helper form defines, if it is one.
+fn helper_name(form: &SExp) -> Option<(Srcloc, Vec<u8>)> {
+    form.proper_list().and_then(|x| {
+        match (x.get(0), x.get(1)) {
+            (Some(SExp::Atom(_,op)), Some(SExp::Atom(nl,name))) => {
+                let kind = decode_string(op);
+                if kind == "defun" || kind == "defun-inline" ||
+                    kind == "defconstant" || kind == "defmacro" {
+                    Some((nl.clone(), name.clone()))
+                } else {
+                    None
+                }
+            },
+            _ => None
+        }
+    })
+}
+
+fn bound_names(args: &SExp, names: &mut HashSet<Vec<u8>>) {
+    match args {
+        SExp::Atom(_,name) => { names.insert(name.clone()); },
+        SExp::Cons(_,f,r) => {
+            bound_names(f.borrow(), names);
+            bound_names(r.borrow(), names);
+        },
+        _ => { }
+    }
+}
+
+// Rename uses of a library's names in body, leaving quoted data and names
+// bound by the enclosing function's arguments alone.
+fn rename_library_uses(
+    renames: &HashMap<Vec<u8>, Vec<u8>>,
+    bound: &HashSet<Vec<u8>>,
+    body: Rc<SExp>
+) -> Rc<SExp> {
+    match body.borrow() {
+        SExp::Atom(l,name) => {
+            match renames.get(name) {
+                Some(new_name) => {
+                    if bound.contains(name) {
+                        body.clone()
+                    } else {
+                        Rc::new(SExp::Atom(l.clone(), new_name.clone()))
+                    }
+                },
+                None => body.clone()
+            }
+        },
+        SExp::Cons(l,f,r) => {
+            match f.borrow() {
+                SExp::Atom(_,q) => {
+                    if *q == "q".as_bytes().to_vec() || *q == "quote".as_bytes().to_vec() {
+                        return body.clone();
+                    }
+                },
+                _ => { }
+            }
+
+            Rc::new(SExp::Cons(
+                l.clone(),
+                rename_library_uses(renames, bound, f.clone()),
+                rename_library_uses(renames, bound, r.clone())
+            ))
+        },
+        _ => body.clone()
+    }
+}
+
+fn rename_library_form(renames: &HashMap<Vec<u8>, Vec<u8>>, form: Rc<SExp>) -> Rc<SExp> {
+    let x =
+        match form.proper_list() {
+            Some(x) => x,
+            None => { return rename_library_uses(renames, &HashSet::new(), form); }
+        };
+
+    let mut bound = HashSet::new();
+    let kind = x.get(0).map(|op| op.to_string()).unwrap_or_else(|| "".to_string());
+    if x.len() > 2 && kind != "defconstant" {
+        bound_names(&x[2], &mut bound);
+    }
+
+    let renamed: Vec<Rc<SExp>> = x.iter().enumerate().map(|(i,elt)| {
+        let elt = Rc::new(elt.clone());
+        if i == 0 || (i == 2 && kind != "defconstant") {
+            elt
+        } else if i == 1 {
+            rename_library_uses(renames, &HashSet::new(), elt)
+        } else {
+            rename_library_uses(renames, &bound, elt)
+        }
+    }).collect();
+
+    Rc::new(enlist(form.loc(), renamed))
+}
+
+// How an import names what it brings in: everything under a prefix, or only
+// the listed names as they are.
+enum ImportNames {
+    Prefix(Vec<u8>),
+    Exports(Vec<(Srcloc, Vec<u8>)>)
+}
+
+fn parse_import(body: Rc<SExp>, x: &Vec<SExp>) -> Result<Option<(String, ImportNames)>, CompileErr> {
+    let is_import =
+        match x.get(0) {
+            Some(SExp::Atom(_,imp)) => *imp == "import".as_bytes().to_vec(),
+            _ => false
+        };
+    if !is_import {
+        return Ok(None);
+    }
+
+    let bad_form = || Err(CompileErr(
+        body.loc(),
+        format!("bad import form {}", body.to_string())
+    ));
+
+    let fname =
+        match x.get(1) {
+            Some(SExp::Atom(_,f)) => decode_string(f),
+            Some(SExp::QuotedString(_,_,f)) => decode_string(f),
+            _ => { return bad_form(); }
+        };
+
+    let names =
+        match &x[2..] {
+            [] => {
+                let stem =
+                    Path::new(&fname).file_stem().map(|s| {
+                        s.to_string_lossy().to_string()
+                    }).unwrap_or_else(|| fname.clone());
+                ImportNames::Prefix(stem.as_bytes().to_vec())
+            },
+            [SExp::Atom(_,kw), SExp::Atom(_,prefix)] if *kw == "as".as_bytes().to_vec() => {
+                ImportNames::Prefix(prefix.clone())
+            },
+            [exports] => {
+                let mut names = Vec::new();
+                for e in exports.proper_list().unwrap_or_else(|| vec!()).iter() {
+                    match e {
+                        SExp::Atom(l,name) => { names.push((l.clone(), name.clone())); },
+                        _ => { return bad_form(); }
+                    }
+                }
+                if names.len() == 0 {
+                    return bad_form();
+                }
+                ImportNames::Exports(names)
+            },
+            _ => { return bad_form(); }
+        };
+
+    Ok(Some((fname, names)))
+}
+
+/* Load a library and name its helpers as the import asks.  Helpers that
+ * aren't exported are still brought in, since exported ones may use them,
+ * but under the library's name as a prefix.
+ */
+fn process_import(
+    opts: Rc<dyn CompilerOpts>,
+    fname: &String,
+    names: &ImportNames
+) -> Result<Vec<Rc<SExp>>, CompileErr> {
+    let forms = process_include(opts, fname)?;
+    let stem =
+        Path::new(fname).file_stem().map(|s| {
+            s.to_string_lossy().to_string()
+        }).unwrap_or_else(|| fname.clone());
+
+    let defined: Vec<Vec<u8>> =
+        forms.iter().filter_map(|f| helper_name(f.borrow())).map(|(_,n)| n).collect();
+
+    let prefixed = |prefix: &Vec<u8>, name: &Vec<u8>| {
+        let mut new_name = prefix.clone();
+        new_name.push(b'.');
+        new_name.append(&mut name.clone());
+        new_name
+    };
+
+    let mut renames = HashMap::new();
+    match names {
+        ImportNames::Prefix(prefix) => {
+            for name in defined.iter() {
+                renames.insert(name.clone(), prefixed(prefix, name));
+            }
+        },
+        ImportNames::Exports(exports) => {
+            for (l,e) in exports.iter() {
+                if !defined.contains(e) {
+                    return Err(CompileErr(
+                        l.clone(),
+                        format!("{} is not defined in {}", decode_string(e), fname)
+                    ));
+                }
+            }
+            for name in defined.iter() {
+                if !exports.iter().any(|(_,e)| e == name) {
+                    renames.insert(name.clone(), prefixed(&stem.as_bytes().to_vec(), name));
+                }
+            }
+        }
+    }
+
+    Ok(forms.iter().map(|f| rename_library_form(&renames, f.clone())).collect())
+}
+
+/* Expand include and import inline in forms */
 fn process_pp_form(
     opts: Rc<dyn CompilerOpts>,
     body: Rc<SExp>
 ) -> Result<Vec<Rc<SExp>>, CompileErr> {
+    let import = body.proper_list().map(|x| {
+        parse_import(body.clone(), &x)
+    }).unwrap_or_else(|| Ok(None))?;
+
+    match import {
+        Some((fname, names)) => {
+            return process_import(opts, &fname, &names);
+        },
+        None => { }
+    }
+
     let filename: Option<Vec<u8>> = body.proper_list().map(|x| {
         match &x[..] {
             [SExp::Atom(_,inc), SExp::Atom(_,fname)] => {
@@ -285,7 +285,8 @@ fn rename_in_compileform(
         loc: c.loc.clone(),
         args: c.args.clone(),
         helpers: c.helpers.iter().map(|x| rename_in_helperform(namemap, x)).collect(),
-        exp: Rc::new(rename_in_bodyform(namemap, c.exp.clone()))
+        exp: Rc::new(rename_in_bodyform(namemap, c.exp.clone())),
+        types: c.types.clone()
     }
 }
 
@@ -297,7 +298,8 @@ pub fn rename_children_compileform(c: &CompileForm) -> CompileForm {
         loc: c.loc.clone(),
         args: c.args.clone(),
         helpers: local_renamed_helpers,
-        exp: Rc::new(local_renamed_body)
+        exp: Rc::new(local_renamed_body),
+        types: c.types.clone()
     }
 }
 
@@ -316,6 +318,7 @@ pub fn rename_args_compileform(c: &CompileForm) -> CompileForm {
         args: local_renamed_arg,
         helpers: local_renamed_helpers.iter().
             map(|x| rename_in_helperform(&local_namemap, x)).collect(),
-        exp: Rc::new(rename_in_bodyform(&local_namemap, Rc::new(local_renamed_body)))
+        exp: Rc::new(rename_in_bodyform(&local_namemap, Rc::new(local_renamed_body))),
+        types: c.types.clone()
     }
 }
@@ -569,20 +569,10 @@ fn parse_sexp_inner(start_: Srcloc, p_: SExpParseState, n_: usize, s: &Vec<u8>)
                     return Ok(vec!(Rc::new(make_atom(l,t))));
                 },
                 SExpParseState::CommentText(_,_) => { return Ok(res); },
-                SExpParseState::QuotedText(l, _, _) => {
-                    return Err((l, "unterminated quoted string".to_string()));
-                },
-                SExpParseState::QuotedEscaped(l, _, _) => {
-                    return Err((l, "unterminated quoted string with escape".to_string()));
-                },
-                SExpParseState::OpenList(l) => {
-                    return Err((l, "Unterminated list (empty)".to_string()));
-                },
-                SExpParseState::ParsingList(l, _, _) => {
-                    return Err((l, "Unterminated mid list".to_string()));
-                },
-                SExpParseState::TermList(l, _, _) => {
-                    return Err((l, "Unterminated tail list".to_string()));
+                _ => {
+                    return Err(unterminated(&p).unwrap_or_else(|| {
+                        (start, "unexpected end of input".to_string())
+                    }));
                 }
             }
         } else {
@@ -606,6 +596,463 @@ fn parse_sexp_inner(start_: Srcloc, p_: SExpParseState, n_: usize, s: &Vec<u8>)
     }
 }
 
+fn unterminated(p: &SExpParseState) -> Option<(Srcloc, String)> {
+    match p {
+        SExpParseState::QuotedText(l, _, _) => {
+            Some((l.clone(), "unterminated quoted string".to_string()))
+        },
+        SExpParseState::QuotedEscaped(l, _, _) => {
+            Some((l.clone(), "unterminated quoted string with escape".to_string()))
+        },
+        SExpParseState::OpenList(l) => {
+            Some((l.clone(), "Unterminated list (empty)".to_string()))
+        },
+        SExpParseState::ParsingList(l, _, _) => {
+            Some((l.clone(), "Unterminated mid list".to_string()))
+        },
+        SExpParseState::TermList(l, _, _) => {
+            Some((l.clone(), "Unterminated tail list".to_string()))
+        },
+        _ => None
+    }
+}
+
+// The character that closes the innermost open form at the end of input.
+fn closing_char(p: &SExpParseState) -> Option<u8> {
+    match p {
+        SExpParseState::CommentText(_,_) => Some('\n' as u8),
+        SExpParseState::QuotedText(_, term, _) => Some(*term),
+        SExpParseState::QuotedEscaped(_, term, _) => Some(*term),
+        SExpParseState::OpenList(_) => Some(')' as u8),
+        SExpParseState::ParsingList(_, pp, _) => closing_char(pp.borrow()).or(Some(')' as u8)),
+        SExpParseState::TermList(_, pp, _) => closing_char(pp.borrow()).or(Some(')' as u8)),
+        _ => None
+    }
+}
+
 pub fn parse_sexp(start: Srcloc, input: &String) -> Result<Vec<Rc<SExp>>, (Srcloc, String)> {
     parse_sexp_inner(start, SExpParseState::Empty, 0, &input.as_bytes().to_vec())
 }
+
+// Parse as much as possible, collecting every error.  A character that
+// causes an error is skipped, and forms left open at the end of the input
+// are closed, so later forms are still read after unbalanced parens.
+pub fn parse_sexp_recover(start_: Srcloc, input: &String) -> (Vec<Rc<SExp>>, Vec<(Srcloc, String)>) {
+    let s = input.as_bytes();
+    let mut start = start_;
+    let mut p = SExpParseState::Empty;
+    let mut n = 0;
+    let mut closed = 0;
+    let mut res = Vec::new();
+    let mut errors = Vec::new();
+
+    loop {
+        let this_char =
+            if n < s.len() {
+                s[n]
+            } else {
+                match &p {
+                    SExpParseState::Bareword(l,t) => {
+                        res.push(Rc::new(make_atom(l.clone(), t.to_vec())));
+                        return (res, errors);
+                    },
+                    _ => { }
+                }
+
+                if closed == 0 {
+                    match unterminated(&p) {
+                        Some(e) => { errors.push(e); },
+                        _ => { }
+                    }
+                }
+
+                // Each open form needs at most two characters to close.
+                match closing_char(&p) {
+                    Some(ch) => {
+                        if closed > 2 * s.len() + 2 {
+                            return (res, errors);
+                        }
+                        closed += 1;
+                        ch
+                    },
+                    None => { return (res, errors); }
+                }
+            };
+
+        let next_location = start.clone().advance(this_char);
+        match parse_sexp_step(start.clone(), p.borrow(), this_char) {
+            SExpParseResult::PError(l,e) => {
+                errors.push((l,e));
+                if n >= s.len() {
+                    return (res, errors);
+                }
+                start = next_location;
+            },
+            SExpParseResult::PResume(np) => {
+                start = next_location;
+                p = np;
+            },
+            SExpParseResult::PEmit(o,np) => {
+                p = np;
+                res.push(o);
+            }
+        }
+        n = n + 1;
+    }
+}
+
+// Whitespace and comments, which parse_sexp discards.  A comment runs from
+// its ';' up to, but not including, the end of its line.
+#[derive(Clone)]
+#[derive(Debug)]
+pub enum Trivia {
+    Whitespace(Srcloc, Vec<u8>),
+    Comment(Srcloc, Vec<u8>)
+}
+
+impl Trivia {
+    pub fn loc(&self) -> Srcloc {
+        match self {
+            Trivia::Whitespace(l,_) => l.clone(),
+            Trivia::Comment(l,_) => l.clone()
+        }
+    }
+
+    pub fn text(&self) -> &Vec<u8> {
+        match self {
+            Trivia::Whitespace(_,t) => t,
+            Trivia::Comment(_,t) => t
+        }
+    }
+
+    pub fn newlines(&self) -> usize {
+        match self {
+            Trivia::Whitespace(_,t) => t.iter().filter(|ch| **ch == b'\n').count(),
+            Trivia::Comment(_,_) => 0
+        }
+    }
+}
+
+// A lossless concrete syntax tree.  Atoms and quoted strings keep the exact
+// bytes they were written with, so the radix of a number, a '#' prefix and
+// the quotes and escapes of a string are all kept.  Dot is the marker before
+// the tail of a dotted list.
+#[derive(Clone)]
+#[derive(Debug)]
+pub enum CstNode {
+    Atom(Srcloc, Vec<u8>),
+    QuotedString(Srcloc, u8, Vec<u8>),
+    Dot(Srcloc),
+    List(Srcloc, Vec<CstElement>, Vec<Trivia>)
+}
+
+// A node with the trivia that precedes it.  A list's trivia before its
+// close paren, and a file's after its last form, are kept separately.
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct CstElement {
+    pub trivia: Vec<Trivia>,
+    pub node: CstNode
+}
+
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct CstFile {
+    pub forms: Vec<CstElement>,
+    pub trailing: Vec<Trivia>
+}
+
+fn print_trivia(trivia: &Vec<Trivia>, v: &mut Vec<u8>) {
+    for t in trivia.iter() {
+        v.extend_from_slice(t.text());
+    }
+}
+
+// The content of a quoted string as parse_sexp reads it.
+fn unquote(raw: &Vec<u8>) -> Vec<u8> {
+    let mut res = Vec::new();
+    let mut escaped = false;
+    if raw.len() < 2 {
+        return res;
+    }
+
+    for ch in raw[1..raw.len()-1].iter() {
+        if escaped || *ch != b'\\' {
+            res.push(*ch);
+            escaped = false;
+        } else {
+            escaped = true;
+        }
+    }
+
+    res
+}
+
+impl CstNode {
+    pub fn loc(&self) -> Srcloc {
+        match self {
+            CstNode::Atom(l,_) => l.clone(),
+            CstNode::QuotedString(l,_,_) => l.clone(),
+            CstNode::Dot(l) => l.clone(),
+            CstNode::List(l,_,_) => l.clone()
+        }
+    }
+
+    pub fn print_mut(&self, v: &mut Vec<u8>) {
+        match self {
+            CstNode::Atom(_,t) => { v.extend_from_slice(t); },
+            CstNode::QuotedString(_,_,t) => { v.extend_from_slice(t); },
+            CstNode::Dot(_) => { v.push(b'.'); },
+            CstNode::List(_,elts,closing) => {
+                v.push(b'(');
+                for e in elts.iter() {
+                    e.print_mut(v);
+                }
+                print_trivia(closing, v);
+                v.push(b')');
+            }
+        }
+    }
+
+    // The SExp parse_sexp gives for this node.  A Dot on its own reads as
+    // the atom ".".
+    pub fn to_sexp(&self) -> Rc<SExp> {
+        match self {
+            CstNode::Atom(l,t) => Rc::new(make_atom(l.clone(), t.clone())),
+            CstNode::QuotedString(l,q,t) => Rc::new(SExp::QuotedString(l.clone(), *q, unquote(t))),
+            CstNode::Dot(l) => Rc::new(SExp::Atom(l.clone(), vec!(b'.'))),
+            CstNode::List(l,elts,_) => {
+                let mut list_content = Vec::new();
+                let mut dotted = false;
+                for e in elts.iter() {
+                    match &e.node {
+                        CstNode::Dot(_) => { dotted = true; },
+                        n => {
+                            let o = n.to_sexp();
+                            if dotted {
+                                // As in parse_sexp, each element after the
+                                // dot becomes the tail of the one before.
+                                match list_content.pop() {
+                                    Some(v) => { list_content.push(Rc::new(make_cons(v, o))); },
+                                    None => { list_content.push(o); }
+                                }
+                            } else {
+                                list_content.push(o);
+                            }
+                        }
+                    }
+                }
+
+                if dotted && list_content.len() == 1 {
+                    list_content[0].clone()
+                } else {
+                    Rc::new(enlist(l.clone(), list_content))
+                }
+            }
+        }
+    }
+}
+
+impl CstElement {
+    pub fn print_mut(&self, v: &mut Vec<u8>) {
+        print_trivia(&self.trivia, v);
+        self.node.print_mut(v);
+    }
+}
+
+impl CstFile {
+    // Reproduces the parsed text exactly.
+    pub fn print(&self) -> String {
+        let mut v = Vec::new();
+        for f in self.forms.iter() {
+            f.print_mut(&mut v);
+        }
+        print_trivia(&self.trailing, &mut v);
+        String::from_utf8_lossy(&v).to_string()
+    }
+
+    pub fn to_sexp(&self) -> Vec<Rc<SExp>> {
+        self.forms.iter().map(|f| f.node.to_sexp()).collect()
+    }
+}
+
+struct CstParser<'a> {
+    text: &'a [u8],
+    n: usize,
+    loc: Srcloc
+}
+
+impl<'a> CstParser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.n).map(|ch| *ch)
+    }
+
+    // Consume one character, returning its location.
+    fn advance(&mut self) -> Srcloc {
+        let here = self.loc.clone();
+        self.loc = self.loc.advance(self.text[self.n]);
+        self.n += 1;
+        here
+    }
+
+    fn take_while<F>(&mut self, f: F) -> (Srcloc, Vec<u8>) where F: Fn(u8) -> bool {
+        let start = self.loc.clone();
+        let mut last = start.clone();
+        let first = self.n;
+        while let Some(ch) = self.peek() {
+            if !f(ch) {
+                break;
+            }
+            last = self.advance();
+        }
+        (start.ext(&last), self.text[first..self.n].to_vec())
+    }
+
+    // A comment ends before the \n or \r\n ending its line.
+    fn comment(&mut self) -> (Srcloc, Vec<u8>) {
+        let start = self.loc.clone();
+        let mut last = start.clone();
+        let first = self.n;
+        while let Some(ch) = self.peek() {
+            let line_end = ch == b'\n' || (ch == b'\r' && self.text.get(self.n + 1) == Some(&b'\n'));
+            if line_end {
+                break;
+            }
+            last = self.advance();
+        }
+        (start.ext(&last), self.text[first..self.n].to_vec())
+    }
+
+    fn trivia(&mut self) -> Vec<Trivia> {
+        let mut res = Vec::new();
+        loop {
+            match self.peek() {
+                Some(b';') => {
+                    let (l,t) = self.comment();
+                    res.push(Trivia::Comment(l,t));
+                },
+                Some(ch) if char::is_whitespace(ch as char) => {
+                    let (l,t) = self.take_while(|ch| char::is_whitespace(ch as char));
+                    res.push(Trivia::Whitespace(l,t));
+                },
+                _ => { return res; }
+            }
+        }
+    }
+
+    fn quoted(&mut self, term: u8) -> Result<CstNode, (Srcloc, String)> {
+        let first = self.n;
+        let start = self.advance();
+        loop {
+            match self.peek() {
+                None => {
+                    return Err((start, "unterminated quoted string".to_string()));
+                },
+                Some(b'\\') => {
+                    self.advance();
+                    if self.peek().is_none() {
+                        return Err((start, "unterminated quoted string with escape".to_string()));
+                    }
+                    self.advance();
+                },
+                Some(ch) => {
+                    let last = self.advance();
+                    if ch == term {
+                        let raw = self.text[first..self.n].to_vec();
+                        return Ok(CstNode::QuotedString(start.ext(&last), term, raw));
+                    }
+                }
+            }
+        }
+    }
+
+    // A token other than a list or dot.  Barewords in a list also end at a
+    // close paren.
+    fn token(&mut self, in_list: bool) -> Result<CstNode, (Srcloc, String)> {
+        match self.peek() {
+            Some(b'"') => self.quoted(b'"'),
+            Some(b'\'') => self.quoted(b'\''),
+            _ => {
+                let (l,t) = self.take_while(|ch| {
+                    !char::is_whitespace(ch as char) && !(in_list && ch == b')')
+                });
+                Ok(CstNode::Atom(l,t))
+            }
+        }
+    }
+
+    fn list(&mut self) -> Result<CstNode, (Srcloc, String)> {
+        let start = self.advance();
+        let mut elts: Vec<CstElement> = Vec::new();
+        let mut dotted = false;
+        let mut before_dot = 0;
+
+        loop {
+            let trivia = self.trivia();
+            let node = match self.peek() {
+                None => {
+                    let msg =
+                        if dotted {
+                            "Unterminated tail list"
+                        } else if elts.len() == 0 && trivia.len() == 0 {
+                            "Unterminated list (empty)"
+                        } else {
+                            "Unterminated mid list"
+                        };
+                    return Err((start.ext(&self.loc), msg.to_string()));
+                },
+                Some(b')') => {
+                    let end = self.advance();
+                    return Ok(CstNode::List(start.ext(&end), elts, trivia));
+                },
+                Some(b'.') => {
+                    if elts.len() == 0 && trivia.len() == 0 {
+                        return Err((self.loc.clone(), "Dot can't appear directly after begin paren".to_string()));
+                    } else if dotted {
+                        return Err((self.loc.clone(), "Multiple dots in list notation are illegal".to_string()));
+                    }
+                    dotted = true;
+                    CstNode::Dot(self.advance())
+                },
+                Some(b'(') => self.list()?,
+                _ => self.token(true)?
+            };
+
+            match node {
+                CstNode::Dot(_) => { },
+                _ => {
+                    if dotted && before_dot == 0 {
+                        return Err((node.loc(), "Dot as first element of list?".to_string()));
+                    } else if !dotted {
+                        before_dot += 1;
+                    }
+                }
+            }
+
+            elts.push(CstElement { trivia: trivia, node: node });
+        }
+    }
+}
+
+// Parse in lossless mode, keeping the trivia and spelling parse_sexp drops.
+// The same inputs are accepted, and CstFile::to_sexp gives the same forms.
+pub fn parse_sexp_lossless(start: Srcloc, input: &String) -> Result<CstFile, (Srcloc, String)> {
+    let mut parser = CstParser { text: input.as_bytes(), n: 0, loc: start };
+    let mut forms = Vec::new();
+
+    loop {
+        let trivia = parser.trivia();
+        let node = match parser.peek() {
+            None => {
+                return Ok(CstFile { forms: forms, trailing: trivia });
+            },
+            Some(b')') => {
+                return Err((parser.loc.clone(), "Too many close parens".to_string()));
+            },
+            Some(b'(') => parser.list()?,
+            _ => parser.token(false)?
+        };
+
+        forms.push(CstElement { trivia: trivia, node: node });
+    }
+}
@@ -0,0 +1,362 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use clvm_rs::allocator;
+use clvm_rs::allocator::{
+    Allocator,
+    NodePtr
+};
+
+use num_bigint::ToBigInt;
+use serde_json::{json, Value};
+
+use crate::classic::clvm::__type_compatibility__::{
+    Bytes,
+    BytesFromType,
+    sha256
+};
+use crate::classic::clvm_tools::sha256tree::sha256tree;
+use crate::compiler::cldb::FunctionMap;
+use crate::compiler::clvm::convert_from_clvm_rs;
+use crate::compiler::runtypes::RunFailure;
+use crate::compiler::sexp::SExp;
+use crate::compiler::srcloc::Srcloc;
+use crate::util::Number;
+
+pub const SOURCE_MAP_VERSION: u64 = 1;
+
+/* Where one node of a compiled program came from.  The node is named both by
+ * its clvm path from the root of the program and by the offset of its first
+ * byte in the serialized program.
+ */
+#[derive(Clone, Debug)]
+pub struct SourceMapEntry {
+    pub path: Number,
+    pub offset: usize,
+    pub loc: Srcloc,
+    pub function: Option<String>
+}
+
+#[derive(Clone, Debug)]
+pub struct SourceMap {
+    pub entries: Vec<SourceMapEntry>,
+    by_path: HashMap<Number, usize>
+}
+
+fn serialized_atom_length(atom: &[u8]) -> usize {
+    let len = atom.len();
+    if len == 0 || (len == 1 && atom[0] <= 0x7f) {
+        1
+    } else if len < 0x40 {
+        1 + len
+    } else if len < 0x2000 {
+        2 + len
+    } else if len < 0x100000 {
+        3 + len
+    } else if len < 0x8000000 {
+        4 + len
+    } else {
+        5 + len
+    }
+}
+
+fn serialized_length(allocator: &Allocator, program: NodePtr) -> usize {
+    match allocator.sexp(program) {
+        allocator::SExp::Pair(a,b) => {
+            1 + serialized_length(allocator, a) + serialized_length(allocator, b)
+        },
+        allocator::SExp::Atom(buf) => serialized_atom_length(allocator.buf(&buf))
+    }
+}
+
+fn loc_json(entry: &SourceMapEntry) -> Value {
+    let mut v = json!({
+        "path": entry.path.to_string(),
+        "offset": entry.offset,
+        "file": entry.loc.file.to_string(),
+        "line": entry.loc.line,
+        "col": entry.loc.col
+    });
+    match entry.loc.until {
+        Some((line, col)) => { v["until"] = json!({"line": line, "col": col}); },
+        None => { }
+    }
+    match &entry.function {
+        Some(f) => { v["function"] = json!(f); },
+        None => { }
+    }
+    v
+}
+
+fn entry_from_json(v: &Value) -> Option<SourceMapEntry> {
+    let path = v["path"].as_str().and_then(|p| p.parse::<Number>().ok())?;
+    let offset = v["offset"].as_u64()? as usize;
+    let file = v["file"].as_str()?.to_string();
+    let line = v["line"].as_u64()? as usize;
+    let col = v["col"].as_u64()? as usize;
+    let until = v["until"]["line"].as_u64().and_then(|l| {
+        v["until"]["col"].as_u64().map(|c| (l as usize, c as usize))
+    });
+    let function = v["function"].as_str().map(|f| f.to_string());
+    Some(SourceMapEntry {
+        path: path,
+        offset: offset,
+        loc: Srcloc { file: Rc::new(file), line: line, col: col, until: until },
+        function: function
+    })
+}
+
+impl SourceMap {
+    fn from_entries(entries: Vec<SourceMapEntry>) -> Self {
+        let by_path = entries.iter().enumerate().map(|(i, e)| (e.path.clone(), i)).collect();
+        SourceMap { entries: entries, by_path: by_path }
+    }
+
+    fn add_entries(
+        entries: &mut Vec<SourceMapEntry>,
+        allocator: &Allocator,
+        functions: &FunctionMap,
+        program: NodePtr,
+        code: &SExp,
+        path: Number,
+        bit: Number,
+        offset: &mut usize
+    ) {
+        entries.push(SourceMapEntry {
+            path: path.clone() + bit.clone(),
+            offset: *offset,
+            loc: code.loc(),
+            function: functions.function_at(&code.loc())
+        });
+
+        match (allocator.sexp(program), code) {
+            (allocator::SExp::Pair(a,b), SExp::Cons(_,ca,cb)) => {
+                let next_bit = bit.clone() * 2_i32.to_bigint().unwrap();
+                *offset += 1;
+                SourceMap::add_entries(entries, allocator, functions, a, ca.borrow(), path.clone(), next_bit.clone(), offset);
+                SourceMap::add_entries(entries, allocator, functions, b, cb.borrow(), path + bit, next_bit, offset);
+            },
+            // The located code doesn't follow the program here, so nothing
+            // below this node can be given a better location.
+            _ => { *offset += serialized_length(allocator, program); }
+        }
+    }
+
+    /* Map the nodes of program, a serialized form of code, to the locations
+     * code carries, naming the defun from functions that each lies in.
+     */
+    pub fn new(
+        allocator: &Allocator,
+        functions: &FunctionMap,
+        program: NodePtr,
+        code: &SExp
+    ) -> Self {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        SourceMap::add_entries(
+            &mut entries,
+            allocator,
+            functions,
+            program,
+            code,
+            0_i32.to_bigint().unwrap(),
+            1_i32.to_bigint().unwrap(),
+            &mut offset
+        );
+        SourceMap::from_entries(entries)
+    }
+
+    pub fn to_json(&self) -> String {
+        let entries: Vec<Value> = self.entries.iter().map(|e| loc_json(e)).collect();
+        json!({
+            "version": SOURCE_MAP_VERSION,
+            "entries": entries
+        }).to_string()
+    }
+
+    pub fn from_json(text: &String) -> Result<Self, String> {
+        let v: Value = serde_json::from_str(text).map_err(|e| {
+            format!("source map isn't json: {}", e)
+        })?;
+        if v["version"].as_u64() != Some(SOURCE_MAP_VERSION) {
+            return Err(format!("unsupported source map version {}", v["version"]));
+        }
+        let mut entries = Vec::new();
+        for e in v["entries"].as_array().unwrap_or(&Vec::new()).iter() {
+            match entry_from_json(e) {
+                Some(entry) => { entries.push(entry); },
+                None => {
+                    return Err(format!("bad source map entry {}", e));
+                }
+            }
+        }
+        Ok(SourceMap::from_entries(entries))
+    }
+
+    pub fn entry(&self, path: &Number) -> Option<&SourceMapEntry> {
+        self.by_path.get(path).map(|i| &self.entries[*i])
+    }
+
+    fn located_inner(
+        &self,
+        allocator: &mut Allocator,
+        loc: Srcloc,
+        program: NodePtr,
+        path: Number,
+        bit: Number
+    ) -> Result<Rc<SExp>, RunFailure> {
+        let here = path.clone() + bit.clone();
+        let srcloc = self.entry(&here).map(|e| e.loc.clone()).unwrap_or_else(|| loc);
+        match allocator.sexp(program) {
+            allocator::SExp::Pair(a,b) => {
+                let next_bit = bit.clone() * 2_i32.to_bigint().unwrap();
+                let first = self.located_inner(allocator, srcloc.clone(), a, path.clone(), next_bit.clone())?;
+                let rest = self.located_inner(allocator, srcloc.clone(), b, path + bit, next_bit)?;
+                Ok(Rc::new(SExp::Cons(srcloc, first, rest)))
+            },
+            _ => convert_from_clvm_rs(allocator, srcloc, program)
+        }
+    }
+
+    /* Convert program to a located SExp, giving each node the location the
+     * map has for it or else the location of its nearest mapped parent.
+     */
+    pub fn located_sexp(
+        &self,
+        allocator: &mut Allocator,
+        loc: Srcloc,
+        program: NodePtr
+    ) -> Result<Rc<SExp>, RunFailure> {
+        self.located_inner(
+            allocator,
+            loc,
+            program,
+            0_i32.to_bigint().unwrap(),
+            1_i32.to_bigint().unwrap()
+        )
+    }
+
+    fn symbols_inner(
+        &self,
+        allocator: &mut Allocator,
+        symbols: &mut HashMap<String, (String, Srcloc)>,
+        parent_function: Option<String>,
+        program: NodePtr,
+        path: Number,
+        bit: Number
+    ) {
+        let here = path.clone() + bit.clone();
+        let entry = self.entry(&here).cloned();
+        let function = entry.as_ref().and_then(|e| e.function.clone());
+        match allocator.sexp(program) {
+            allocator::SExp::Pair(a,b) => {
+                // Only conses are named: an atom's hash says too little about
+                // where it came from.
+                match (&function, &entry) {
+                    (Some(f), Some(e)) => {
+                        if parent_function.as_ref() != Some(f) {
+                            symbols.insert(
+                                sha256tree(allocator, program).hex(),
+                                (f.clone(), e.loc.clone())
+                            );
+                        }
+                    },
+                    _ => { }
+                }
+
+                let next_bit = bit.clone() * 2_i32.to_bigint().unwrap();
+                self.symbols_inner(allocator, symbols, function.clone(), a, path.clone(), next_bit.clone());
+                self.symbols_inner(allocator, symbols, function, b, path + bit, next_bit);
+            },
+            _ => { }
+        }
+    }
+
+    /* The code of each function in program, which is the outermost node
+     * attributed to that function, by tree hash, with the function's name
+     * and the location of its code.
+     */
+    pub fn functions(
+        &self,
+        allocator: &mut Allocator,
+        program: NodePtr
+    ) -> HashMap<String, (String, Srcloc)> {
+        let mut symbols = HashMap::new();
+        self.symbols_inner(
+            allocator,
+            &mut symbols,
+            None,
+            program,
+            0_i32.to_bigint().unwrap(),
+            1_i32.to_bigint().unwrap()
+        );
+        symbols
+    }
+
+    fn locations_inner(
+        &self,
+        allocator: &Allocator,
+        locations: &mut HashMap<String, String>,
+        program: NodePtr,
+        path: Number,
+        bit: Number
+    ) -> Bytes {
+        match allocator.sexp(program) {
+            allocator::SExp::Pair(a,b) => {
+                let here = path.clone() + bit.clone();
+                let next_bit = bit.clone() * 2_i32.to_bigint().unwrap();
+                let left = self.locations_inner(allocator, locations, a, path.clone(), next_bit.clone());
+                let right = self.locations_inner(allocator, locations, b, path + bit, next_bit);
+                let treehash = sha256(
+                    Bytes::new(Some(BytesFromType::Raw(vec!(2)))).
+                        concat(&left).concat(&right)
+                );
+                match self.entry(&here) {
+                    Some(e) => { locations.insert(treehash.hex(), e.loc.to_string()); },
+                    None => { }
+                }
+                treehash
+            },
+            allocator::SExp::Atom(buf) => {
+                sha256(
+                    Bytes::new(Some(BytesFromType::Raw(vec!(1)))).
+                        concat(&Bytes::new(Some(BytesFromType::Raw(allocator.buf(&buf).to_vec()))))
+                )
+            }
+        }
+    }
+
+    /* The location of each cons in program by tree hash, in the form of the
+     * symbol file written for a modern compile.
+     */
+    pub fn locations(
+        &self,
+        allocator: &Allocator,
+        program: NodePtr
+    ) -> HashMap<String, String> {
+        let mut locations = HashMap::new();
+        self.locations_inner(
+            allocator,
+            &mut locations,
+            program,
+            0_i32.to_bigint().unwrap(),
+            1_i32.to_bigint().unwrap()
+        );
+        locations
+    }
+
+    // A symbol table in the form brun reads, naming the functions of program.
+    pub fn function_symbols(
+        &self,
+        allocator: &mut Allocator,
+        program: NodePtr
+    ) -> HashMap<String, String> {
+        self.functions(allocator, program).into_iter().map(|(h, (name, _))| (h, name)).collect()
+    }
+}
+
+// The source map written for a hex file sits next to it.
+pub fn source_map_path(hex_path: &String) -> String {
+    std::path::Path::new(hex_path).with_extension("map.json").to_string_lossy().to_string()
+}
@@ -77,6 +77,44 @@ impl Srcloc {
     }
 }
 
+fn parse_position(s: &str) -> Option<(String, usize, usize)> {
+    let open = s.rfind('(')?;
+    let close = s[open..].find("):")? + open;
+    let line = s[open+1..close].parse::<usize>().ok()?;
+    let col = s[close+2..].parse::<usize>().ok()?;
+    Some((s[..open].to_string(), line, col))
+}
+
+// Read back the text produced by Srcloc::to_string, as found in symbol tables.
+pub fn parse_srcloc(s: &str) -> Option<Srcloc> {
+    let (file, line, col) = parse_position(s)?;
+    let start = Srcloc {
+        file: Rc::new(file.clone()),
+        line: line,
+        col: col,
+        until: None
+    };
+
+    // A range repeats the file name after the '-'.
+    for (i, _) in file.match_indices('-') {
+        match parse_position(&file[..i]) {
+            Some((start_file, start_line, start_col)) => {
+                if start_file == file[i+1..] {
+                    return Some(Srcloc {
+                        file: Rc::new(start_file),
+                        line: start_line,
+                        col: start_col,
+                        until: Some((line, col))
+                    });
+                }
+            },
+            _ => { }
+        }
+    }
+
+    Some(start)
+}
+
 pub fn src_location_min(a: &Srcloc) -> (usize, usize) {
     return (a.line, a.col);
 }
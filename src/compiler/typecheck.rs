@@ -0,0 +1,282 @@
+use std::borrow::Borrow;
+use std::collections::{
+    HashMap,
+    HashSet
+};
+use std::rc::Rc;
+
+use crate::compiler::comptypes::{
+    BodyForm,
+    CompileErr,
+    CompileForm,
+    FunctionType,
+    HelperForm,
+    TypeAnnotation
+};
+use crate::compiler::sexp::{
+    SExp,
+    decode_string
+};
+
+struct TypeContext {
+    macros: HashSet<Vec<u8>>,
+    functions: HashMap<Vec<u8>, Option<FunctionType>>
+}
+
+fn is_atom_type(t: &TypeAnnotation) -> bool {
+    match t {
+        TypeAnnotation::Atom | TypeAnnotation::Int |
+        TypeAnnotation::Bytes32 | TypeAnnotation::G1 => true,
+        _ => false
+    }
+}
+
+// Whether a value known to have type given could be what's wanted.  Only
+// types that can never hold the same value are a mismatch: an Atom might be
+// any kind of atom, or nil and so an empty List, and a List might be a Pair.
+// Every atom is also a valid Int.
+fn compatible(wanted: &TypeAnnotation, given: &TypeAnnotation) -> bool {
+    match (wanted, given) {
+        (TypeAnnotation::Any, _) | (_, TypeAnnotation::Any) => true,
+        (TypeAnnotation::Int, t) if is_atom_type(t) => true,
+        (TypeAnnotation::Atom, t) | (t, TypeAnnotation::Atom) => {
+            is_atom_type(t) || *t == TypeAnnotation::List
+        },
+        (TypeAnnotation::List, TypeAnnotation::Pair) |
+        (TypeAnnotation::Pair, TypeAnnotation::List) => true,
+        (w, g) => w == g
+    }
+}
+
+fn quoted_type(v: &SExp) -> TypeAnnotation {
+    match v {
+        SExp::Nil(_) => TypeAnnotation::Any,
+        SExp::Cons(_,_,_) => TypeAnnotation::Pair,
+        _ => TypeAnnotation::Atom
+    }
+}
+
+// What each argument of a primitive must be and what it gives back.
+fn prim_type(name: &Vec<u8>) -> Option<(TypeAnnotation, TypeAnnotation)> {
+    let t =
+        match decode_string(name).as_str() {
+            "+" | "-" | "*" | "/" | "ash" | "lsh" | "strlen" |
+            "logand" | "logior" | "logxor" | "lognot" => {
+                (TypeAnnotation::Atom, TypeAnnotation::Int)
+            },
+            "divmod" => (TypeAnnotation::Atom, TypeAnnotation::Pair),
+            "=" | ">" | ">s" | "concat" | "substr" => {
+                (TypeAnnotation::Atom, TypeAnnotation::Atom)
+            },
+            "sha256" => (TypeAnnotation::Atom, TypeAnnotation::Bytes32),
+            "point_add" => (TypeAnnotation::G1, TypeAnnotation::G1),
+            "pubkey_for_exp" => (TypeAnnotation::Atom, TypeAnnotation::G1),
+            "f" | "r" => (TypeAnnotation::Pair, TypeAnnotation::Any),
+            "c" => (TypeAnnotation::Any, TypeAnnotation::Pair),
+            "l" => (TypeAnnotation::Any, TypeAnnotation::Atom),
+            _ => { return None; }
+        };
+    Some(t)
+}
+
+fn arg_types(args: Rc<SExp>, signature: &Option<FunctionType>) -> HashMap<Vec<u8>, TypeAnnotation> {
+    let mut env = HashMap::new();
+    let declared =
+        match signature {
+            Some(s) => s.args.clone(),
+            None => { return env; }
+        };
+
+    let mut shape = args;
+    for (_,t) in declared.iter() {
+        let (here, rest) =
+            match shape.borrow() {
+                SExp::Cons(_,h,r) => (h.clone(), r.clone()),
+                _ => { break; }
+            };
+        match here.borrow() {
+            SExp::Atom(_,name) => { env.insert(name.clone(), t.clone()); },
+            _ => { }
+        }
+        shape = rest;
+    }
+
+    env
+}
+
+fn infer_args(
+    ctx: &TypeContext,
+    env: &HashMap<Vec<u8>, TypeAnnotation>,
+    args: &[Rc<BodyForm>]
+) -> Result<Vec<TypeAnnotation>, CompileErr> {
+    let mut types = Vec::new();
+    for a in args.iter() {
+        types.push(infer(ctx, env, a.clone())?);
+    }
+    Ok(types)
+}
+
+fn infer_call(
+    ctx: &TypeContext,
+    env: &HashMap<Vec<u8>, TypeAnnotation>,
+    name: &Vec<u8>,
+    args: &[Rc<BodyForm>]
+) -> Result<TypeAnnotation, CompileErr> {
+    let name_str = decode_string(name);
+
+    // The standard if and list macros evaluate their arguments in place, so
+    // they can be looked through.  Other macros can do anything with theirs.
+    if name_str == "if" && args.len() == 3 {
+        infer(ctx, env, args[0].clone())?;
+        let then_type = infer(ctx, env, args[1].clone())?;
+        let else_type = infer(ctx, env, args[2].clone())?;
+        return Ok(if then_type == else_type { then_type } else { TypeAnnotation::Any });
+    } else if name_str == "list" {
+        infer_args(ctx, env, args)?;
+        return Ok(TypeAnnotation::List);
+    } else if ctx.macros.contains(name) {
+        return Ok(TypeAnnotation::Any);
+    }
+
+    match ctx.functions.get(name) {
+        Some(None) => {
+            infer_args(ctx, env, args)?;
+            return Ok(TypeAnnotation::Any);
+        },
+        Some(Some(signature)) => {
+            let given = infer_args(ctx, env, args)?;
+            for (i, (arg_name, wanted)) in signature.args.iter().enumerate() {
+                if i >= given.len() {
+                    break;
+                }
+
+                if !compatible(wanted, &given[i]) {
+                    return Err(CompileErr(
+                        args[i].loc(),
+                        format!(
+                            "argument {} of {} is declared {} but given {}",
+                            decode_string(arg_name),
+                            name_str,
+                            wanted.to_string(),
+                            given[i].to_string()
+                        )
+                    ));
+                }
+            }
+
+            return Ok(signature.result.clone());
+        },
+        None => { }
+    }
+
+    match prim_type(name) {
+        Some((wanted, result)) => {
+            let given = infer_args(ctx, env, args)?;
+            for (i, g) in given.iter().enumerate() {
+                if !compatible(&wanted, g) {
+                    return Err(CompileErr(
+                        args[i].loc(),
+                        format!(
+                            "argument {} of {} should be {} but is given {}",
+                            i + 1,
+                            name_str,
+                            wanted.to_string(),
+                            g.to_string()
+                        )
+                    ));
+                }
+            }
+            Ok(result)
+        },
+        None => Ok(TypeAnnotation::Any)
+    }
+}
+
+fn infer(
+    ctx: &TypeContext,
+    env: &HashMap<Vec<u8>, TypeAnnotation>,
+    body: Rc<BodyForm>
+) -> Result<TypeAnnotation, CompileErr> {
+    match body.borrow() {
+        BodyForm::Let(_,bindings,body) => {
+            let mut let_env = env.clone();
+            for b in bindings.iter() {
+                let_env.insert(b.name.clone(), infer(ctx, env, b.body.clone())?);
+            }
+            infer(ctx, &let_env, body.clone())
+        },
+        BodyForm::Quoted(v) => Ok(quoted_type(v)),
+        BodyForm::Value(SExp::Atom(_,name)) => {
+            Ok(env.get(name).cloned().unwrap_or(TypeAnnotation::Any))
+        },
+        BodyForm::Value(SExp::Integer(_,_)) => Ok(TypeAnnotation::Atom),
+        BodyForm::Value(SExp::QuotedString(_,_,_)) => Ok(TypeAnnotation::Atom),
+        BodyForm::Value(_) => Ok(TypeAnnotation::Any),
+        BodyForm::Call(_,list) => {
+            if list.len() == 0 {
+                return Ok(TypeAnnotation::Any);
+            }
+
+            match list[0].borrow() {
+                BodyForm::Value(SExp::Atom(_,name)) => {
+                    infer_call(ctx, env, name, &list[1..])
+                },
+                _ => Ok(TypeAnnotation::Any)
+            }
+        }
+    }
+}
+
+// Check the calls and results of a program against the types its defuns are
+// annotated with.  Nothing here changes the program, and a program without
+// annotations isn't checked at all.  In one with them, unannotated arguments
+// are taken to have any type, but literals and primitives have their own.
+pub fn typecheck(form: &CompileForm) -> Result<(), CompileErr> {
+    if form.types.is_empty() {
+        return Ok(());
+    }
+
+    let mut ctx = TypeContext {
+        macros: HashSet::new(),
+        functions: HashMap::new()
+    };
+
+    for h in form.helpers.iter() {
+        match h {
+            HelperForm::Defmacro(_,name,_,_) => { ctx.macros.insert(name.clone()); },
+            HelperForm::Defun(_,name,_,_,_) => {
+                ctx.functions.insert(name.clone(), form.types.get(name).cloned());
+            },
+            _ => { }
+        }
+    }
+
+    for h in form.helpers.iter() {
+        match h {
+            HelperForm::Defun(_,name,_,args,body) => {
+                let signature = form.types.get(name).cloned();
+                let env = arg_types(args.clone(), &signature);
+                let result = infer(&ctx, &env, body.clone())?;
+                match signature {
+                    Some(s) => {
+                        if !compatible(&s.result, &result) {
+                            return Err(CompileErr(
+                                body.loc(),
+                                format!(
+                                    "{} is declared to return {} but returns {}",
+                                    decode_string(name),
+                                    s.result.to_string(),
+                                    result.to_string()
+                                )
+                            ));
+                        }
+                    },
+                    None => { }
+                }
+            },
+            _ => { }
+        }
+    }
+
+    infer(&ctx, &HashMap::new(), form.exp.clone()).map(|_| ())
+}
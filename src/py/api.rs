@@ -1,8 +1,90 @@
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use pyo3::create_exception;
 use pyo3::exceptions::PyException;
 use pyo3::prelude::*;
 use pyo3::wrap_pyfunction;
 
+use clvm_rs::allocator::{Allocator, NodePtr};
+use clvm_rs::reduction::EvalErr;
+
+use crate::classic::clvm::__type_compatibility__::{Bytes, BytesFromType, Stream};
+use crate::classic::clvm::serialize::{sexp_from_stream, sexp_to_stream, SimpleCreateCLVMObject};
+use crate::classic::clvm_tools::binutils::{
+    assemble as assemble_text, assemble_from_ir, disassemble as disassemble_node,
+};
 use crate::classic::clvm_tools::clvmc;
+use crate::classic::clvm_tools::clvmc::detect_modern;
+use crate::classic::clvm_tools::ir::reader::read_ir;
+use crate::classic::clvm_tools::sha256tree::sha256tree as sha256tree_node;
+use crate::classic::clvm_tools::stages::run;
+use crate::classic::clvm_tools::stages::stage_0::{
+    DefaultProgramRunner, RunProgramOption, TRunProgram,
+};
+use crate::classic::clvm_tools::stages::stage_2::operators::run_program_for_search_paths;
+
+use crate::compiler::cldb::{CldbRun, CldbRunEnv};
+use crate::compiler::clvm::{convert_to_clvm_rs, start_step};
+use crate::compiler::compiler::{
+    compile_file as compile_modern, run_optimizer, DefaultCompilerOpts,
+};
+use crate::compiler::comptypes::{CompileErr, CompilerOpts};
+use crate::compiler::prims;
+use crate::compiler::runtypes::RunFailure;
+use crate::compiler::sexp::{parse_sexp, SExp};
+use crate::compiler::srcloc::Srcloc;
+
+create_exception!(clvm_tools_rs, CompileError, PyException);
+create_exception!(clvm_tools_rs, EvalError, PyException);
+
+// Raise CompileError, with the source location available to python as the
+// file, line, col and until attributes.
+fn compile_err_to_py(err: CompileErr) -> PyErr {
+    let CompileErr(loc, msg) = err;
+    Python::with_gil(|py| {
+        let e = CompileError::new_err(format!("{}: {}", loc.to_string(), msg));
+        let instance = e.instance(py);
+        let _ = instance.setattr("message", msg);
+        let _ = instance.setattr("file", loc.file.to_string());
+        let _ = instance.setattr("line", loc.line);
+        let _ = instance.setattr("col", loc.col);
+        let _ = instance.setattr("until", loc.until);
+        e
+    })
+}
+
+fn run_failure_to_compile_err(r: RunFailure) -> CompileErr {
+    match r {
+        RunFailure::RunErr(l, x) => CompileErr(l, x),
+        RunFailure::RunExn(l, x) => CompileErr(l, x.to_string()),
+    }
+}
+
+fn eval_err_to_py(allocator: &mut Allocator, err: EvalErr) -> PyErr {
+    EvalError::new_err(format!("{} {}", err.1, disassemble_node(allocator, err.0)))
+}
+
+fn read_node(allocator: &mut Allocator, text: &String, hex: bool) -> PyResult<NodePtr> {
+    if hex {
+        let bytes = Bytes::new(Some(BytesFromType::Hex(text.to_string())));
+        sexp_from_stream(
+            allocator,
+            &mut Stream::new(Some(bytes)),
+            Box::new(SimpleCreateCLVMObject {}),
+        )
+        .map(|x| x.1)
+        .map_err(|e| EvalError::new_err(format!("bad hex input: {}", e.1)))
+    } else {
+        assemble_text(allocator, text).map_err(|e| EvalError::new_err(e.1))
+    }
+}
+
+fn node_to_hex(allocator: &mut Allocator, node: NodePtr) -> String {
+    let mut stream = Stream::new(None);
+    sexp_to_stream(allocator, node, &mut stream);
+    stream.get_value().hex()
+}
 
 // Thanks: https://www.reddit.com/r/rust/comments/bkkpkz/pkgversion_access_your_crates_version_number_as/
 #[pyfunction]
@@ -40,9 +122,171 @@ fn compile_clvm(
         .map_err(|s| PyException::new_err(s))
 }
 
+/* Compile chialisp source text, returning the compiled clvm as text.
+ * filename is used for include resolution and error locations.  Programs
+ * that include *standard-cl-21* use the modern compiler, others the classic
+ * one, as the run command does.
+ */
+#[pyfunction(filename = "None", search_paths = "Vec::new()", optimize = "false")]
+fn compile_file(
+    source: String,
+    filename: Option<String>,
+    search_paths: Vec<String>,
+    optimize: bool,
+) -> PyResult<String> {
+    let mut allocator = Allocator::new();
+    let use_filename = filename.unwrap_or_else(|| "*command*".to_string());
+    let start_loc = Srcloc::start(&use_filename);
+
+    let ir_src =
+        read_ir(&source).map_err(|e| compile_err_to_py(CompileErr(start_loc.clone(), e)))?;
+    let assembled_sexp = assemble_from_ir(&mut allocator, Rc::new(ir_src))
+        .map_err(|e| compile_err_to_py(CompileErr(start_loc.clone(), e.1)))?;
+
+    let compiled = if detect_modern(&mut allocator, assembled_sexp) {
+        let runner = Rc::new(DefaultProgramRunner::new());
+        let opts = Rc::new(DefaultCompilerOpts::new(&use_filename))
+            .set_optimize(optimize)
+            .set_search_paths(&search_paths);
+        let unopt_res = compile_modern(&mut allocator, runner.clone(), opts, &source);
+        let res = if optimize {
+            unopt_res.and_then(|x| run_optimizer(&mut allocator, runner, Rc::new(x)))
+        } else {
+            unopt_res.map(|x| Rc::new(x))
+        };
+
+        res.and_then(|x| convert_to_clvm_rs(&mut allocator, x).map_err(run_failure_to_compile_err))
+            .map_err(compile_err_to_py)?
+    } else {
+        let compile_invoke_code = run(&mut allocator);
+        let input_sexp = allocator
+            .new_pair(assembled_sexp, allocator.null())
+            .map_err(|e| eval_err_to_py(&mut allocator, e))?;
+        let run_program = run_program_for_search_paths(&search_paths);
+        run_program
+            .run_program(&mut allocator, compile_invoke_code, input_sexp, None)
+            .map(|x| x.1)
+            .map_err(|e| eval_err_to_py(&mut allocator, e))?
+    };
+
+    Ok(disassemble_node(&mut allocator, compiled))
+}
+
+/* Run a clvm program as brun does, returning (cost, result).
+ * program and args are clvm text, or serialized hex if hex is true.  A
+ * max_cost of None or 0 means no limit.
+ */
+#[pyfunction(args = "\"()\".to_string()", max_cost = "None", hex = "false")]
+fn run_program(
+    program: String,
+    args: String,
+    max_cost: Option<u64>,
+    hex: bool,
+) -> PyResult<(u64, String)> {
+    let mut allocator = Allocator::new();
+    let program_node = read_node(&mut allocator, &program, hex)?;
+    let args_node = read_node(&mut allocator, &args, hex)?;
+    let runner = DefaultProgramRunner::new();
+
+    runner
+        .run_program(
+            &mut allocator,
+            program_node,
+            args_node,
+            Some(RunProgramOption {
+                operator_lookup: None,
+                max_cost: max_cost.and_then(|c| if c == 0 { None } else { Some(c) }),
+                pre_eval_f: None,
+                strict: false,
+            }),
+        )
+        .map(|r| (r.0, disassemble_node(&mut allocator, r.1)))
+        .map_err(|e| eval_err_to_py(&mut allocator, e))
+}
+
+// Return the tree hash of a clvm value as hex.
+#[pyfunction(hex = "false")]
+fn sha256tree(program: String, hex: bool) -> PyResult<String> {
+    let mut allocator = Allocator::new();
+    let node = read_node(&mut allocator, &program, hex)?;
+    Ok(sha256tree_node(&mut allocator, node).hex())
+}
+
+// Assemble clvm text into serialized hex.
+#[pyfunction]
+fn assemble(program: String) -> PyResult<String> {
+    let mut allocator = Allocator::new();
+    let node = read_node(&mut allocator, &program, false)?;
+    Ok(node_to_hex(&mut allocator, node))
+}
+
+// Disassemble serialized hex into clvm text.
+#[pyfunction]
+fn disassemble(program: String) -> PyResult<String> {
+    let mut allocator = Allocator::new();
+    let node = read_node(&mut allocator, &program, true)?;
+    Ok(disassemble_node(&mut allocator, node))
+}
+
+/* Compile chialisp source and run it with args in the stepping runner,
+ * returning the rows cldb would print as a list of dicts.
+ */
+#[pyfunction(
+    args = "\"()\".to_string()",
+    filename = "None",
+    search_paths = "Vec::new()"
+)]
+fn cldb_trace(
+    source: String,
+    args: String,
+    filename: Option<String>,
+    search_paths: Vec<String>,
+) -> PyResult<Vec<BTreeMap<String, String>>> {
+    let mut allocator = Allocator::new();
+    let use_filename = filename.unwrap_or_else(|| "*command*".to_string());
+    let runner = Rc::new(DefaultProgramRunner::new());
+    let opts = Rc::new(DefaultCompilerOpts::new(&use_filename)).set_search_paths(&search_paths);
+
+    let program =
+        compile_modern(&mut allocator, runner.clone(), opts, &source).map_err(compile_err_to_py)?;
+
+    let args_srcloc = Srcloc::start(&"*args*".to_string());
+    let parsed_args = parse_sexp(args_srcloc.clone(), &args)
+        .map_err(|(l, e)| compile_err_to_py(CompileErr(l, e)))?;
+    let args_sexp = parsed_args
+        .first()
+        .map(|a| a.clone())
+        .unwrap_or_else(|| Rc::new(SExp::Nil(args_srcloc)));
+
+    let program_lines: Vec<String> = source.lines().map(|x| x.to_string()).collect();
+    let step = start_step(Rc::new(program), args_sexp);
+    let cldbenv = CldbRunEnv::new(Some(use_filename), program_lines);
+    let mut cldbrun = CldbRun::new(runner, prims::prim_map(), Box::new(cldbenv), step);
+
+    let mut output = Vec::new();
+    while !cldbrun.is_ended() {
+        match cldbrun.step(&mut allocator) {
+            Some(row) => {
+                output.push(row);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(output)
+}
+
 #[pymodule]
-fn clvm_tools_rs(_py: Python, m: &PyModule) -> PyResult<()> {
+fn clvm_tools_rs(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add("CompileError", py.get_type::<CompileError>())?;
+    m.add("EvalError", py.get_type::<EvalError>())?;
     m.add_function(wrap_pyfunction!(compile_clvm, m)?)?;
     m.add_function(wrap_pyfunction!(get_version, m)?)?;
+    m.add_function(wrap_pyfunction!(compile_file, m)?)?;
+    m.add_function(wrap_pyfunction!(run_program, m)?)?;
+    m.add_function(wrap_pyfunction!(sha256tree, m)?)?;
+    m.add_function(wrap_pyfunction!(assemble, m)?)?;
+    m.add_function(wrap_pyfunction!(disassemble, m)?)?;
+    m.add_function(wrap_pyfunction!(cldb_trace, m)?)?;
     Ok(())
 }
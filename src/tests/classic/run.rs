@@ -1,20 +1,40 @@
+use std::collections::HashMap;
+use std::fs;
 use std::path::PathBuf;
 
+use clvm_rs::allocator::Allocator;
+
 use crate::classic::clvm::__type_compatibility__::Stream;
-use crate::classic::clvm_tools::cmds::launch_tool;
+use crate::classic::clvm_tools::cmds::{
+    call_tool,
+    launch_tool,
+    OpcConversion
+};
+use crate::compiler::sourcemap::{
+    source_map_path,
+    SourceMap
+};
 
 fn do_basic_brun(args: &Vec<String>) -> String {
     let mut s = Stream::new(None);
-    launch_tool(&mut s, args, &"run".to_string(), 0);
+    launch_tool(&mut s, &mut Stream::new(None), args, &"run".to_string(), 0);
     return s.get_value().decode();
 }
 
 fn do_basic_run(args: &Vec<String>) -> String {
     let mut s = Stream::new(None);
-    launch_tool(&mut s, args, &"run".to_string(), 2);
+    launch_tool(&mut s, &mut Stream::new(None), args, &"run".to_string(), 2);
     return s.get_value().decode();
 }
 
+// The exit status, output and diagnostics of run.
+fn do_run_with_stderr(args: &Vec<String>) -> (i32, String, String) {
+    let mut s = Stream::new(None);
+    let mut e = Stream::new(None);
+    let code = launch_tool(&mut s, &mut e, args, &"run".to_string(), 2);
+    return (code, s.get_value().decode(), e.get_value().decode());
+}
+
 #[test]
 fn basic_run_test() {
     assert_eq!(
@@ -31,6 +51,24 @@ fn add_1_test() {
     );
 }
 
+#[test]
+fn brun_hex_env_test() {
+    assert_eq!(
+        do_basic_brun(&vec!(
+            "brun".to_string(),
+            "-x".to_string(),
+            "ff05ff0180\n".to_string(),
+            "ff0780\n".to_string()
+        )).trim(),
+        "7"
+    );
+    // With no env, a hex program runs with nil.
+    assert_eq!(
+        do_basic_brun(&vec!("brun".to_string(), "-x".to_string(), "01".to_string())).trim(),
+        "()"
+    );
+}
+
 #[test]
 fn brun_y_1_test() {
     let testpath = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -101,3 +139,317 @@ fn brun_v_test() {
             (q . 3) [()] => 3"}
     );
 }
+
+#[test]
+fn run_werror_test() {
+    let program = "(mod (X Y) (include *standard-cl-21*) (+ X 1))".to_string();
+    let warning = "*command*(1):9: warning: unused argument Y of mod\n".to_string();
+    assert_eq!(
+        do_run_with_stderr(&vec!("run".to_string(), program.clone())),
+        (0, "(2 (1 16 5 (1 . 1)) (4 (1) 1))\n".to_string(), warning.clone())
+    );
+    assert_eq!(
+        do_run_with_stderr(&vec!("run".to_string(), "--Werror".to_string(), program)),
+        (1, "".to_string(), warning)
+    );
+}
+
+#[test]
+fn run_opt_passes_test() {
+    let program = "(mod (X) (+ (f X) (* 2 3)))".to_string();
+    assert_eq!(
+        do_basic_run(&vec!("run".to_string(), program.clone())).trim(),
+        "(+ 4 (q . 6))".to_string()
+    );
+    assert_eq!(
+        do_basic_run(&vec!(
+            "run".to_string(),
+            "--opt-passes".to_string(),
+            "cons_optimizer,constant_optimizer,cons_q_a_optimizer,var_change_optimizer_cons_eval,children_optimizer,quote_null_optimizer,apply_null_optimizer".to_string(),
+            program.clone()
+        )).trim(),
+        "(+ (f 2) (q . 6))".to_string()
+    );
+    assert_eq!(
+        do_basic_run(&vec!(
+            "run".to_string(),
+            "--opt-passes".to_string(),
+            "no_such_optimizer".to_string(),
+            program
+        )).trim(),
+        "FAIL: unknown optimizer pass no_such_optimizer".to_string()
+    );
+}
+
+#[test]
+fn run_opt_passes_reset_after_failure_test() {
+    let program = "(mod (X) (+ (f X) (* 2 3)))".to_string();
+    assert!(do_basic_run(&vec!(
+        "run".to_string(),
+        "--opt-passes".to_string(),
+        "constant_optimizer".to_string(),
+        "(mod (X) (+ X".to_string()
+    )).starts_with("FAIL"));
+    // A failed run leaves the optimizer as it found it.
+    assert_eq!(
+        do_basic_run(&vec!("run".to_string(), program)).trim(),
+        "(+ 4 (q . 6))".to_string()
+    );
+}
+
+
+#[test]
+fn run_source_map_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let hex_path = dir.path().join("fact.hex").to_str().unwrap().to_string();
+    let program = "(mod (N) (include *standard-cl-21*) (defun fact (N) (if (= N 1) 1 (* N (fact (- N 1))))) (fact N))".to_string();
+    do_basic_run(&vec!("run".to_string(), "-o".to_string(), hex_path.clone(), program));
+
+    let map_path = dir.path().join("fact.map.json").to_str().unwrap().to_string();
+    let map_text = fs::read_to_string(&map_path).unwrap();
+    let map = SourceMap::from_json(&map_text).unwrap();
+    assert_eq!(map.entries[0].offset, 0);
+    assert!(map.entries.iter().any(|e| {
+        e.function == Some("fact".to_string()) && e.loc.line == 1 && e.loc.col > 37
+    }));
+
+    let hex = fs::read_to_string(&hex_path).unwrap();
+    assert_eq!(
+        do_basic_brun(&vec!(
+            "brun".to_string(),
+            "-x".to_string(),
+            "--source-map".to_string(),
+            map_path,
+            hex.trim().to_string(),
+            "ff0380".to_string()
+        )).trim(),
+        indoc! {"6
+            
+            (\"fact\" 3) => 6
+            
+            (\"fact\" 2) => 2
+            
+            (\"fact\" 1) => 1"}
+    );
+}
+
+#[test]
+fn brun_backtrace_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let hex_path = dir.path().join("fact.hex").to_str().unwrap().to_string();
+    let program = "(mod (N) (include *standard-cl-21*) (defun fact (N) (if (= N 1) (x \"done\" N) (* N (fact (- N 1))))) (fact N))".to_string();
+    do_basic_run(&vec!("run".to_string(), "-o".to_string(), hex_path.clone(), program));
+
+    let map_path = dir.path().join("fact.map.json").to_str().unwrap().to_string();
+    let hex = fs::read_to_string(&hex_path).unwrap();
+    // fact 1 raises in its then branch, having been called from the else
+    // branch of fact 2.
+    assert_eq!(
+        do_basic_brun(&vec!(
+            "brun".to_string(),
+            "-x".to_string(),
+            "--backtrace".to_string(),
+            "--source-map".to_string(),
+            map_path,
+            hex.trim().to_string(),
+            "ff0280".to_string()
+        )).trim(),
+        indoc! {"FAIL: clvm raise (\"done\" 1)
+            backtrace:
+              #0 (\"fact\" 1) at *command*(1):66
+              #1 (\"fact\" 2) at *command*(1):79"}
+    );
+
+    // Profiling the failing run keeps the backtrace and still reports what
+    // the run cost up to the failure.
+    let map_path = dir.path().join("fact.map.json").to_str().unwrap().to_string();
+    let output = do_basic_brun(&vec!(
+        "brun".to_string(),
+        "-x".to_string(),
+        "--backtrace".to_string(),
+        "--profile".to_string(),
+        "--source-map".to_string(),
+        map_path,
+        hex.trim().to_string(),
+        "ff0280".to_string()
+    ));
+    assert!(output.starts_with("FAIL: clvm raise (\"done\" 1)\n"));
+    assert!(output.contains("  #0 (\"fact\" 1) at *command*(1):66\n"));
+    let total_line = output.lines().find(|l| l.starts_with("flat profile, total cost ")).unwrap();
+    let total: u64 = total_line["flat profile, total cost ".len()..].parse().unwrap();
+    assert!(total > 0);
+}
+
+#[test]
+fn brun_profile_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let hex_path = dir.path().join("fact.hex").to_str().unwrap().to_string();
+    let program = indoc! {"
+        (mod (N)
+          (include *standard-cl-21*)
+          (defun fact (N)
+            (if (= N 1)
+              1
+              (* N (fact (- N 1)))
+              )
+            )
+          (fact N)
+          )
+        "}.to_string();
+    do_basic_run(&vec!("run".to_string(), "-o".to_string(), hex_path.clone(), program));
+
+    let map_path = dir.path().join("fact.map.json").to_str().unwrap().to_string();
+    let stacks_path = dir.path().join("fact.stacks").to_str().unwrap().to_string();
+    let hex = fs::read_to_string(&hex_path).unwrap();
+    let output = do_basic_brun(&vec!(
+        "brun".to_string(),
+        "-x".to_string(),
+        "-c".to_string(),
+        "--profile".to_string(),
+        "--profile-stacks".to_string(),
+        stacks_path.clone(),
+        "--source-map".to_string(),
+        map_path,
+        hex.trim().to_string(),
+        "ff0380".to_string()
+    ));
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[1], "6");
+
+    let total_line = lines.iter().find(|l| l.starts_with("flat profile, total cost ")).unwrap();
+    let total: u64 = total_line["flat profile, total cost ".len()..].parse().unwrap();
+    let calls_of = |name: &str| {
+        let row = lines.iter().find(|l| l.ends_with(&format!("  {}", name))).unwrap();
+        row.split_whitespace().nth(3).unwrap().to_string()
+    };
+    assert_eq!(calls_of("fact"), "3");
+    assert_eq!(calls_of("main"), "1");
+    // The recursive call is on line 6, and costs something.
+    assert!(lines.iter().any(|l| l.ends_with("*command*(6)")));
+
+    // Every bit of cost lands on exactly one stack.
+    let stacks = fs::read_to_string(&stacks_path).unwrap();
+    let mut stack_names = Vec::new();
+    let mut stack_total = 0;
+    for l in stacks.lines() {
+        let (stack, cost) = l.rsplit_once(' ').unwrap();
+        stack_names.push(stack.to_string());
+        stack_total += cost.parse::<u64>().unwrap();
+    }
+    assert_eq!(stack_names, vec!["main", "main;fact", "main;fact;fact", "main;fact;fact;fact"]);
+    assert_eq!(stack_total, total);
+}
+
+#[test]
+fn brun_coverage_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let hex_path = dir.path().join("count.hex").to_str().unwrap().to_string();
+    let program = indoc! {"
+        (mod (N)
+          (include *standard-cl-21*)
+          (defun count-down (N)
+            (if (= N 1)
+              (q . done)
+              (count-down (- N 1))
+              )
+            )
+          (count-down N)
+          )
+        "}.to_string();
+    do_basic_run(&vec!("run".to_string(), "-o".to_string(), hex_path.clone(), program));
+
+    let map_path = dir.path().join("count.map.json").to_str().unwrap().to_string();
+    let lcov_path = dir.path().join("count.info").to_str().unwrap().to_string();
+    let hex = fs::read_to_string(&hex_path).unwrap();
+    let run_count = |env: &str| {
+        do_basic_brun(&vec!(
+            "brun".to_string(),
+            "-x".to_string(),
+            "--coverage".to_string(),
+            lcov_path.clone(),
+            "--coverage-summary".to_string(),
+            "--source-map".to_string(),
+            map_path.clone(),
+            hex.trim().to_string(),
+            env.to_string()
+        ))
+    };
+
+    let hits_by_line = |lcov: &String| {
+        let mut hits = HashMap::new();
+        for l in lcov.lines() {
+            match l.strip_prefix("DA:").and_then(|d| d.split_once(',')) {
+                Some((line, n)) => {
+                    hits.insert(line.parse::<usize>().unwrap(), n.parse::<u64>().unwrap());
+                },
+                None => { }
+            }
+        }
+        hits
+    };
+
+    // (count-down 1) never takes the recursive arm on line 6.
+    let output = run_count("ff0180");
+    assert!(output.contains("        3/4   75.0%         1/2   50.0%  *command*\n"));
+    let first = fs::read_to_string(&lcov_path).unwrap();
+    assert!(first.starts_with("TN:\nSF:*command*\nBRDA:4,10,0,"));
+    assert!(first.contains("BRDA:4,10,1,0\n"));
+    let first_hits = hits_by_line(&first);
+    let mut lines: Vec<usize> = first_hits.keys().cloned().collect();
+    lines.sort();
+    assert_eq!(lines, vec![4, 5, 6, 9]);
+    assert!(first_hits[&5] > 0);
+    assert_eq!(first_hits[&6], 0);
+
+    // (count-down 3) takes both, and its hits add to those of the first run.
+    let output = run_count("ff0380");
+    assert!(output.contains("        4/4  100.0%         2/2  100.0%  total\n"));
+    let second = fs::read_to_string(&lcov_path).unwrap();
+    let second_hits = hits_by_line(&second);
+    assert_eq!(second_hits[&5], first_hits[&5] * 2);
+    assert!(second_hits[&6] > 0);
+}
+
+#[test]
+fn brun_coverage_variable_arm_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let hex_path = dir.path().join("pick.hex").to_str().unwrap().to_string();
+    // (if L (f L) ACC) for (L ACC), whose arms are both lookups.
+    let program = indoc! {"
+        (a (i 2
+              (q . 4)
+              (q . 5))
+           1)
+        "}.to_string();
+    call_tool(
+        &mut Allocator::new(),
+        "opc".to_string(),
+        "".to_string(),
+        Box::new(OpcConversion {}),
+        &vec!("opc".to_string(), "-o".to_string(), hex_path.clone(), program)
+    );
+
+    let map_path = source_map_path(&hex_path);
+    let lcov_path = dir.path().join("pick.info").to_str().unwrap().to_string();
+    let hex = fs::read_to_string(&hex_path).unwrap();
+    let run_pick = |env: &str| {
+        do_basic_brun(&vec!(
+            "brun".to_string(),
+            "-x".to_string(),
+            "--coverage".to_string(),
+            lcov_path.clone(),
+            "--source-map".to_string(),
+            map_path.clone(),
+            hex.trim().to_string(),
+            env.to_string()
+        ))
+    };
+
+    assert!(run_pick("ffff0980ff0780").starts_with("9\n"));
+    let lcov = fs::read_to_string(&lcov_path).unwrap();
+    assert!(lcov.contains("BRDA:1,7,0,1\nBRDA:1,7,1,0\n"));
+
+    assert!(run_pick("ff80ff0780").starts_with("7\n"));
+    let lcov = fs::read_to_string(&lcov_path).unwrap();
+    assert!(lcov.contains("BRDA:1,7,0,1\nBRDA:1,7,1,1\n"));
+}
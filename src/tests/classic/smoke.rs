@@ -9,23 +9,30 @@ use clvm_rs::allocator::{
 };
 use clvm_rs::reduction::EvalErr;
 
+use num_bigint::ToBigInt;
+
 use crate::classic::clvm::__type_compatibility__::{
     Stream,
     t
 };
 use crate::classic::clvm_tools::cmds::{
+    curry_tool,
     launch_tool,
     OpcConversion,
     OpdConversion,
     TConversion
 };
 
+use crate::classic::clvm::sexp::equal_to;
 use crate::classic::clvm_tools::binutils::{
+    assemble,
     assemble_from_ir,
     disassemble
 };
+use crate::classic::clvm_tools::curry::{curry, uncurry};
 use crate::classic::clvm_tools::ir::reader::read_ir;
 use crate::classic::clvm_tools::NodePath::NodePath;
+use crate::classic::clvm_tools::sha256tree::sha256tree;
 use crate::classic::clvm_tools::stages;
 use crate::classic::clvm_tools::stages::stage_0::{
     DefaultProgramRunner,
@@ -116,6 +123,7 @@ fn quoted_negative() {
     let mut s = Stream::new(None);
     launch_tool(
         &mut s,
+        &mut Stream::new(None),
         &vec!(
             "run".to_string(),
             "-d".to_string(),
@@ -219,6 +227,24 @@ fn basic_opc() {
     assert_eq!(result.rest(), "80");
 }
 
+#[test]
+fn opc_source_map() {
+    let mut allocator = Allocator::new();
+    let program = "(a (q . \"hello\")\n   (c 2 3))".to_string();
+    let conv = OpcConversion {};
+    let result = conv.invoke(&mut allocator, &program).unwrap();
+    let map = conv.source_map(
+        &mut allocator, &Some("hello.clvm".to_string()), &program, *result.first()
+    ).unwrap();
+    // (c 2 3) is the first of the rest of the rest of the program, path 11,
+    // and follows ff 02 ff, then ff 01 and the quoted string, then ff.
+    let entry = map.entry(&11_i32.to_bigint().unwrap()).unwrap();
+    assert_eq!(*entry.loc.file, "hello.clvm");
+    assert_eq!((entry.loc.line, entry.loc.col), (2, 4));
+    assert_eq!(entry.offset, 12);
+    assert_eq!(result.rest()[entry.offset * 2..].starts_with("ff04ff02ff03"), true);
+}
+
 #[test]
 fn basic_opc_lil() {
     let mut allocator = Allocator::new();
@@ -349,6 +375,76 @@ fn map_6() {
     assert_eq!(result, Ok("(64 36 16 4)".to_string()));
 }
 
+#[test]
+fn curry_and_uncurry() {
+    let mut allocator = Allocator::new();
+    let program = assemble(&mut allocator, &"(+ 2 5)".to_string()).unwrap();
+    let args = assemble(&mut allocator, &"(1 (100 . 300))".to_string()).unwrap();
+    let curried = curry(&mut allocator, program, args).unwrap().1;
+    assert_eq!(
+        disassemble(&mut allocator, curried),
+        "(a (q 16 2 5) (c (q . 1) (c (q 100 . 300) 1)))"
+    );
+
+    let uncurried = uncurry(&mut allocator, curried).unwrap().unwrap();
+    assert!(equal_to(&mut allocator, *uncurried.first(), program));
+    assert!(equal_to(&mut allocator, *uncurried.rest(), args));
+}
+
+#[test]
+fn uncurry_not_curried() {
+    let mut allocator = Allocator::new();
+    let program = assemble(&mut allocator, &"(a (q 16 2 5) (c (q . 1) 2))".to_string()).unwrap();
+    assert!(uncurry(&mut allocator, program).unwrap().is_none());
+}
+
+#[test]
+fn curried_program_runs() {
+    let mut allocator = Allocator::new();
+    let program = assemble(&mut allocator, &"(+ 2 5)".to_string()).unwrap();
+    let args = assemble(&mut allocator, &"(7)".to_string()).unwrap();
+    let curried = curry(&mut allocator, program, args).unwrap().1;
+    let env = assemble(&mut allocator, &"(3)".to_string()).unwrap();
+    let runner = DefaultProgramRunner::new();
+    let res = runner.run_program(&mut allocator, curried, env, None).unwrap();
+    assert_eq!(disassemble(&mut allocator, res.1), "10");
+}
+
+fn do_curry_tool(args: Vec<&str>) -> String {
+    let mut s = Stream::new(None);
+    let mut argv = vec!("curry".to_string());
+    for a in args {
+        argv.push(a.to_string());
+    }
+    curry_tool(&mut s, &argv);
+    return s.get_value().decode().trim().to_string();
+}
+
+#[test]
+fn curry_tool_basic() {
+    assert_eq!(
+        do_curry_tool(vec!("-a", "1", "-a", "\"hello\"", "(+ 2 5)")),
+        "(a (q 16 2 5) (c (q . 1) (c (q . \"hello\") 1)))"
+    );
+}
+
+#[test]
+fn curry_tool_uncurry() {
+    assert_eq!(
+        do_curry_tool(vec!("-u", "(a (q 16 2 5) (c (q . 1) (c (q . \"hello\") 1)))")),
+        "(+ 2 5)\n(1 \"hello\")"
+    );
+}
+
+#[test]
+fn curry_tool_treehash_matches_opc() {
+    let curried = do_curry_tool(vec!("-a", "1", "(+ 2 5)"));
+    let mut allocator = Allocator::new();
+    let sexp = assemble(&mut allocator, &curried).unwrap();
+    let expected = sha256tree(&mut allocator, sexp).hex();
+    assert_eq!(do_curry_tool(vec!("-H", "-a", "1", "(+ 2 5)")), expected);
+}
+
 /* #[test]
 fn pool_member_innerpuz() {
     let mut testpath = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -429,6 +525,7 @@ fn pool_member_innerpuz() {
     let mut s = Stream::new(None);
     launch_tool(
         &mut s,
+        &mut Stream::new(None),
         &vec!(
             "run".to_string(),
             "-i".to_string(),
@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use clvm_rs::allocator::Allocator;
+
+use crate::classic::clvm::__type_compatibility__::Stream;
+use crate::classic::clvm_tools::stages::stage_0::DefaultProgramRunner;
+
+use crate::compiler::cldb::{
+    Breakpoint,
+    CldbDebugger,
+    CldbRun,
+    CldbRunEnv,
+    FunctionMap
+};
+use crate::compiler::cldb_repl::CldbRepl;
+use crate::compiler::clvm::start_step;
+use crate::compiler::compiler::{
+    compile_file,
+    DefaultCompilerOpts
+};
+use crate::compiler::prims;
+use crate::compiler::sexp::parse_sexp;
+use crate::compiler::srcloc::Srcloc;
+
+fn run_cldb_rows(program: &String, args: &String) -> Vec<BTreeMap<String, String>> {
+    let mut allocator = Allocator::new();
+    let runner = Rc::new(DefaultProgramRunner::new());
+    let filename = "*test*".to_string();
+    let opts = Rc::new(DefaultCompilerOpts::new(&filename));
+    let compiled = compile_file(&mut allocator, runner.clone(), opts, program).unwrap();
+    let parsed_args = parse_sexp(Srcloc::start(&"*args*".to_string()), args).unwrap();
+
+    let program_lines = program.lines().map(|x| x.to_string()).collect();
+    let step = start_step(Rc::new(compiled), parsed_args[0].clone());
+    let cldbenv = CldbRunEnv::new(Some(filename), program_lines);
+    let mut cldbrun = CldbRun::new(runner, prims::prim_map(), Box::new(cldbenv), step);
+
+    let mut output = Vec::new();
+    while !cldbrun.is_ended() {
+        match cldbrun.step(&mut allocator) {
+            Some(row) => { output.push(row); },
+            _ => { }
+        }
+    }
+
+    return output;
+}
+
+#[test]
+fn test_cldb_run_to_final() {
+    let rows = run_cldb_rows(
+        &"(mod (X) (+ X 1))".to_string(),
+        &"(3)".to_string()
+    );
+    let last = rows.last().unwrap();
+    assert_eq!(last.get("Final"), Some(&"4".to_string()));
+    for (i, row) in rows[..rows.len() - 1].iter().enumerate() {
+        assert_eq!(row.get("Row"), Some(&i.to_string()));
+    }
+}
+
+#[test]
+fn test_cldb_run_reports_operator() {
+    let rows = run_cldb_rows(
+        &"(mod (X) (* X 5))".to_string(),
+        &"(3)".to_string()
+    );
+    let mul_row = rows.iter().find(|r| r.get("Value") == Some(&"15".to_string())).unwrap();
+    assert_eq!(mul_row.get("Operator"), Some(&"18".to_string()));
+    assert_eq!(mul_row.get("Function"), Some(&"*".to_string()));
+}
+
+#[test]
+fn test_cldb_run_stops_on_failure() {
+    let rows = run_cldb_rows(
+        &"(mod (X) (x X))".to_string(),
+        &"(3)".to_string()
+    );
+    // x raises, which cldb reports as a throw.
+    let last = rows.last().unwrap();
+    assert!(last.get("Throw").is_some());
+    assert!(last.get("Throw-Location").is_some());
+    assert!(last.get("Final").is_none());
+}
+
+const REPL_PROGRAM: &'static str = indoc! {"
+    (mod (X)
+      (include *standard-cl-21*)
+      (defun double (Y)
+        (* Y 2)
+      )
+      (defun inc (Z)
+        (+ (double Z) 1)
+      )
+      (inc X)
+    )
+"};
+
+fn program_debugger(allocator: &mut Allocator, program: &String, args: &String) -> CldbDebugger {
+    let runner = Rc::new(DefaultProgramRunner::new());
+    let filename = "test.clsp".to_string();
+    let opts = Rc::new(DefaultCompilerOpts::new(&filename));
+    let compiled = compile_file(allocator, runner.clone(), opts.clone(), program).unwrap();
+    let parsed_args = parse_sexp(Srcloc::start(&"*args*".to_string()), args).unwrap();
+
+    let program_lines: Vec<String> = program.lines().map(|x| x.to_string()).collect();
+    let step = start_step(Rc::new(compiled), parsed_args[0].clone());
+    let cldbenv = CldbRunEnv::new(Some(filename.clone()), program_lines.clone());
+    let cldbrun = CldbRun::new(runner, prims::prim_map(), Box::new(cldbenv), step);
+    let functions = FunctionMap::from_source(opts, program).unwrap();
+    CldbDebugger::new(cldbrun, functions)
+}
+
+fn repl_debugger(allocator: &mut Allocator) -> CldbDebugger {
+    program_debugger(allocator, &REPL_PROGRAM.to_string(), &"(3)".to_string())
+}
+
+fn run_repl_commands(commands: Vec<&str>) -> String {
+    let mut allocator = Allocator::new();
+    let debugger = repl_debugger(&mut allocator);
+    let program_lines: Vec<String> = REPL_PROGRAM.lines().map(|x| x.to_string()).collect();
+    let mut repl = CldbRepl::new(debugger, Some("test.clsp".to_string()), program_lines);
+
+    let mut out = Stream::new(None);
+    for c in commands {
+        if !repl.command(&mut allocator, &c.to_string(), &mut out) {
+            break;
+        }
+    }
+
+    return String::from_utf8_lossy(out.get_value().data()).to_string();
+}
+
+#[test]
+fn test_cldb_repl_continue_to_end() {
+    let output = run_repl_commands(vec!("continue"));
+    assert!(output.contains("Final: 7"));
+    assert!(output.ends_with("the program has finished\n"));
+}
+
+#[test]
+fn test_cldb_repl_break_on_function() {
+    let output = run_repl_commands(vec!("break double", "c", "bt"));
+    assert!(output.contains("breakpoint 1, function double"));
+    assert!(output.contains("    4 |     (* Y 2)"));
+    assert!(output.contains("#0 double at test.clsp(4):6 args (3)\n#1 inc at test.clsp(7):6 args (3)"));
+}
+
+#[test]
+fn test_cldb_repl_break_on_line() {
+    let output = run_repl_commands(vec!("b 7", "c", "p env", "p stack"));
+    assert!(output.contains("breakpoint 1, line test.clsp:7"));
+    assert!(output.contains("Env-Args: (3)"));
+    assert!(output.contains("the stack is empty"));
+}
+
+#[test]
+fn test_cldb_repl_function_entered_once_per_call() {
+    let output = run_repl_commands(vec!("b double", "c", "c"));
+    assert_eq!(output.matches("breakpoint 1, function double").count(), 1);
+    assert!(output.contains("Final: 7"));
+}
+
+#[test]
+fn test_cldb_repl_next_repeats() {
+    let output = run_repl_commands(vec!("next", "", ""));
+    assert!(output.contains("Row: 0"));
+    assert!(output.contains("Row: 2"));
+}
+
+#[test]
+fn test_cldb_repl_bad_breakpoint() {
+    let output = run_repl_commands(vec!("b nothing", "delete 1", "info", "quit", "c"));
+    assert_eq!(
+        output,
+        "no function named nothing\nno breakpoint 1\nno breakpoints\n".to_string()
+    );
+}
+
+#[test]
+fn test_cldb_repl_break_on_operator() {
+    let output = run_repl_commands(vec!("b op *", "c", "c"));
+    assert!(output.contains("breakpoint 1 at operator 18"));
+    assert!(output.contains("breakpoint 1, operator 18"));
+    assert!(output.contains("apply 18 to (3 2)"));
+    assert!(output.contains("Final: 7"));
+}
+
+#[test]
+fn test_cldb_repl_watch() {
+    let output = run_repl_commands(vec!("b double", "watch (+ Y 1)", "c", "p (* Y Y)", "info"));
+    assert!(output.contains("watch 1: (+ Y 1)\n"));
+    assert!(output.contains("watch 1: (+ Y 1) = 4"));
+    assert!(output.contains("\n9\n"));
+    assert!(output.contains("breakpoint 1: function double\nwatch 1: (+ Y 1)\n"));
+}
+
+#[test]
+fn test_cldb_debugger_run_until_break() {
+    let mut allocator = Allocator::new();
+    let mut debugger = repl_debugger(&mut allocator);
+    let id = debugger.add_breakpoint(Breakpoint::FunctionEntry("inc".to_string()));
+    let (_, hit) = debugger.run_until_break(&mut allocator);
+    assert_eq!(hit, Some(id));
+
+    let watch = debugger.add_watch(&"Z".to_string()).unwrap();
+    let values = debugger.evaluate_watches(&mut allocator);
+    assert_eq!(values.len(), 1);
+    assert_eq!(values[0].0, watch);
+    assert_eq!(values[0].2.as_ref().unwrap().to_string(), "3");
+
+    assert!(debugger.remove_breakpoint(id));
+    let (rows, hit) = debugger.run_until_break(&mut allocator);
+    assert_eq!(hit, None);
+    assert!(debugger.is_ended());
+    assert_eq!(rows.last().and_then(|r| r.get("Final")), Some(&"7".to_string()));
+}
+
+#[test]
+fn test_cldb_debugger_watch_later_arguments() {
+    let mut allocator = Allocator::new();
+    let program = "(mod (X) (include *standard-cl-21*) (defun f (A B C) (- A (+ B C))) (f X 2 1))".to_string();
+    let mut debugger = program_debugger(&mut allocator, &program, &"(10)".to_string());
+    debugger.add_breakpoint(Breakpoint::FunctionEntry("f".to_string()));
+    debugger.run_until_break(&mut allocator);
+
+    for w in ["A", "B", "C", "(- A B)"].iter() {
+        debugger.add_watch(&w.to_string()).unwrap();
+    }
+    let values: Vec<String> = debugger.evaluate_watches(&mut allocator).iter().map(|v| {
+        v.2.as_ref().unwrap().to_string()
+    }).collect();
+    assert_eq!(values, vec!("10", "2", "1", "8"));
+}
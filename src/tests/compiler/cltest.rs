@@ -0,0 +1,179 @@
+use std::fs;
+use std::rc::Rc;
+
+use clvm_rs::allocator::Allocator;
+
+use crate::classic::clvm::__type_compatibility__::Stream;
+use crate::classic::clvm_tools::cmds::cltest_tool;
+use crate::classic::clvm_tools::stages::stage_0::DefaultProgramRunner;
+
+use crate::compiler::cltest::{
+    junit_report,
+    parse_tests,
+    run_tests,
+    tap_report,
+    TestSuite,
+    CASE_STEP_LIMIT
+};
+use crate::compiler::compiler::DefaultCompilerOpts;
+
+const FACT_MODULE: &'static str = indoc! {"
+    (mod (N)
+      (include *standard-cl-21*)
+      (defun fact (N)
+        (if (= N 1)
+          1
+          (* N (fact (- N 1)))
+          )
+        )
+      (if (> 1 N)
+        (x \"too small\" N)
+        (fact N)
+        )
+      )
+"};
+
+const FACT_TESTS: &'static str = indoc! {"
+    (deftest \"one\" (1) 1)
+    (deftest \"three\" (3) 6)
+    (deftest \"wrong\" (3) 7)
+    (deftest-raise \"too small\" (-2) (\"too small\" -2))
+    (deftest-raise \"not raised\" (2))
+    (deftest \"raised\" (-1) 1)
+"};
+
+fn run_fact_tests(module: &str) -> Vec<TestSuite> {
+    let mut allocator = Allocator::new();
+    let runner = Rc::new(DefaultProgramRunner::new());
+    let opts = Rc::new(DefaultCompilerOpts::new(&"fact.clsp".to_string()));
+    let cases = parse_tests(&"fact.test.clsp".to_string(), &FACT_TESTS.to_string()).unwrap();
+    let results = run_tests(&mut allocator, runner, opts, &module.to_string(), &cases, None);
+    vec![TestSuite { name: "fact.test.clsp".to_string(), results: results }]
+}
+
+#[test]
+fn test_cltest_tap_report() {
+    let suites = run_fact_tests(FACT_MODULE);
+    assert_eq!(suites[0].failures(), 3);
+
+    // A wrong value is reported at the test, and a raise where it was raised.
+    let report = tap_report(&suites);
+    assert!(report.starts_with(indoc! {"
+        TAP version 13
+        1..6
+        ok 1 - fact.test.clsp: one
+        ok 2 - fact.test.clsp: three
+        not ok 3 - fact.test.clsp: wrong
+          ---
+          message: \"expected 7 but got 6\"
+          at: \"fact.test.clsp(3):1-fact.test.clsp(3):9\"
+          ...
+        ok 4 - fact.test.clsp: too small
+        not ok 5 - fact.test.clsp: not raised
+          ---
+          message: \"expected a raise but got 2\"
+    "}));
+    assert!(report.contains(indoc! {"
+        not ok 6 - fact.test.clsp: raised
+          ---
+          message: \"expected 1 but raised (2147851984891061300332 -1)\"
+          at: \"fact.clsp(10):6\"
+          test: \"fact.test.clsp(6):1-fact.test.clsp(6):9\"
+          ...
+    "}));
+}
+
+#[test]
+fn test_cltest_junit_report() {
+    let report = junit_report(&run_fact_tests(FACT_MODULE));
+    assert!(report.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites tests=\"6\" failures=\"3\">\n"));
+    assert!(report.contains(
+        "    <testcase name=\"one\" classname=\"fact.test.clsp\" file=\"fact.test.clsp\" line=\"1\"/>\n"
+    ));
+    assert!(report.contains(
+        "      <failure message=\"expected 7 but got 6\">at fact.test.clsp(3):1-fact.test.clsp(3):9</failure>\n"
+    ));
+    assert!(report.ends_with("  </testsuite>\n</testsuites>\n"));
+}
+
+#[test]
+fn test_cltest_module_does_not_compile() {
+    let suites = run_fact_tests("(mod (N) (fact N)");
+    assert_eq!(suites[0].failures(), 6);
+    let (loc, message) = suites[0].results[0].failure.clone().unwrap();
+    assert_eq!(loc.file.to_string(), "fact.clsp");
+    assert!(message.starts_with("module didn't compile: "));
+}
+
+#[test]
+fn test_cltest_case_does_not_finish() {
+    let mut allocator = Allocator::new();
+    let runner = Rc::new(DefaultProgramRunner::new());
+    let opts = Rc::new(DefaultCompilerOpts::new(&"spin.clsp".to_string()));
+    let cases = parse_tests(
+        &"spin.test.clsp".to_string(),
+        &"(deftest \"spins\" (1) 1)".to_string()
+    ).unwrap();
+    let module = "(mod (N) (defun spin (N) (spin N)) (spin N))".to_string();
+    let results = run_tests(&mut allocator, runner, opts, &module, &cases, None);
+    let (loc, message) = results[0].failure.clone().unwrap();
+    assert_eq!(loc, cases[0].loc);
+    assert_eq!(message, format!("expected 1 but failed: didn't finish in {} steps", CASE_STEP_LIMIT));
+}
+
+#[test]
+fn test_cltest_tool_finds_tests() {
+    let dir = tempfile::tempdir().unwrap();
+    let sub = dir.path().join("puzzles");
+    fs::create_dir(&sub).unwrap();
+    let module = sub.join("fact.clsp").to_string_lossy().to_string();
+    let tests = sub.join("fact.test.clsp").to_string_lossy().to_string();
+    let lcov = dir.path().join("tests.info").to_string_lossy().to_string();
+    fs::write(&module, FACT_MODULE).unwrap();
+    fs::write(&tests, "(deftest \"one\" (1) 1)\n(deftest \"three\" (3) 6)\n").unwrap();
+
+    // Directories are searched, and a module stands for the tests beside it.
+    let dir_name = dir.path().to_string_lossy().to_string();
+    for path in vec![dir_name.clone(), module.clone()].iter() {
+        let mut s = Stream::new(None);
+        let code = cltest_tool(&mut s, &vec!["cltest".to_string(), path.clone()]);
+        assert_eq!(code, 0);
+        assert_eq!(
+            s.get_value().decode(),
+            format!("TAP version 13\n1..2\nok 1 - {}: one\nok 2 - {}: three\n", tests, tests)
+        );
+    }
+
+    let mut s = Stream::new(None);
+    let code = cltest_tool(&mut s, &vec![
+        "cltest".to_string(), "--junit".to_string(), "--coverage".to_string(), lcov.clone(), dir_name.clone()
+    ]);
+    assert_eq!(code, 0);
+    assert!(s.get_value().decode().contains("<testsuites tests=\"2\" failures=\"0\">"));
+    assert!(fs::read_to_string(&lcov).unwrap().contains(&format!("SF:{}\n", module)));
+
+    fs::write(&tests, "(deftest \"three\" (3) 7)\n").unwrap();
+    let mut s = Stream::new(None);
+    assert_eq!(cltest_tool(&mut s, &vec!["cltest".to_string(), dir_name.clone()]), 1);
+
+    fs::write(&tests, "(defthing \"three\" (3) 7)\n").unwrap();
+    let mut s = Stream::new(None);
+    assert_eq!(cltest_tool(&mut s, &vec!["cltest".to_string(), dir_name]), 2);
+    assert!(s.get_value().decode().contains("expected deftest or deftest-raise"));
+}
+
+#[test]
+fn test_cltest_tool_includes_beside_module() {
+    let dir = tempfile::tempdir().unwrap();
+    let module = dir.path().join("double.clsp").to_string_lossy().to_string();
+    let tests = dir.path().join("double.test.clsp").to_string_lossy().to_string();
+    fs::write(dir.path().join("double.clib"), "((defun double (X) (* X 2)))\n").unwrap();
+    fs::write(&module, "(mod (X) (include *standard-cl-21*) (include double.clib) (double X))\n").unwrap();
+    fs::write(&tests, "(deftest \"three\" (3) 6)\n").unwrap();
+
+    // No -i is given, so the library is found beside the module.
+    let mut s = Stream::new(None);
+    let code = cltest_tool(&mut s, &vec!["cltest".to_string(), module.clone()]);
+    assert_eq!(s.get_value().decode(), format!("TAP version 13\n1..1\nok 1 - {}: three\n", tests));
+    assert_eq!(code, 0);
+}
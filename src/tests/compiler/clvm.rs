@@ -13,7 +13,15 @@ use crate::compiler::sexp::{
 };
 use crate::compiler::srcloc::Srcloc;
 use crate::compiler::runtypes::RunFailure;
-use crate::compiler::clvm::parse_and_run;
+use crate::compiler::clvm::{
+    parse_and_run,
+    run_with_coverage
+};
+use crate::compiler::coverage::{
+    Coverage,
+    CoverageHits
+};
+use crate::compiler::prims;
 
 fn test_compiler_clvm(
     to_run: &String,
@@ -141,3 +149,87 @@ fn test_clvm_4() {
 
     assert!(result.equal_to(want[0].borrow()));
 }
+
+#[test]
+fn test_run_with_coverage() {
+    let mut allocator = Allocator::new();
+    let runner = Rc::new(DefaultProgramRunner::new());
+    let loc = Srcloc::start(&"*test*".to_string());
+    let code = parse_sexp(loc.clone(), &indoc! {"
+        (2 (3 2
+             (1 .
+               (16 2 (1 . 1)))
+             (1 .
+               (1 . 7)))
+           1)
+    "}.to_string()).unwrap();
+    let args = parse_sexp(loc, &"(9)".to_string()).unwrap();
+
+    let mut hits = CoverageHits::new();
+    let res = run_with_coverage(
+        &mut allocator,
+        runner,
+        prims::prim_map(),
+        code[0].clone(),
+        args[0].clone(),
+        &mut hits
+    ).unwrap();
+    assert_eq!(res.to_string(), "10");
+
+    // Only the first arm ran, so the second's line and branch are uncovered.
+    let coverage = Coverage::from_hits(code[0].borrow(), &hits);
+    let lcov = coverage.to_lcov();
+    assert_eq!(lcov, indoc! {"
+        TN:
+        SF:*test*
+        BRDA:1,7,0,1
+        BRDA:1,7,1,0
+        BRF:2
+        BRH:1
+        DA:1,1
+        DA:2,1
+        DA:3,1
+        DA:4,1
+        DA:5,0
+        DA:6,1
+        LF:6
+        LH:5
+        end_of_record
+    "});
+
+    // Runs merge by adding up their hits, and LCOV reads back the same.
+    let mut merged = Coverage::from_lcov(&lcov).unwrap();
+    assert_eq!(merged, coverage);
+    merged.merge(&coverage);
+    assert!(merged.to_lcov().contains("BRDA:1,7,0,2\n"));
+    assert!(merged.to_lcov().contains("DA:5,0\nDA:6,2\n"));
+}
+
+#[test]
+fn test_run_with_coverage_variable_arm() {
+    let mut allocator = Allocator::new();
+    let runner = Rc::new(DefaultProgramRunner::new());
+    let loc = Srcloc::start(&"*test*".to_string());
+    // Both arms are lookups, so each is an atom with no evaluation inside it.
+    let code = parse_sexp(loc.clone(), &indoc! {"
+        (2 (3 2
+             (1 . 5)
+             (1 . 11))
+           1)
+    "}.to_string()).unwrap();
+    let args = parse_sexp(loc, &"(() 7 8)".to_string()).unwrap();
+
+    let mut hits = CoverageHits::new();
+    let res = run_with_coverage(
+        &mut allocator,
+        runner,
+        prims::prim_map(),
+        code[0].clone(),
+        args[0].clone(),
+        &mut hits
+    ).unwrap();
+    assert_eq!(res.to_string(), "8");
+
+    let lcov = Coverage::from_hits(code[0].borrow(), &hits).to_lcov();
+    assert!(lcov.contains("BRDA:1,7,0,0\nBRDA:1,7,1,1\n"));
+}
@@ -1,16 +1,26 @@
 use std::collections::HashMap;
+use std::fs;
 use std::rc::Rc;
 
 use clvm_rs::allocator::Allocator;
 
-use crate::classic::clvm_tools::stages::stage_0::DefaultProgramRunner;
-use crate::compiler::clvm::run;
+use crate::classic::clvm_tools::stages::stage_0::{
+    DefaultProgramRunner,
+    TRunProgram
+};
+use crate::compiler::clvm::{
+    convert_to_clvm_rs,
+    run
+};
 use crate::compiler::compiler::{
     DefaultCompilerOpts,
+    check_file,
     compile_file
 };
 use crate::compiler::comptypes::{
-    CompileErr
+    CompileErr,
+    CompilerOpts,
+    Severity
 };
 use crate::compiler::runtypes::RunFailure;
 use crate::compiler::sexp::{
@@ -33,10 +43,14 @@ fn compile_string(content: &String) -> Result<String, CompileErr> {
 }
 
 fn run_string(content: &String, args: &String) -> Result<Rc<SExp>, CompileErr> {
+    run_string_maybe_opt(content, args, false)
+}
+
+fn run_string_maybe_opt(content: &String, args: &String, optimize: bool) -> Result<Rc<SExp>, CompileErr> {
     let mut allocator = Allocator::new();
     let runner = Rc::new(DefaultProgramRunner::new());
     let srcloc = Srcloc::start(&"*test*".to_string());
-    let opts = Rc::new(DefaultCompilerOpts::new(&"*test*".to_string()));
+    let opts = Rc::new(DefaultCompilerOpts::new(&"*test*".to_string())).set_optimize(optimize);
     let sexp_args = parse_sexp(srcloc.clone(), &args).map_err(|e| {
         CompileErr(e.0, e.1)
     })?[0].clone();
@@ -254,3 +268,575 @@ fn inline_compile_test() {
         ).unwrap();
     assert_eq!(result, "(2 (1 16 5 (1 . 1)) (4 (1) 1))".to_string());
 }
+
+const SEVERAL_MISTAKES: &'static str = indoc! {"
+    (mod (X)
+      (defun first-bad (A) (let ((B)) B))
+      (defun fine (A) (+ A 1))
+      (defun second-bad (A) (f (let (C) C) (let (D) D)))
+      (fine (let ((E)) E))
+    )
+"};
+
+#[test]
+fn test_check_file_reports_every_mistake() {
+    let opts = Rc::new(DefaultCompilerOpts::new(&"*test*".to_string()));
+    let diagnostics = check_file(opts, &SEVERAL_MISTAKES.to_string());
+    let found: Vec<(usize, String)> = diagnostics.iter().map(|d| {
+        assert_eq!(d.severity, Severity::Error);
+        (d.loc.line, d.message.clone())
+    }).collect();
+    assert_eq!(found, vec!(
+        (2, "Bad binding tail ((B))".to_string()),
+        (4, "Bad binding tail (C)".to_string()),
+        (4, "Bad binding tail (D)".to_string()),
+        (5, "Bad binding tail ((E))".to_string())
+    ));
+
+    // Compiling still stops at the first.
+    let first = compile_string(&SEVERAL_MISTAKES.to_string()).unwrap_err();
+    assert_eq!(diagnostics[0].to_string(), format!("{}: {}", first.0.to_string(), first.1));
+}
+
+#[test]
+fn test_check_file_recovers_from_unbalanced_parens() {
+    let opts = Rc::new(DefaultCompilerOpts::new(&"*test*".to_string()));
+    let diagnostics = check_file(opts, &"(mod (X) (defun f (A) (let ((B)) B)) (f X)".to_string());
+    let messages: Vec<String> = diagnostics.iter().map(|d| d.message.clone()).collect();
+    assert_eq!(messages, vec!(
+        "Unterminated mid list".to_string(),
+        "Bad binding tail ((B))".to_string()
+    ));
+}
+
+#[test]
+fn test_check_file_clean() {
+    let opts = Rc::new(DefaultCompilerOpts::new(&"*test*".to_string()));
+    assert_eq!(check_file(opts, &"(mod (X) (+ X 1))".to_string()).len(), 0);
+}
+
+const WARNING_PROGRAM: &'static str = indoc! {"
+    (mod (X Y _Z)
+      (defconstant UNUSED_CONSTANT 1)
+      (defun never-called () 2)
+      (defun helper (A B) (let ((A (+ A 1))) A))
+      (helper X 3)
+    )
+"};
+
+#[test]
+fn test_check_file_warnings() {
+    let opts = Rc::new(DefaultCompilerOpts::new(&"*test*".to_string()));
+    let diagnostics = check_file(opts, &WARNING_PROGRAM.to_string());
+    let found: Vec<String> = diagnostics.iter().map(|d| {
+        assert_eq!(d.severity, Severity::Warning);
+        d.to_string()
+    }).collect();
+    assert_eq!(found, vec!(
+        "*test*(1):9: warning: unused argument Y of mod".to_string(),
+        "*test*(2):3-*test*(2):15: warning: unused defconstant UNUSED_CONSTANT".to_string(),
+        "*test*(3):3-*test*(3):9: warning: unused defun never-called".to_string(),
+        "*test*(4):20: warning: unused argument B of defun helper".to_string(),
+        "*test*(4):30: warning: let binding A shadows an argument".to_string()
+    ));
+
+    // Warnings don't stop compilation.
+    assert!(compile_string(&WARNING_PROGRAM.to_string()).is_ok());
+}
+
+fn compile_strict(content: &str) -> Result<String, CompileErr> {
+    let mut allocator = Allocator::new();
+    let runner = Rc::new(DefaultProgramRunner::new());
+    let opts = Rc::new(DefaultCompilerOpts::new(&"*test*".to_string())).set_strict(true);
+
+    compile_file(
+        &mut allocator,
+        runner,
+        opts,
+        &content.to_string()
+    ).map(|x| x.to_string())
+}
+
+#[test]
+fn test_strict_unbound_identifier() {
+    let program = indoc! {"
+        (mod (AMOUNT)
+          (defconstant FEE 10)
+          (defun deduct (VALUE) (- VALUE FEE))
+          (deduct AMOUNTT)
+        )
+    "};
+
+    // Otherwise the misspelling compiles as the atom AMOUNTT.
+    assert!(compile_string(&program.to_string()).is_ok());
+
+    let e = compile_strict(program).unwrap_err();
+    assert_eq!(e.0.line, 4);
+    assert_eq!(e.0.col, 11);
+    assert_eq!(e.1, "unbound identifier AMOUNTT (did you mean AMOUNT?)");
+}
+
+#[test]
+fn test_strict_unknown_callable_suggestion() {
+    let e = compile_strict("(mod (X) (defun double (V) (* V 2)) (c (double X) (doubel X)))").unwrap_err();
+    assert_eq!(e.1, "no such callable 'doubel' (did you mean double?)");
+
+    let e = compile_strict("(mod (X) (sah256 X))").unwrap_err();
+    assert_eq!(e.1, "no such callable 'sah256' (did you mean sha256?)");
+}
+
+#[test]
+fn test_strict_accepts_bound_names() {
+    let program = indoc! {"
+        (mod (X)
+          (defconstant ONE 1)
+          (defun add (A B) (+ A B))
+          (defmacro twice (V) (qq (+ (unquote V) (unquote V))))
+          (let ((Y (add X ONE))) (twice (if Y 64 \"text\")))
+        )
+    "};
+    assert_eq!(compile_strict(program).ok(), compile_string(&program.to_string()).ok());
+}
+
+fn compile_optimized(content: &str) -> String {
+    let mut allocator = Allocator::new();
+    let runner = Rc::new(DefaultProgramRunner::new());
+    let opts = Rc::new(DefaultCompilerOpts::new(&"*test*".to_string())).set_optimize(true);
+
+    compile_file(
+        &mut allocator,
+        runner,
+        opts,
+        &content.to_string()
+    ).unwrap().to_string()
+}
+
+fn assert_same_result_optimized(content: &str, args: &str, expected: &str) {
+    for optimize in [false, true].iter() {
+        let result = run_string_maybe_opt(&content.to_string(), &args.to_string(), *optimize).unwrap();
+        assert_eq!(result.to_string(), expected);
+    }
+}
+
+#[test]
+fn test_fold_constant_defun_call() {
+    let program = "(mod (X) (defun square (N) (* N N)) (+ X (square 5)))";
+    assert!(compile_optimized(program).contains("(1 . 25)"));
+    assert_same_result_optimized(program, "(3)", "28");
+}
+
+#[test]
+fn test_fold_recursive_defun_call() {
+    let program = indoc! {"
+        (mod (X)
+          (defun fact (N) (if N (* N (fact (- N 1))) 1))
+          (+ X (fact 5))
+        )
+    "};
+    assert!(compile_optimized(program).contains("(1 . 120)"));
+    assert_same_result_optimized(program, "(3)", "123");
+}
+
+#[test]
+fn test_fold_constant_inline_call() {
+    let program = "(mod (X) (defun-inline double (N) (* N 2)) (+ X (double 21)))";
+    assert!(compile_optimized(program).contains("(1 . 42)"));
+    assert_same_result_optimized(program, "(1)", "43");
+}
+
+#[test]
+fn test_no_fold_with_variable_args() {
+    let program = "(mod (X) (defun square (N) (* N N)) (square (+ X 1)))";
+    assert_same_result_optimized(program, "(4)", "25");
+}
+
+#[test]
+fn test_no_fold_inline_reading_caller_env() {
+    let program = "(mod (X) (defun-inline add-x (N) (+ N X)) (add-x 1))";
+    assert_same_result_optimized(program, "(4)", "5");
+}
+
+
+// The result and cost of running a program, with or without optimization.
+fn run_with_cost(content: &str, args: &str, optimize: bool) -> (String, u64) {
+    let mut allocator = Allocator::new();
+    let runner = Rc::new(DefaultProgramRunner::new());
+    let opts = Rc::new(DefaultCompilerOpts::new(&"*test*".to_string())).set_optimize(optimize);
+    let program = compile_file(&mut allocator, runner.clone(), opts, &content.to_string()).unwrap();
+    let sexp_args = parse_sexp(Srcloc::start(&"*args*".to_string()), &args.to_string()).unwrap()[0].clone();
+
+    let program_node = convert_to_clvm_rs(&mut allocator, Rc::new(program)).unwrap();
+    let args_node = convert_to_clvm_rs(&mut allocator, sexp_args).unwrap();
+    let result = runner.run_program(&mut allocator, program_node, args_node, None).unwrap();
+    let value = run_string_maybe_opt(&content.to_string(), &args.to_string(), optimize).unwrap();
+    (value.to_string(), result.0)
+}
+
+#[test]
+fn test_constant_if_drops_branch() {
+    let program = "(mod (X) (if 1 (+ X 1) (x)))";
+    assert_eq!(compile_optimized(program), "(2 (1 16 5 (1 . 1)) (4 (1) 1))");
+    assert_same_result_optimized(program, "(3)", "4");
+
+    let program = "(mod (X) (if (- 2 2) (x) (+ X 1)))";
+    assert_eq!(compile_optimized(program), "(2 (1 16 5 (1 . 1)) (4 (1) 1))");
+}
+
+#[test]
+fn test_constant_i_drops_branch() {
+    let program = "(mod (X) (i 1 (+ X 1) (q . 7)))";
+    assert_eq!(compile_optimized(program), "(2 (1 16 5 (1 . 1)) (4 (1) 1))");
+    assert_same_result_optimized(program, "(3)", "4");
+}
+
+#[test]
+fn test_constant_i_keeps_lookup() {
+    // i evaluates both arguments, and looking up X raises when the
+    // environment doesn't have it.
+    let program = "(mod (X) (i 1 7 X))";
+    assert!(compile_optimized(program).contains("3 (1 . 1) (1 . 7) 5"));
+    assert_same_result_optimized(program, "(3)", "7");
+}
+
+#[test]
+fn test_redefined_if_not_folded() {
+    let program = "(mod (X) (defmacro if (A B C) (qq (c (unquote B) (unquote C)))) (if 1 (+ X 1) 7))";
+    assert_same_result_optimized(program, "(3)", "(4 . 7)");
+}
+
+#[test]
+fn test_cse_shares_repeated_calls() {
+    let program = "(mod (X) (defun g (A) (sha256 A 1)) (c (g (g X)) (g (g X))))";
+    let plain = run_with_cost(program, "(3)", false);
+    let optimized = run_with_cost(program, "(3)", true);
+    assert_eq!(plain.0, optimized.0);
+    assert!(optimized.1 < plain.1);
+}
+
+#[test]
+fn test_cse_leaves_cheap_repeats() {
+    let program = "(mod (X) (+ (sha256 X 1) (sha256 X 1)))";
+    assert_eq!(run_with_cost(program, "(3)", true), run_with_cost(program, "(3)", false));
+}
+
+#[test]
+fn test_cse_ignores_lazy_branches() {
+    // Sharing (h X) would evaluate it when X is (), where it raises.
+    let program = indoc! {"
+        (mod (X)
+          (defun h (A) (f A))
+          (if X (c (h X) (h X)) 0)
+        )
+    "};
+    assert_same_result_optimized(program, "(())", "0");
+    assert_same_result_optimized(program, "((1))", "(1 . 1)");
+}
+
+#[test]
+fn test_cse_ignores_inline_arguments() {
+    // An inline function's arguments are only evaluated where its body uses
+    // them, so (h X) must not be shared when X is ().
+    let program = indoc! {"
+        (mod (X)
+          (defun h (A) (f A))
+          (defun-inline when-x (C V) (if C V 0))
+          (c (when-x X (h X)) (c (when-x X (f (h X))) (when-x X (r (h X)))))
+        )
+    "};
+    assert!(run_with_cost(program, "(())", true).1 <= run_with_cost(program, "(())", false).1);
+    assert_same_result_optimized(program, "(((1 2)))", "((1 2) 1 2)");
+}
+
+#[test]
+fn test_cse_leaves_bodies_using_env() {
+    // @ would be the environment of the new defun, which has the shared
+    // values after the arguments.
+    let program = "(mod (X) (defun g (A) (sha256 A 1)) (c (r @) (c (g (g X)) (g (g X)))))";
+    assert_eq!(run_with_cost(program, "(3)", true).0, run_with_cost(program, "(3)", false).0);
+}
+
+#[test]
+fn test_self_call_reuses_unchanged_args() {
+    let program = indoc! {"
+        (mod (LST MUL)
+          (defun scaled-sum (ACC LST MUL)
+            (if LST (scaled-sum (+ ACC (* MUL (f LST))) (r LST) MUL) ACC)
+          )
+          (scaled-sum 0 LST MUL)
+        )
+    "};
+    // MUL is passed through, so the recursive call takes it from the
+    // environment after the first two arguments.
+    assert!(compile_optimized(program).contains("(4 2 (4 "));
+    assert!(compile_optimized(program).contains(" 15)"));
+    assert_same_result_optimized(program, "((1 2 3) 5)", "30");
+
+    let optimized = run_with_cost(program, "((1 2 3 4 5 6 7 8) 5)", true);
+    let plain = run_with_cost(program, "((1 2 3 4 5 6 7 8) 5)", false);
+    assert_eq!(optimized.0, plain.0);
+    assert!(optimized.1 < plain.1);
+}
+
+#[test]
+fn test_self_call_rebuilds_changed_args() {
+    // Only a trailing run of unchanged arguments can be shared.
+    let program = indoc! {"
+        (mod (LST)
+          (defun walk (N LST ACC)
+            (if LST (walk N (r LST) (c (+ N (f LST)) ACC)) ACC)
+          )
+          (walk 10 LST ())
+        )
+    "};
+    assert_same_result_optimized(program, "((1 2 3))", "(13 12 11)");
+
+    let program = indoc! {"
+        (mod (LST)
+          (defun count-rest (N . REST)
+            (if (f REST) (count-rest (+ N 1) (r (f REST))) N)
+          )
+          (count-rest 0 LST)
+        )
+    "};
+    assert_same_result_optimized(program, "((1 2 3))", "3");
+}
+
+#[test]
+fn test_defun_arity_mismatch() {
+    let program = indoc! {"
+        (mod (X)
+          (defun add2 (A B) (+ A B))
+          (add2 X)
+        )
+    "};
+    let err = compile_string(&program.to_string()).unwrap_err();
+    assert_eq!(err.0.line, 3);
+    assert_eq!(err.1, "wrong number of arguments for function add2, want 2 given 1 (defined at *test*(2):3)");
+}
+
+#[test]
+fn test_inline_arity_mismatch() {
+    let program = indoc! {"
+        (mod (X)
+          (defun-inline first-of (A . REST) A)
+          (defun pick (L) (first-of))
+          (pick X)
+        )
+    "};
+    let err = compile_string(&program.to_string()).unwrap_err();
+    assert_eq!(err.0.line, 3);
+    assert_eq!(err.1, "wrong number of arguments for function first-of, want at least 1 given 0 (defined at *test*(2):3)");
+}
+
+#[test]
+fn test_arity_accepts_matching_calls() {
+    let program = indoc! {"
+        (mod (X)
+          (defun-inline double (A) (* A 2))
+          (defun count (L) (if L (+ 1 (count (r L))) 0))
+          (double (count X))
+        )
+    "};
+    assert_same_result_optimized(program, "((1 2 3))", "6");
+}
+
+#[test]
+fn test_type_annotations_erase() {
+    let annotated = indoc! {"
+        (mod (ITEMS AMOUNT)
+          (defun sum-items ((L : List) (ACC : Int)) : Int
+            (if L (sum-items (r L) (+ ACC (f L))) ACC)
+          )
+          (defun-inline fee ((A : Int)) : Int (* A 2))
+          (c (sha256 (sum-items ITEMS 0)) (fee AMOUNT))
+        )
+    "};
+    let plain = indoc! {"
+        (mod (ITEMS AMOUNT)
+          (defun sum-items (L ACC)
+            (if L (sum-items (r L) (+ ACC (f L))) ACC)
+          )
+          (defun-inline fee (A) (* A 2))
+          (c (sha256 (sum-items ITEMS 0)) (fee AMOUNT))
+        )
+    "};
+    assert_eq!(
+        compile_string(&annotated.to_string()).unwrap(),
+        compile_string(&plain.to_string()).unwrap()
+    );
+    assert_eq!(compile_optimized(annotated), compile_optimized(plain));
+
+    // Annotations of Any erase too.
+    let any_args = "(mod (X) (defun f ((A : Any) (B : Int)) (c A B)) (f X 1))";
+    let any_result = "(mod (X) (defun f (A B) : Any (c A B)) (f X 1))";
+    let any_only = "(mod (X) (defun f ((A : Any) B) (c A B)) (f X 1))";
+    let plain_any = "(mod (X) (defun f (A B) (c A B)) (f X 1))";
+    for program in [any_args, any_result, any_only].iter() {
+        assert_eq!(
+            compile_string(&program.to_string()).unwrap(),
+            compile_string(&plain_any.to_string()).unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_type_mismatch_in_argument() {
+    let program = indoc! {"
+        (mod (X)
+          (defun double ((N : Int)) : Int (* N 2))
+          (double (c X X))
+        )
+    "};
+    let err = compile_string(&program.to_string()).unwrap_err();
+    assert_eq!(err.0.line, 3);
+    assert_eq!(err.1, "argument N of double is declared Int but given Pair");
+
+    let program = indoc! {"
+        (mod (X)
+          (defun head ((P : Pair)) (f P))
+          (defun hash-of ((N : Int)) (head (sha256 N)))
+          (hash-of X)
+        )
+    "};
+    let err = compile_string(&program.to_string()).unwrap_err();
+    assert_eq!(err.1, "argument P of head is declared Pair but given Bytes32");
+}
+
+#[test]
+fn test_type_mismatch_in_result_and_prim() {
+    let program = "(mod (X) (defun wrap ((V : Int)) : Int (c V ())) (wrap X))";
+    let err = compile_string(&program.to_string()).unwrap_err();
+    assert_eq!(err.1, "wrap is declared to return Int but returns Pair");
+
+    let program = "(mod (X) (defun total ((P : Pair)) (+ P 1)) (total X))";
+    let err = compile_string(&program.to_string()).unwrap_err();
+    assert_eq!(err.1, "argument 1 of + should be Atom but is given Pair");
+
+    let program = "(mod (X) (defun g ((A : Int)) A) (if X (f 1) (g 2)))";
+    let err = compile_string(&program.to_string()).unwrap_err();
+    assert_eq!(err.1, "argument 1 of f should be Pair but is given Atom");
+
+    let program = "(mod (X) (defun total ((P : Strng)) P) (total X))";
+    let err = compile_string(&program.to_string()).unwrap_err();
+    assert_eq!(err.1, "unknown type Strng");
+}
+
+#[test]
+fn test_types_only_checked_when_annotated() {
+    // Without annotations nothing is checked, so code that would raise if it
+    // ran compiles as it did before.
+    let program = "(mod (X) (if X (f 1) 2))";
+    assert_eq!(run_string_maybe_opt(&program.to_string(), &"(())".to_string(), false).unwrap().to_string(), "2");
+
+    // Every atom is a valid int.
+    let program = "(mod (X) (defun inc ((N : Int)) : Int (+ N 1)) (inc (sha256 X)))";
+    assert!(compile_string(&program.to_string()).is_ok());
+}
+
+const UTILS_LIBRARY: &str = indoc! {"
+    (
+      (defconstant FACTOR 3)
+      (defun scale (X) (* X FACTOR))
+      (defun scale-all (L) (if L (c (scale (f L)) (scale-all (r L))) ()))
+    )
+"};
+
+fn run_with_library(program: &str, args: &str) -> Result<String, CompileErr> {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("utils.clib"), UTILS_LIBRARY).unwrap();
+
+    let mut allocator = Allocator::new();
+    let runner = Rc::new(DefaultProgramRunner::new());
+    let opts = Rc::new(DefaultCompilerOpts::new(&"*test*".to_string())).
+        set_search_paths(&vec!(dir.path().to_string_lossy().to_string()));
+    let sexp_args = parse_sexp(Srcloc::start(&"*args*".to_string()), &args.to_string()).unwrap()[0].clone();
+
+    let program = compile_file(&mut allocator, runner.clone(), opts, &program.to_string())?;
+    run(
+        &mut allocator,
+        runner,
+        Rc::new(HashMap::new()),
+        Rc::new(program),
+        sexp_args
+    ).map(|r| r.to_string()).map_err(|e| match e {
+        RunFailure::RunErr(l,s) => CompileErr(l, s),
+        RunFailure::RunExn(l,s) => CompileErr(l, s.to_string())
+    })
+}
+
+#[test]
+fn test_import_with_file_prefix() {
+    let program = "(mod (L) (import \"utils.clib\") (utils.scale-all L))";
+    assert_eq!(run_with_library(program, "((1 2))").unwrap(), "(3 6)");
+}
+
+#[test]
+fn test_import_with_chosen_prefix() {
+    let program = indoc! {"
+        (mod (X)
+          (import \"utils.clib\" as u)
+          (defun scale (X) (+ X 1))
+          (c (u.scale X) (scale X))
+        )
+    "};
+    assert_eq!(run_with_library(program, "(5)").unwrap(), "(15 . 6)");
+}
+
+#[test]
+fn test_import_export_list() {
+    let program = "(mod (X) (import \"utils.clib\" (scale)) (scale X))";
+    assert_eq!(run_with_library(program, "(5)").unwrap(), "15");
+
+    let program = "(mod (X) (import \"utils.clib\" (scale nope)) (scale X))";
+    let err = run_with_library(program, "(5)").unwrap_err();
+    assert_eq!(err.1, "nope is not defined in utils.clib");
+}
+
+#[test]
+fn test_import_duplicate_definition() {
+    let program = "(mod (X) (import \"utils.clib\" (scale)) (defun scale (X) X) (scale X))";
+    let err = run_with_library(program, "(5)").unwrap_err();
+    assert_eq!(err.1, "duplicate definition of scale (at utils.clib(3):3 and *test*(1):40)");
+}
+
+#[test]
+fn test_duplicate_helper_across_include() {
+    let program = "(mod (X) (include \"utils.clib\") (defun scale (X) X) (scale X))";
+    let err = run_with_library(program, "(5)").unwrap_err();
+    assert_eq!(err.0.line, 1);
+    assert_eq!(err.1, "duplicate definition of scale (at utils.clib(3):3 and *test*(1):33)");
+}
+
+#[test]
+fn test_duplicate_defun_and_constant() {
+    let err = compile_string(&"(mod (X) (defconstant K 1) (defun K () 2) X)".to_string()).unwrap_err();
+    assert_eq!(err.1, "duplicate definition of K (at *test*(1):10 and *test*(1):28)");
+}
+
+#[test]
+fn test_override_library_helper() {
+    let program = indoc! {"
+        (mod (L)
+          (include \"utils.clib\")
+          (override (defun scale (X) (+ X 100)))
+          (scale-all L)
+        )
+    "};
+    assert_eq!(run_with_library(program, "((1 2))").unwrap(), "(101 102)");
+
+    let program = indoc! {"
+        (mod (L)
+          (override (defun scale (X) (+ X 100)))
+          (include \"utils.clib\")
+          (scale-all L)
+        )
+    "};
+    assert_eq!(run_with_library(program, "((1 2))").unwrap(), "(101 102)");
+}
+
+#[test]
+fn test_override_needs_another_definition() {
+    let program = "(mod (X) (override (defun scale (X) X)) (scale X))";
+    let err = compile_string(&program.to_string()).unwrap_err();
+    assert_eq!(err.1, "override of scale doesn't replace any other definition");
+}
@@ -0,0 +1,202 @@
+use std::io::{Cursor, Write};
+
+use serde_json::{json, Value};
+use tempfile::NamedTempFile;
+
+use crate::compiler::dap::{encode_message, read_message, DapServer};
+
+const DAP_PROGRAM: &'static str = indoc! {"
+    (mod (X)
+      (include *standard-cl-21*)
+      (defun double (Y)
+        (* Y 2)
+      )
+      (defun inc (Z)
+        (+ (double Z) 1)
+      )
+      (inc X)
+    )
+"};
+
+fn program_file() -> NamedTempFile {
+    let mut file = tempfile::Builder::new().suffix(".clsp").tempfile().unwrap();
+    file.write_all(DAP_PROGRAM.as_bytes()).unwrap();
+    file
+}
+
+/* Send each request to a server through the wire encoding, as a client
+ * would, returning everything the server sent back.
+ */
+fn run_session(requests: Vec<Value>) -> Vec<Value> {
+    let mut wire = Vec::new();
+    for (i, r) in requests.iter().enumerate() {
+        let mut request = r.clone();
+        request["seq"] = json!(i + 1);
+        request["type"] = json!("request");
+        wire.append(&mut encode_message(&request));
+    }
+
+    let mut input = Cursor::new(wire);
+    let mut server = DapServer::new();
+    let mut replies = Vec::new();
+    while !server.is_finished() {
+        match read_message(&mut input).unwrap() {
+            Some(request) => { replies.append(&mut server.handle(&request)); },
+            None => { break; }
+        }
+    }
+
+    return replies;
+}
+
+fn launch_requests(file: &NamedTempFile, extra: Vec<Value>) -> Vec<Value> {
+    let mut requests = vec!(
+        json!({ "command": "initialize", "arguments": { "adapterID": "cldb" } }),
+        json!({
+            "command": "launch",
+            "arguments": { "program": file.path().to_string_lossy(), "args": "(3)" }
+        })
+    );
+    requests.append(&mut extra.clone());
+    requests
+}
+
+fn find_response<'a>(replies: &'a Vec<Value>, command: &str) -> &'a Value {
+    replies.iter().find(|r| r["type"] == "response" && r["command"] == command).unwrap()
+}
+
+fn events<'a>(replies: &'a Vec<Value>, event: &str) -> Vec<&'a Value> {
+    replies.iter().filter(|r| r["type"] == "event" && r["event"] == event).collect()
+}
+
+#[test]
+fn test_dap_message_round_trip() {
+    let msg = json!({ "seq": 1, "type": "request", "command": "threads" });
+    let mut wire = encode_message(&msg);
+    wire.append(&mut encode_message(&msg));
+    let mut input = Cursor::new(wire);
+    assert_eq!(read_message(&mut input).unwrap(), Some(msg.clone()));
+    assert_eq!(read_message(&mut input).unwrap(), Some(msg));
+    assert_eq!(read_message(&mut input).unwrap(), None);
+}
+
+#[test]
+fn test_dap_run_to_end() {
+    let file = program_file();
+    let replies = run_session(launch_requests(&file, vec!(
+        json!({ "command": "configurationDone" })
+    )));
+    assert_eq!(find_response(&replies, "launch")["success"], true);
+    assert_eq!(events(&replies, "initialized").len(), 1);
+    assert_eq!(events(&replies, "output")[0]["body"]["output"], "Final: 7\n");
+    assert_eq!(events(&replies, "exited")[0]["body"]["exitCode"], 0);
+    assert_eq!(events(&replies, "terminated").len(), 1);
+    // A client is told the program exited before the session terminates.
+    let position = |event: &str| replies.iter().position(|r| r["event"] == event).unwrap();
+    assert!(position("exited") < position("terminated"));
+}
+
+#[test]
+fn test_dap_line_breakpoint() {
+    let file = program_file();
+    let path = file.path().to_string_lossy().to_string();
+    let replies = run_session(launch_requests(&file, vec!(
+        json!({
+            "command": "setBreakpoints",
+            "arguments": { "source": { "path": path }, "breakpoints": [{ "line": 4 }] }
+        }),
+        json!({ "command": "configurationDone" }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+        json!({ "command": "evaluate", "arguments": { "expression": "(+ Y 10)", "frameId": 1 } }),
+        json!({ "command": "evaluate", "arguments": { "expression": "Z", "frameId": 2 } }),
+        json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+        json!({ "command": "disconnect" })
+    )));
+
+    let breakpoints = &find_response(&replies, "setBreakpoints")["body"]["breakpoints"];
+    assert_eq!(breakpoints[0]["verified"], true);
+
+    let stopped = events(&replies, "stopped");
+    assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+    assert_eq!(stopped[0]["body"]["hitBreakpointIds"], json!([breakpoints[0]["id"]]));
+
+    let frames = &find_response(&replies, "stackTrace")["body"]["stackFrames"];
+    assert_eq!(frames[0]["name"], "double");
+    assert_eq!(frames[0]["line"], 4);
+    assert_eq!(frames[1]["name"], "inc");
+
+    let variables = &find_response(&replies, "variables")["body"]["variables"];
+    assert_eq!(variables, &json!([{ "name": "Y", "value": "3", "variablesReference": 0 }]));
+
+    let evaluations: Vec<&Value> = replies.iter().filter(|r| r["command"] == "evaluate").collect();
+    assert_eq!(evaluations[0]["body"]["result"], "13");
+    assert_eq!(evaluations[1]["body"]["result"], "3");
+
+    assert_eq!(events(&replies, "output")[0]["body"]["output"], "Final: 7\n");
+    assert_eq!(find_response(&replies, "disconnect")["success"], true);
+}
+
+#[test]
+fn test_dap_function_breakpoint_and_step_out() {
+    let file = program_file();
+    let replies = run_session(launch_requests(&file, vec!(
+        json!({
+            "command": "setFunctionBreakpoints",
+            "arguments": { "breakpoints": [{ "name": "double" }, { "name": "nothing" }] }
+        }),
+        json!({ "command": "configurationDone" }),
+        json!({ "command": "stepOut", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } })
+    )));
+
+    let breakpoints = &find_response(&replies, "setFunctionBreakpoints")["body"]["breakpoints"];
+    assert_eq!(breakpoints[0]["verified"], true);
+    assert_eq!(breakpoints[1]["verified"], false);
+
+    let stopped = events(&replies, "stopped");
+    assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+    assert_eq!(stopped[1]["body"]["reason"], "step");
+
+    let frames = &find_response(&replies, "stackTrace")["body"]["stackFrames"];
+    assert_eq!(frames[0]["name"], "inc");
+}
+
+#[test]
+fn test_dap_stop_on_entry_and_step() {
+    let file = program_file();
+    let mut requests = launch_requests(&file, vec!(
+        json!({ "command": "configurationDone" }),
+        json!({ "command": "stepIn", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stepIn", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "next", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } })
+    ));
+    requests[1]["arguments"]["stopOnEntry"] = json!(true);
+    let replies = run_session(requests);
+
+    let stopped = events(&replies, "stopped");
+    assert_eq!(stopped[0]["body"]["reason"], "entry");
+    assert_eq!(stopped[1]["body"]["reason"], "step");
+    assert_eq!(stopped[2]["body"]["reason"], "step");
+    assert_eq!(stopped[3]["body"]["reason"], "step");
+
+    let traces: Vec<&Value> = replies.iter().filter(|r| r["command"] == "stackTrace").collect();
+    assert_eq!(traces[0]["body"]["stackFrames"][0]["name"], "double");
+    assert_eq!(traces[0]["body"]["stackFrames"][0]["line"], 4);
+    assert_eq!(traces[1]["body"]["stackFrames"][0]["name"], "inc");
+    assert_eq!(traces[1]["body"]["stackFrames"][0]["line"], 7);
+}
+
+#[test]
+fn test_dap_launch_errors() {
+    let replies = run_session(vec!(
+        json!({ "command": "threads" }),
+        json!({ "command": "launch", "arguments": { "program": "/nonexistent/program.clsp" } })
+    ));
+    assert_eq!(replies[0]["success"], false);
+    assert_eq!(replies[0]["message"], "no program has been launched");
+    assert_eq!(replies[1]["success"], false);
+    assert!(replies[1]["message"].as_str().unwrap().starts_with("can't read /nonexistent/program.clsp"));
+}
@@ -0,0 +1,125 @@
+use std::fs;
+
+use crate::classic::clvm::__type_compatibility__::Stream;
+use crate::classic::clvm_tools::cmds::fmt_tool;
+
+use crate::compiler::format::{format_source, DEFAULT_WIDTH};
+use crate::compiler::sexp::parse_sexp;
+use crate::compiler::srcloc::Srcloc;
+
+const MESSY_PROGRAM: &'static str = indoc! {"
+    ; computes a factorial
+    (mod (N)   (include *standard-cl-21*)
+    (defun factorial (N) ; recursive
+    (if (= N 0) 1 (* N (factorial (- N 1)))))
+
+
+    (defun-inline plus-one-hundred-thousand-and-then-some (VALUE) (+ VALUE 100000 2000 300 40 5))
+    (let ((F (factorial N)))
+    ; add a bit
+    (plus-one-hundred-thousand-and-then-some F))
+    )
+"};
+
+const FORMATTED_PROGRAM: &'static str = indoc! {"
+    ; computes a factorial
+    (mod (N)
+      (include *standard-cl-21*)
+      (defun factorial (N) ; recursive
+        (if (= N 0) 1 (* N (factorial (- N 1))))
+      )
+
+      (defun-inline plus-one-hundred-thousand-and-then-some (VALUE)
+        (+ VALUE 100000 2000 300 40 5)
+      )
+      (let ((F (factorial N)))
+        ; add a bit
+        (plus-one-hundred-thousand-and-then-some F))
+    )
+"};
+
+// indoc drops the final newline, which formatted files end with.
+fn formatted_program() -> String {
+    format!("{}\n", FORMATTED_PROGRAM)
+}
+
+fn format(text: &str) -> String {
+    format_source(&"test.clsp".to_string(), &text.to_string(), DEFAULT_WIDTH).unwrap()
+}
+
+fn parsed(text: &str) -> Vec<String> {
+    let loc = Srcloc::start(&"test.clsp".to_string());
+    parse_sexp(loc, &text.to_string()).unwrap().iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn test_format_layout() {
+    assert_eq!(format(MESSY_PROGRAM), formatted_program());
+}
+
+#[test]
+fn test_format_idempotent() {
+    let once = format(MESSY_PROGRAM);
+    assert_eq!(format(&once), once);
+}
+
+#[test]
+fn test_format_keeps_meaning() {
+    assert_eq!(parsed(&format(MESSY_PROGRAM)), parsed(MESSY_PROGRAM));
+}
+
+#[test]
+fn test_format_aligns_long_if() {
+    let program = "(if (some-long-condition-name ARGUMENT_ONE) (first-branch-function ARGUMENT_TWO) (second-branch ARGUMENT_THREE))";
+    assert_eq!(format(program), indoc! {"
+        (if (some-long-condition-name ARGUMENT_ONE)
+            (first-branch-function ARGUMENT_TWO)
+            (second-branch ARGUMENT_THREE))
+    "});
+}
+
+#[test]
+fn test_format_include_file() {
+    let program = "((defconstant ONE 1) ; one\n (defun-inline two () 2))";
+    assert_eq!(format(program), indoc! {"
+        (
+          (defconstant ONE 1) ; one
+          (defun-inline two () 2)
+        )
+    "});
+}
+
+#[test]
+fn test_format_keeps_token_spelling() {
+    let program = "(mod () (list 0x0102 \"a string\" #a 'q'))";
+    assert_eq!(format(program), format!("{}\n", program));
+}
+
+#[test]
+fn test_format_parse_error() {
+    let e = format_source(&"test.clsp".to_string(), &"(mod (X)".to_string(), DEFAULT_WIDTH);
+    assert!(e.is_err());
+}
+
+#[test]
+fn test_fmt_tool_check() {
+    let dir = tempfile::tempdir().unwrap();
+    let messy = dir.path().join("messy.clsp").to_string_lossy().to_string();
+    let tidy = dir.path().join("tidy.clsp").to_string_lossy().to_string();
+    fs::write(&messy, MESSY_PROGRAM).unwrap();
+    fs::write(&tidy, formatted_program()).unwrap();
+
+    let mut s = Stream::new(None);
+    let code = fmt_tool(&mut s, &vec!["chialisp-fmt".to_string(), "--check".to_string(), messy.clone(), tidy.clone()]);
+    assert_eq!(code, 1);
+    assert_eq!(s.get_value().decode().trim(), messy);
+
+    let mut s = Stream::new(None);
+    let code = fmt_tool(&mut s, &vec!["chialisp-fmt".to_string(), "--write".to_string(), messy.clone()]);
+    assert_eq!(code, 0);
+    assert_eq!(fs::read_to_string(&messy).unwrap(), formatted_program());
+
+    let mut s = Stream::new(None);
+    let code = fmt_tool(&mut s, &vec!["chialisp-fmt".to_string(), "--check".to_string(), messy, tidy]);
+    assert_eq!(code, 0);
+}
@@ -0,0 +1,186 @@
+use std::fs;
+
+use serde_json::{json, Value};
+use tempfile::TempDir;
+
+use crate::compiler::lsp::{path_to_uri, uri_to_path, LspServer};
+
+const LSP_PROGRAM: &'static str = indoc! {"
+    (mod (X)
+      (include *standard-cl-21*)
+      (include helpers.clib)
+      (defun double (Y)
+        (* Y 2)
+      )
+      (add-one (double X))
+    )
+"};
+
+const LSP_INCLUDE: &'static str = indoc! {"
+    (
+      (defun add-one (N) (+ N 1))
+    )
+"};
+
+struct Workspace {
+    dir: TempDir,
+    server: LspServer,
+    uri: String
+}
+
+fn open_workspace(program: &str) -> (Workspace, Vec<Value>) {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("helpers.clib"), LSP_INCLUDE).unwrap();
+    let program_path = dir.path().join("program.clsp");
+    fs::write(&program_path, program).unwrap();
+
+    let uri = path_to_uri(&program_path.to_string_lossy());
+    let mut server = LspServer::new();
+    server.handle(&json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "initialize",
+        "params": { "rootUri": path_to_uri(&dir.path().to_string_lossy()) }
+    }));
+    let published = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": { "uri": uri, "languageId": "chialisp", "version": 1, "text": program }
+        }
+    }));
+
+    (Workspace { dir: dir, server: server, uri: uri }, published)
+}
+
+fn position_request(ws: &mut Workspace, method: &str, line: usize, character: usize) -> Value {
+    let replies = ws.server.handle(&json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": method,
+        "params": {
+            "textDocument": { "uri": ws.uri },
+            "position": { "line": line, "character": character }
+        }
+    }));
+    replies[0]["result"].clone()
+}
+
+#[test]
+fn test_lsp_uri_round_trip() {
+    let path = "/tmp/my programs/a#b.clsp".to_string();
+    let uri = path_to_uri(&path);
+    assert_eq!(uri, "file:///tmp/my%20programs/a%23b.clsp");
+    assert_eq!(uri_to_path(&uri), path);
+
+    // An escape that isn't followed by two hex digits is left as it is.
+    assert_eq!(uri_to_path("file:///a%1\u{e9}"), "/a%1\u{e9}");
+}
+
+#[test]
+fn test_lsp_clean_program_has_no_diagnostics() {
+    let (ws, published) = open_workspace(LSP_PROGRAM);
+    assert_eq!(published[0]["method"], "textDocument/publishDiagnostics");
+    assert_eq!(published[0]["params"]["uri"], ws.uri.as_str());
+    assert_eq!(published[0]["params"]["diagnostics"], json!([]));
+}
+
+#[test]
+fn test_lsp_diagnostics_follow_changes() {
+    let (mut ws, _) = open_workspace(LSP_PROGRAM);
+    let broken = LSP_PROGRAM.replace("(add-one (double X))", "(add-one (double X)) X");
+    let published = ws.server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {
+            "textDocument": { "uri": ws.uri, "version": 2 },
+            "contentChanges": [{ "text": broken }]
+        }
+    }));
+    let diagnostics = &published[0]["params"]["diagnostics"];
+    assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(diagnostics[0]["message"], "only the last form can be an exprssion in mod");
+    assert_eq!(diagnostics[0]["range"]["start"], json!({ "line": 6, "character": 2 }));
+
+    let closed = ws.server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didClose",
+        "params": { "textDocument": { "uri": ws.uri } }
+    }));
+    assert_eq!(closed[0]["params"]["diagnostics"], json!([]));
+}
+
+#[test]
+fn test_lsp_parse_error_location() {
+    let (_, published) = open_workspace("(mod (X)\n  (+ X 1)\n");
+    let diagnostics = &published[0]["params"]["diagnostics"];
+    assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 0);
+}
+
+#[test]
+fn test_lsp_reports_several_errors() {
+    let program = LSP_PROGRAM.replace("(* Y 2)", "(let ((Z)) Z)").replace("(add-one (double X))", "(let (W) W)");
+    let (_, published) = open_workspace(&program);
+    let diagnostics = &published[0]["params"]["diagnostics"];
+    let lines: Vec<Value> = diagnostics.as_array().unwrap().iter().map(|d| d["range"]["start"]["line"].clone()).collect();
+    assert_eq!(lines, vec!(json!(4), json!(6)));
+}
+
+#[test]
+fn test_lsp_missing_include() {
+    let program = LSP_PROGRAM.replace("helpers.clib", "missing.clib");
+    let (_, published) = open_workspace(&program);
+    let diagnostics = &published[0]["params"]["diagnostics"];
+    assert_eq!(diagnostics[0]["message"], "could not find missing.clib to include");
+    assert_eq!(diagnostics[0]["range"], json!({
+        "start": { "line": 2, "character": 11 },
+        "end": { "line": 2, "character": 23 }
+    }));
+}
+
+#[test]
+fn test_lsp_definition_in_document() {
+    let (mut ws, _) = open_workspace(LSP_PROGRAM);
+    let location = position_request(&mut ws, "textDocument/definition", 6, 13);
+    assert_eq!(location["uri"], ws.uri.as_str());
+    assert_eq!(location["range"]["start"], json!({ "line": 3, "character": 2 }));
+}
+
+#[test]
+fn test_lsp_definition_in_include() {
+    let (mut ws, _) = open_workspace(LSP_PROGRAM);
+    let include_uri = path_to_uri(&ws.dir.path().join("helpers.clib").to_string_lossy());
+
+    let location = position_request(&mut ws, "textDocument/definition", 6, 4);
+    assert_eq!(location["uri"], include_uri.as_str());
+    assert_eq!(location["range"]["start"], json!({ "line": 1, "character": 2 }));
+
+    let target = position_request(&mut ws, "textDocument/definition", 2, 14);
+    assert_eq!(target["uri"], include_uri.as_str());
+
+    let nothing = position_request(&mut ws, "textDocument/definition", 4, 8);
+    assert_eq!(nothing, Value::Null);
+}
+
+#[test]
+fn test_lsp_hover_shows_arguments() {
+    let (mut ws, _) = open_workspace(LSP_PROGRAM);
+    let hover = position_request(&mut ws, "textDocument/hover", 6, 14);
+    assert_eq!(hover["contents"]["value"], "```chialisp\n(defun double (Y))\n```");
+
+    let hover = position_request(&mut ws, "textDocument/hover", 6, 3);
+    assert_eq!(hover["contents"]["value"], "```chialisp\n(defun add-one (N))\n```");
+}
+
+#[test]
+fn test_lsp_shutdown() {
+    let (mut ws, _) = open_workspace(LSP_PROGRAM);
+    let replies = ws.server.handle(&json!({ "jsonrpc": "2.0", "id": 9, "method": "shutdown" }));
+    assert_eq!(replies[0]["id"], 9);
+    assert_eq!(replies[0]["result"], Value::Null);
+    assert!(!ws.server.is_finished());
+    ws.server.handle(&json!({ "jsonrpc": "2.0", "method": "exit" }));
+    assert!(ws.server.is_finished());
+}
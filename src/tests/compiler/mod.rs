@@ -1,14 +1,23 @@
 use std::rc::Rc;
 use num_bigint::ToBigInt;
 
+use crate::compiler::gensym::{gensym, ungensym};
 use crate::compiler::sexp::{
+    CstNode,
     parse_sexp,
+    parse_sexp_lossless,
+    parse_sexp_recover,
     SExp
 };
-use crate::compiler::srcloc::Srcloc;
+use crate::compiler::srcloc::{parse_srcloc, Srcloc};
 
+mod cldb;
+mod cltest;
 mod clvm;
 mod compiler;
+mod dap;
+mod format;
+mod lsp;
 
 #[test]
 fn test_sexp_parse_print() {
@@ -45,3 +54,116 @@ fn test_sexp_parse_print() {
         "(hi . 3)".to_string()
     )
 }
+
+#[test]
+fn test_ungensym() {
+    assert_eq!(ungensym(&gensym(b"cse".to_vec())), b"cse".to_vec());
+    assert_eq!(ungensym(&b"a_$_b".to_vec()), b"a_$_b".to_vec());
+    // Names needn't be utf-8.
+    assert_eq!(ungensym(&b"\xff_$_12".to_vec()), b"\xff".to_vec());
+}
+
+#[test]
+fn test_parse_srcloc_round_trip() {
+    let mut loc = Srcloc::start(&"my-file.clsp".to_string());
+    loc.line = 4;
+    loc.col = 6;
+    assert_eq!(parse_srcloc(&loc.to_string()), Some(loc.clone()));
+
+    loc.until = Some((5, 2));
+    assert_eq!(parse_srcloc(&loc.to_string()), Some(loc));
+    assert_eq!(parse_srcloc("no location"), None);
+}
+
+const LOSSLESS_SOURCE: &'static str = "; header\r\n(mod (A . B) ; args\n\n\t(list 0x00ff #a \"q \\\" d\" 'x' -0)\n  ) ; end\n\n";
+
+fn parsed_strings(text: &str) -> Vec<String> {
+    let loc = Srcloc::start(&"test.clsp".to_string());
+    parse_sexp(loc, &text.to_string()).unwrap().iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn test_parse_sexp_lossless_round_trip() {
+    let loc = Srcloc::start(&"test.clsp".to_string());
+    let cst = parse_sexp_lossless(loc, &LOSSLESS_SOURCE.to_string()).unwrap();
+    assert_eq!(cst.print(), LOSSLESS_SOURCE);
+
+    let forms: Vec<String> = cst.to_sexp().iter().map(|s| s.to_string()).collect();
+    assert_eq!(forms, parsed_strings(LOSSLESS_SOURCE));
+}
+
+#[test]
+fn test_parse_sexp_lossless_trivia_and_spelling() {
+    let loc = Srcloc::start(&"test.clsp".to_string());
+    let cst = parse_sexp_lossless(loc, &LOSSLESS_SOURCE.to_string()).unwrap();
+    assert_eq!(cst.forms.len(), 1);
+    assert_eq!(cst.forms[0].trivia[0].text(), &b"; header".to_vec());
+
+    let elts = match &cst.forms[0].node {
+        CstNode::List(_,elts,closing) => {
+            assert_eq!(closing[0].newlines(), 1);
+            elts.clone()
+        },
+        _ => { panic!("expected a list"); }
+    };
+
+    // The comment after the args is trivia in front of the body, which
+    // follows a blank line.
+    assert_eq!(elts[2].trivia[1].text(), &b"; args".to_vec());
+    assert_eq!(elts[2].trivia[2].newlines(), 2);
+
+    let body = match &elts[2].node {
+        CstNode::List(_,body,_) => body.clone(),
+        _ => { panic!("expected a list"); }
+    };
+    let spellings: Vec<String> = body.iter().map(|e| {
+        let mut v = Vec::new();
+        e.node.print_mut(&mut v);
+        String::from_utf8_lossy(&v).to_string()
+    }).collect();
+    assert_eq!(spellings, vec!["list", "0x00ff", "#a", "\"q \\\" d\"", "'x'", "-0"]);
+}
+
+#[test]
+fn test_parse_sexp_lossless_matches_sample_files() {
+    for dir in ["resources/tests", "resources/tests/stage_2"].iter() {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.to_string_lossy().to_string();
+            if !(name.ends_with(".clvm") || name.ends_with(".clib") || name.ends_with(".clsp")) {
+                continue;
+            }
+
+            let text = std::fs::read_to_string(&path).unwrap();
+            let cst = parse_sexp_lossless(Srcloc::start(&name), &text).unwrap();
+            assert_eq!(cst.print(), text);
+            let forms: Vec<String> = cst.to_sexp().iter().map(|s| s.to_string()).collect();
+            assert_eq!(forms, parsed_strings(&text), "{}", name);
+        }
+    }
+}
+
+#[test]
+fn test_parse_sexp_lossless_errors() {
+    for text in ["(a", "(a . b . c)", "(. a)", "( . a)", "a)", "\"abc"].iter() {
+        let loc = Srcloc::start(&"test.clsp".to_string());
+        let lossless = parse_sexp_lossless(loc.clone(), &text.to_string()).map(|_| ()).map_err(|e| e.1);
+        let ordinary = parse_sexp(loc, &text.to_string()).map(|_| ()).map_err(|e| e.1);
+        assert_eq!(lossless, ordinary, "{}", text);
+    }
+}
+
+#[test]
+fn test_parse_sexp_recover() {
+    let loc = Srcloc::start(&"test.clsp".to_string());
+    let (forms, errors) = parse_sexp_recover(loc, &"(a b)) (c . . d) (e \"f".to_string());
+    let printed: Vec<String> = forms.iter().map(|f| f.to_string()).collect();
+    assert_eq!(printed, vec!("(a b)", "(c . d)", "(e \"f\")"));
+
+    let messages: Vec<String> = errors.iter().map(|e| e.1.clone()).collect();
+    assert_eq!(messages, vec!(
+        "Too many close parens",
+        "Multiple dots in list notation are illegal",
+        "Unterminated mid list"
+    ));
+}
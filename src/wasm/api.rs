@@ -1,25 +1,101 @@
-use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::sync::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use clvm_rs::allocator::Allocator;
 
 use wasm_bindgen::prelude::*;
 
+use crate::classic::clvm_tools::stages::stage_0::DefaultProgramRunner;
+use crate::compiler::cldb::{CldbRun, CldbRunEnv};
+use crate::compiler::clvm::start_step;
+use crate::compiler::compiler::{compile_file, DefaultCompilerOpts};
+use crate::compiler::comptypes::CompileErr;
+use crate::compiler::prims;
+use crate::compiler::sexp::{parse_sexp, SExp};
+use crate::compiler::srcloc::Srcloc;
+
 #[cfg(feature = "wee_alloc")]
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
 struct Runner {
-    
+    allocator: Allocator,
+    // Either a run in progress or the error that prevented it from starting.
+    run: Result<CldbRun, BTreeMap<String, String>>,
+    steps: usize,
+    reported_error: bool,
 }
 
 lazy_static! {
     static ref next_id: AtomicUsize = {
         return AtomicUsize::new(0);
     };
-    static ref runners: Mutex<RefCell<HashMap<i32, Runner>>> = {
-        return Mutex::new(RefCell::new(HashMap::new()));
-    };
+}
+
+// The runners hold Rc values, so they live in thread local storage rather
+// than behind a Mutex.  wasm is single threaded in any case.
+thread_local! {
+    static runners: RefCell<HashMap<i32, Runner>> = RefCell::new(HashMap::new());
+}
+
+fn get_next_id() -> i32 {
+    return next_id.fetch_add(1, Ordering::SeqCst) as i32;
+}
+
+fn error_row(loc: Option<Srcloc>, error: String) -> BTreeMap<String, String> {
+    let mut result = BTreeMap::new();
+    match loc {
+        Some(l) => {
+            result.insert("Error-Location".to_string(), l.to_string());
+        }
+        _ => {}
+    }
+    result.insert("Error".to_string(), error);
+    return result;
+}
+
+fn rows_to_json(rows: &Vec<BTreeMap<String, String>>) -> Vec<u8> {
+    return serde_json::to_string(rows)
+        .unwrap_or_else(|e| format!("[{{\"Error\":\"{}\"}}]", e))
+        .as_bytes()
+        .to_vec();
+}
+
+fn create_cldb_run(
+    allocator: &mut Allocator,
+    program: &[u8],
+    args: &[u8],
+) -> Result<CldbRun, BTreeMap<String, String>> {
+    let program_text = String::from_utf8(program.to_vec())
+        .map_err(|_| error_row(None, "program is not valid utf-8".to_string()))?;
+    let args_text = String::from_utf8(args.to_vec())
+        .map_err(|_| error_row(None, "arguments are not valid utf-8".to_string()))?;
+
+    let filename = "*program*".to_string();
+    let runner = Rc::new(DefaultProgramRunner::new());
+    let opts = Rc::new(DefaultCompilerOpts::new(&filename));
+    let program = compile_file(allocator, runner.clone(), opts, &program_text)
+        .map_err(|CompileErr(l, e)| error_row(Some(l), e))?;
+
+    let args_srcloc = Srcloc::start(&"*args*".to_string());
+    let parsed_args = parse_sexp(args_srcloc.clone(), &args_text)
+        .map_err(|(l, e)| error_row(Some(l), e))?;
+    let args = parsed_args
+        .first()
+        .map(|a| a.clone())
+        .unwrap_or_else(|| Rc::new(SExp::Nil(args_srcloc)));
+
+    let program_lines: Vec<String> = program_text.lines().map(|x| x.to_string()).collect();
+    let step = start_step(Rc::new(program), args);
+    let cldbenv = CldbRunEnv::new(Some(filename), program_lines);
+    return Ok(CldbRun::new(
+        runner,
+        prims::prim_map(),
+        Box::new(cldbenv),
+        step,
+    ));
 }
 
 /* Given a program in text (utf-8) form and arguments, compile the program and
@@ -27,16 +103,72 @@ lazy_static! {
  */
 #[wasm_bindgen]
 pub fn create_clvm_runner(program: &[u8], args: &[u8]) -> i32 {
-    
-    return 0;
+    let mut allocator = Allocator::new();
+    let run = create_cldb_run(&mut allocator, program, args);
+    let this_id = get_next_id();
+
+    runners.with(|r| {
+        r.borrow_mut().insert(
+            this_id,
+            Runner {
+                allocator: allocator,
+                run: run,
+                steps: 0,
+                reported_error: false,
+            },
+        );
+    });
+
+    return this_id;
 }
 
 #[wasm_bindgen]
 pub fn remove_clvm_runner(runner: i32) {
+    runners.with(|r| {
+        r.borrow_mut().remove(&runner);
+    });
 }
 
 // Run until the given step, returning the current machine state.
+//
+// The result is a utf-8 json array of the rows cldb would print that were
+// completed while advancing to the requested step.  The row containing
+// "Final", "Throw", "Failure" or "Error" is the last one a runner produces.
 #[wasm_bindgen]
 pub fn run_until_step(runner: i32, step: i32) -> Vec<u8> {
-    return Vec::new();
+    runners.with(|r| {
+        let mut runner_map = r.borrow_mut();
+        let this_runner = match runner_map.get_mut(&runner) {
+            Some(this_runner) => this_runner,
+            None => {
+                return rows_to_json(&vec![error_row(
+                    None,
+                    format!("no runner with id {}", runner),
+                )]);
+            }
+        };
+
+        let mut output = Vec::new();
+        match &mut this_runner.run {
+            Ok(cldbrun) => {
+                while !cldbrun.is_ended() && (this_runner.steps as i64) < (step as i64) {
+                    match cldbrun.step(&mut this_runner.allocator) {
+                        Some(row) => {
+                            output.push(row);
+                        }
+                        _ => {}
+                    }
+                    this_runner.steps += 1;
+                }
+            }
+            Err(e) => {
+                if !this_runner.reported_error {
+                    output.push(e.clone());
+                    this_runner.reported_error = true;
+                }
+            }
+        }
+
+        return rows_to_json(&output);
+    })
 }
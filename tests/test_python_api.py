@@ -0,0 +1,43 @@
+import pytest
+
+import clvm_tools_rs
+
+
+def test_compile_file():
+    assert clvm_tools_rs.compile_file("(mod (A B) (+ A B))") == "(+ 2 5)"
+
+
+def test_compile_file_error_location():
+    with pytest.raises(clvm_tools_rs.CompileError) as e:
+        clvm_tools_rs.compile_file(
+            "(mod (X)\n  (include *standard-cl-21*)\n  (let ((A)) A)\n  )",
+            filename="bad.clsp",
+        )
+    assert e.value.file == "bad.clsp"
+    assert e.value.line == 3
+
+
+def test_run_program():
+    program = clvm_tools_rs.compile_file("(mod (A B) (+ A B))")
+    cost, result = clvm_tools_rs.run_program(program, "(3 4)")
+    assert result == "7"
+    assert cost > 0
+
+
+def test_run_program_hex():
+    program = clvm_tools_rs.assemble("(+ 2 5)")
+    args = clvm_tools_rs.assemble("(3 4)")
+    assert clvm_tools_rs.run_program(program, args, hex=True)[1] == "7"
+
+
+def test_run_program_raises():
+    with pytest.raises(clvm_tools_rs.EvalError):
+        clvm_tools_rs.run_program("(x 2)", "(3)")
+
+
+def test_cldb_trace():
+    rows = clvm_tools_rs.cldb_trace(
+        "(mod (X) (include *standard-cl-21*) (* X 2))", "(3)"
+    )
+    assert rows[-1]["Final"] == "6"
+    assert all(isinstance(r, dict) for r in rows)